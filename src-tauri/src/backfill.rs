@@ -0,0 +1,170 @@
+use crate::db::{index_instances, init_db, session_gap, update_sessions};
+use crate::metadata::MetadataSource;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, Result};
+use serde_json::{json, Value};
+use tauri::AppHandle;
+
+pub mod output_log;
+pub mod vrcx_db;
+
+/// インスタンスに居たプレイヤー
+#[derive(Debug, Clone)]
+pub(crate) struct Presence {
+    pub user_id: Option<String>,
+    pub display_name: String,
+    pub joined_at: DateTime<Utc>,
+    pub left_at: Option<DateTime<Utc>>,
+}
+
+/// インスタンスへの1回の滞在
+#[derive(Debug, Clone)]
+pub(crate) struct Visit {
+    pub location: String, // wrld_xxx:12345~region(jp) 形式のインスタンスID
+    pub world_id: String,
+    pub world_name: String,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub players: Vec<Presence>,
+}
+
+impl Visit {
+    fn contains(&self, time: DateTime<Utc>) -> bool {
+        self.started_at <= time && self.ended_at.is_none_or(|end| time < end)
+    }
+
+    /// 指定時刻に同じインスタンスに居たプレイヤー
+    fn players_at(&self, time: DateTime<Utc>) -> Vec<&Presence> {
+        let mut present: Vec<&Presence> = Vec::new();
+        for presence in &self.players {
+            if presence.joined_at > time || presence.left_at.is_some_and(|left| left <= time) {
+                continue;
+            }
+            // 同じプレイヤーの重複を除く
+            if !present
+                .iter()
+                .any(|p| p.user_id == presence.user_id && p.display_name == presence.display_name)
+            {
+                present.push(presence);
+            }
+        }
+        present
+    }
+
+    /// 指定時刻に撮影された写真のメタデータをVRCXと同じ形式で組み立てる
+    fn to_metadata_json(&self, time: DateTime<Utc>) -> Value {
+        let players: Vec<Value> = self
+            .players_at(time)
+            .into_iter()
+            .map(|p| {
+                json!({
+                    "id": p.user_id.clone().unwrap_or_default(),
+                    "displayName": p.display_name,
+                })
+            })
+            .collect();
+        json!({
+            "application": "VRCXPhotoSearcher",
+            "version": 1,
+            "inferred": true,
+            "author": {
+                "id": "",
+                "displayName": "",
+            },
+            "world": {
+                "name": self.world_name,
+                "id": self.world_id,
+                "instanceId": self.location,
+            },
+            "players": players,
+        })
+    }
+}
+
+/// 時系列順に並んだ滞在履歴
+#[derive(Debug, Default)]
+pub(crate) struct Timeline {
+    visits: Vec<Visit>,
+}
+
+impl Timeline {
+    pub(crate) fn new(mut visits: Vec<Visit>) -> Self {
+        visits.sort_by_key(|v| v.started_at);
+        Timeline { visits }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.visits.is_empty()
+    }
+
+    /// 指定時刻に滞在していたインスタンス
+    pub(crate) fn visit_at(&self, time: DateTime<Utc>) -> Option<&Visit> {
+        let index = self.visits.partition_point(|v| v.started_at <= time);
+        self.visits[..index].last().filter(|v| v.contains(time))
+    }
+
+    pub(crate) fn visit_at_mut(&mut self, time: DateTime<Utc>) -> Option<&mut Visit> {
+        let index = self.visits.partition_point(|v| v.started_at <= time);
+        self.visits[..index].last_mut().filter(|v| v.contains(time))
+    }
+}
+
+/// 埋め込みメタデータを持たない画像に、滞在履歴から推定したメタデータを書き込む
+///
+/// 以前に推定したメタデータは上書きするが、画像に埋め込まれていたメタデータは変更しない。
+/// 更新した画像の件数を返す。
+pub(crate) fn apply_timeline(
+    conn: &Connection,
+    timeline: &Timeline,
+    source: MetadataSource,
+) -> Result<usize> {
+    let candidates: Vec<(i64, String)> = conn
+        .prepare(
+            "SELECT id, file_created_at FROM images
+             WHERE metadata_json IS NULL OR metadata_json = '' OR json_valid(metadata_json) = 0
+                OR metadata_source IN (SELECT value FROM json_each(?1))",
+        )?
+        .query_map([MetadataSource::inferred_json()], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?
+        .filter_map(Result::ok)
+        .collect();
+
+    let mut stmt = conn.prepare(
+        "UPDATE images SET metadata_json = ?1, metadata_source = ?2, updated_at = ?3 WHERE id = ?4",
+    )?;
+    let mut updated = 0;
+    for (id, file_created_at) in candidates {
+        // 撮影日時は登録時にメタデータ・ファイル名・ファイルの日時から決めたものを使う
+        let Ok(captured_at) = file_created_at.parse::<DateTime<Utc>>() else {
+            continue;
+        };
+        let Some(visit) = timeline.visit_at(captured_at) else {
+            continue;
+        };
+        stmt.execute(params![
+            visit.to_metadata_json(captured_at).to_string(),
+            source.as_str(),
+            Utc::now().to_rfc3339(),
+            id
+        ])?;
+        updated += 1;
+    }
+    Ok(updated)
+}
+
+/// インデックスに登録された全画像に滞在履歴を適用する
+pub(crate) fn apply_timeline_to_index(
+    app: &AppHandle,
+    timeline: &Timeline,
+    source: MetadataSource,
+) -> std::result::Result<usize, String> {
+    let mut conn = init_db(app).map_err(|e| e.to_string())?;
+    let transaction = conn.transaction().map_err(|e| e.to_string())?;
+    let updated = apply_timeline(&transaction, timeline, source).map_err(|e| e.to_string())?;
+    index_instances(&transaction).map_err(|e| e.to_string())?;
+    transaction.commit().map_err(|e| e.to_string())?;
+    // 推定したインスタンスで滞在を作り直す
+    update_sessions(&conn, session_gap(app)).map_err(|e| e.to_string())?;
+    Ok(updated)
+}
@@ -0,0 +1,337 @@
+use super::{apply_timeline_to_index, Presence, Timeline, Visit};
+use crate::metadata::MetadataSource;
+use crate::model::backfill::OutputLogImport;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tauri::AppHandle;
+use tokio::task;
+
+/// 1行分のログイベント
+#[derive(Debug, PartialEq)]
+enum LogEvent {
+    /// `[Behaviour] Joining wrld_xxx:12345~region(jp)`
+    Joining(String),
+    /// `[Behaviour] Entering Room: World Name`
+    EnteringRoom(String),
+    /// `[Behaviour] OnPlayerJoined Name (usr_xxx)`
+    PlayerJoined(String, Option<String>),
+    /// `[Behaviour] OnPlayerLeft Name (usr_xxx)`
+    PlayerLeft(String, Option<String>),
+    /// `[Behaviour] OnLeftRoom`
+    LeftRoom,
+}
+
+/// ログの行頭にある `2024.01.05 21:14:03` 形式のローカル時刻を読み取る
+fn parse_timestamp(line: &str) -> Option<DateTime<Utc>> {
+    let naive = NaiveDateTime::parse_from_str(line.get(..19)?, "%Y.%m.%d %H:%M:%S").ok()?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// `Name (usr_xxx)` を表示名とユーザーIDに分ける（古いログにはIDが無い）
+fn split_player(value: &str) -> (String, Option<String>) {
+    let value = value.trim();
+    if let Some(open) = value.rfind(" (usr_") {
+        if let Some(id) = value[open + 2..].strip_suffix(')') {
+            return (value[..open].to_string(), Some(id.to_string()));
+        }
+    }
+    (value.to_string(), None)
+}
+
+fn parse_event(line: &str) -> Option<LogEvent> {
+    let (_, message) = line.split_once("[Behaviour] ")?;
+    let message = message.trim_end();
+    if let Some(location) = message.strip_prefix("Joining wrld_") {
+        return Some(LogEvent::Joining(format!("wrld_{}", location.trim())));
+    }
+    if let Some(name) = message
+        .strip_prefix("Entering Room: ")
+        .or_else(|| message.strip_prefix("Joining or Creating Room: "))
+    {
+        return Some(LogEvent::EnteringRoom(name.trim().to_string()));
+    }
+    if let Some(player) = message.strip_prefix("OnPlayerJoined ") {
+        let (name, id) = split_player(player);
+        return Some(LogEvent::PlayerJoined(name, id));
+    }
+    if let Some(player) = message.strip_prefix("OnPlayerLeft ") {
+        // OnPlayerLeftRoom は自分の退室なので対象外
+        if player.starts_with("Room") {
+            return None;
+        }
+        let (name, id) = split_player(player);
+        return Some(LogEvent::PlayerLeft(name, id));
+    }
+    if message.starts_with("OnLeftRoom") {
+        return Some(LogEvent::LeftRoom);
+    }
+    None
+}
+
+/// 1つのログファイルから滞在履歴を読み取る
+fn parse_log(content: &str) -> Vec<Visit> {
+    let mut visits: Vec<Visit> = Vec::new();
+    let mut current: Option<Visit> = None;
+    let mut last_time: Option<DateTime<Utc>> = None;
+
+    for line in content.lines() {
+        let Some(time) = parse_timestamp(line) else {
+            continue;
+        };
+        last_time = Some(time);
+        let Some(event) = parse_event(line) else {
+            continue;
+        };
+        match event {
+            LogEvent::Joining(location) => {
+                if let Some(mut visit) = current.take() {
+                    visit.ended_at = Some(time);
+                    visits.push(visit);
+                }
+                let world_id = location.split(':').next().unwrap_or_default().to_string();
+                current = Some(Visit {
+                    location,
+                    world_id,
+                    world_name: String::new(),
+                    started_at: time,
+                    ended_at: None,
+                    players: Vec::new(),
+                });
+            }
+            LogEvent::EnteringRoom(name) => {
+                if let Some(visit) = current.as_mut() {
+                    visit.world_name = name;
+                }
+            }
+            LogEvent::PlayerJoined(display_name, user_id) => {
+                if let Some(visit) = current.as_mut() {
+                    visit.players.push(Presence {
+                        user_id,
+                        display_name,
+                        joined_at: time,
+                        left_at: None,
+                    });
+                }
+            }
+            LogEvent::PlayerLeft(display_name, user_id) => {
+                if let Some(presence) = current.as_mut().and_then(|visit| {
+                    visit.players.iter_mut().rev().find(|p| {
+                        p.left_at.is_none()
+                            && match (&p.user_id, &user_id) {
+                                (Some(a), Some(b)) => a == b,
+                                _ => p.display_name == display_name,
+                            }
+                    })
+                }) {
+                    presence.left_at = Some(time);
+                }
+            }
+            LogEvent::LeftRoom => {
+                if let Some(mut visit) = current.take() {
+                    visit.ended_at = Some(time);
+                    visits.push(visit);
+                }
+            }
+        }
+    }
+
+    // ゲーム終了などでログが途切れた場合は最後の行の時刻で滞在を閉じる
+    if let Some(mut visit) = current.take() {
+        visit.ended_at = last_time;
+        visits.push(visit);
+    }
+    visits
+}
+
+/// フォルダ内の `output_log_*.txt` をすべて読み取り、滞在履歴を組み立てる
+///
+/// 読み取れなかったログファイルは飛ばし、そのパスとエラーを滞在履歴と一緒に返す。
+pub(crate) fn load_timeline(log_dir: &Path) -> Result<(Timeline, Vec<String>), String> {
+    let mut log_files: Vec<PathBuf> = fs::read_dir(log_dir)
+        .map_err(|e| format!("ログフォルダを開けませんでした: {}", e))?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("output_log_") && name.ends_with(".txt"))
+        })
+        .collect();
+    log_files.sort();
+
+    let mut visits = Vec::new();
+    let mut failed_logs = Vec::new();
+    for path in log_files {
+        // ログにはUTF-8として不正なバイトが含まれることがある
+        match fs::read(&path) {
+            Ok(bytes) => visits.extend(parse_log(&String::from_utf8_lossy(&bytes))),
+            Err(e) => failed_logs.push(format!("{}: {}", path.display(), e)),
+        }
+    }
+    Ok((Timeline::new(visits), failed_logs))
+}
+
+/// VRChatの既定のログ出力先（%UserProfile%\AppData\LocalLow\VRChat\VRChat）
+#[cfg(windows)]
+fn default_log_dir(app: &AppHandle) -> Result<PathBuf, String> {
+    use tauri::Manager;
+    Ok(app
+        .path()
+        .home_dir()
+        .map_err(|e| e.to_string())?
+        .join("AppData")
+        .join("LocalLow")
+        .join("VRChat")
+        .join("VRChat"))
+}
+
+/// Windows以外ではログの場所が決まっていないため、フォルダの指定を求める
+#[cfg(not(windows))]
+fn default_log_dir(_app: &AppHandle) -> Result<PathBuf, String> {
+    Err("VRChatのログフォルダを指定してください".to_string())
+}
+
+/// VRChatのログから、メタデータの無い写真のワールド・同席者を推定して登録する
+#[tauri::command]
+pub async fn import_output_logs(
+    app: AppHandle,
+    log_dir: Option<String>,
+) -> Result<OutputLogImport, String> {
+    let log_dir = match log_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => default_log_dir(&app)?,
+    };
+    if !log_dir.is_dir() {
+        return Err(format!(
+            "ログフォルダが見つかりません: {}",
+            log_dir.display()
+        ));
+    }
+
+    task::spawn_blocking(move || {
+        let (timeline, failed_logs) = load_timeline(&log_dir)?;
+        let updated = if timeline.is_empty() {
+            0
+        } else {
+            apply_timeline_to_index(&app, &timeline, MetadataSource::OutputLog)?
+        };
+        Ok(OutputLogImport {
+            updated,
+            failed_logs,
+        })
+    })
+    .await
+    .map_err(|e| format!("インポートエラー: {:?}", e))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(text: &str) -> DateTime<Utc> {
+        parse_timestamp(text).unwrap()
+    }
+
+    #[test]
+    fn splits_player_name_and_id() {
+        assert_eq!(
+            split_player("Tea (Cake) (usr_1234)"),
+            ("Tea (Cake)".to_string(), Some("usr_1234".to_string()))
+        );
+        // 古いログにはユーザーIDが無い
+        assert_eq!(
+            split_player(" Tea (Cake) "),
+            ("Tea (Cake)".to_string(), None)
+        );
+    }
+
+    #[test]
+    fn parses_behaviour_events() {
+        let line =
+            |message: &str| format!("2024.01.05 21:14:03 Log        -  [Behaviour] {}", message);
+        assert_eq!(
+            parse_event(&line("Joining wrld_abc:12345~region(jp)")),
+            Some(LogEvent::Joining("wrld_abc:12345~region(jp)".to_string()))
+        );
+        assert_eq!(
+            parse_event(&line("Entering Room: The Black Cat")),
+            Some(LogEvent::EnteringRoom("The Black Cat".to_string()))
+        );
+        assert_eq!(
+            parse_event(&line("OnPlayerJoined Tea (usr_1)")),
+            Some(LogEvent::PlayerJoined(
+                "Tea".to_string(),
+                Some("usr_1".to_string())
+            ))
+        );
+        assert_eq!(
+            parse_event(&line("OnPlayerLeft Tea")),
+            Some(LogEvent::PlayerLeft("Tea".to_string(), None))
+        );
+        assert_eq!(parse_event(&line("OnPlayerLeftRoom")), None);
+        assert_eq!(parse_event(&line("OnLeftRoom")), Some(LogEvent::LeftRoom));
+        assert_eq!(
+            parse_event("2024.01.05 21:14:03 Log        -  Unrelated"),
+            None
+        );
+    }
+
+    #[test]
+    fn builds_visits_from_log() {
+        let log = "\
+2024.01.05 21:00:00 Log        -  [Behaviour] Joining wrld_a:1~region(jp)
+2024.01.05 21:00:01 Log        -  [Behaviour] Entering Room: World A
+2024.01.05 21:00:02 Log        -  [Behaviour] OnPlayerJoined Tea (usr_1)
+2024.01.05 21:00:03 Log        -  [Behaviour] OnPlayerJoined Cake (usr_2)
+not a log line
+2024.01.05 21:10:00 Log        -  [Behaviour] OnPlayerLeft Cake (usr_2)
+2024.01.05 21:20:00 Log        -  [Behaviour] OnLeftRoom
+2024.01.05 21:21:00 Log        -  [Behaviour] Joining wrld_b:2
+2024.01.05 21:30:00 Log        -  [Behaviour] OnPlayerJoined Old Name
+";
+        let visits = parse_log(log);
+        assert_eq!(visits.len(), 2);
+
+        let first = &visits[0];
+        assert_eq!(first.location, "wrld_a:1~region(jp)");
+        assert_eq!(first.world_id, "wrld_a");
+        assert_eq!(first.world_name, "World A");
+        assert_eq!(first.ended_at, Some(local("2024.01.05 21:20:00")));
+        assert_eq!(first.players.len(), 2);
+        assert_eq!(first.players[0].left_at, None);
+        assert_eq!(first.players[1].left_at, Some(local("2024.01.05 21:10:00")));
+
+        // 途切れたログは最後の行の時刻で閉じる
+        let second = &visits[1];
+        assert_eq!(second.world_id, "wrld_b");
+        assert_eq!(second.ended_at, Some(local("2024.01.05 21:30:00")));
+        assert_eq!(second.players[0].display_name, "Old Name");
+        assert_eq!(second.players[0].user_id, None);
+    }
+
+    #[test]
+    fn reports_unreadable_logs() {
+        let dir =
+            std::env::temp_dir().join(format!("vrcxphotosearcher-logs-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("output_log_2024-01-05_21-00-00.txt"),
+            "2024.01.05 21:00:00 Log        -  [Behaviour] Joining wrld_a:1\n",
+        )
+        .unwrap();
+        fs::write(dir.join("unrelated.txt"), "ignored").unwrap();
+        // ファイルとして読めないもの
+        fs::create_dir_all(dir.join("output_log_broken.txt")).unwrap();
+
+        let (timeline, failed_logs) = load_timeline(&dir).unwrap();
+        assert!(!timeline.is_empty());
+        assert_eq!(failed_logs.len(), 1);
+        assert!(failed_logs[0].contains("output_log_broken.txt"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+use super::{apply_timeline_to_index, Presence, Timeline, Visit};
+use crate::metadata::MetadataSource;
+use chrono::{DateTime, Utc};
+use rusqlite::{Connection, OpenFlags, Result};
+use std::path::PathBuf;
+use tauri::{AppHandle, Manager};
+use tokio::task;
+
+/// VRCXのデータベースを読み取り専用で開く
+fn open_vrcx_db(path: &PathBuf) -> Result<Connection> {
+    Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+}
+
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// VRCXのゲームログ（gamelog_location / gamelog_join_leave）から滞在履歴を組み立てる
+pub(crate) fn load_timeline(conn: &Connection) -> Result<Timeline> {
+    // インスタンスの移動履歴
+    let locations: Vec<(String, String, String, String, Option<i64>)> = conn
+        .prepare(
+            "SELECT created_at, location, world_id, world_name, time
+             FROM gamelog_location ORDER BY created_at",
+        )?
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                row.get(4)?,
+            ))
+        })?
+        .filter_map(Result::ok)
+        .collect();
+
+    let mut visits: Vec<Visit> = Vec::new();
+    for (created_at, location, world_id, world_name, time) in locations {
+        let Some(started_at) = parse_time(&created_at) else {
+            continue;
+        };
+        // 次のインスタンスへ移動した時点で前の滞在は終了
+        if let Some(previous) = visits.last_mut() {
+            if previous.ended_at.is_none_or(|end| end > started_at) {
+                previous.ended_at = Some(started_at);
+            }
+        }
+        // time には退出時に滞在時間（ミリ秒）が記録される
+        let ended_at = time
+            .filter(|ms| *ms > 0)
+            .map(|ms| started_at + chrono::Duration::milliseconds(ms));
+        visits.push(Visit {
+            location,
+            world_id,
+            world_name,
+            started_at,
+            ended_at,
+            players: Vec::new(),
+        });
+    }
+    let mut timeline = Timeline::new(visits);
+
+    // プレイヤーの入退室履歴
+    let events: Vec<(String, String, String, String, Option<String>)> = conn
+        .prepare(
+            "SELECT created_at, type, display_name, location, user_id
+             FROM gamelog_join_leave ORDER BY created_at",
+        )?
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                row.get::<_, Option<String>>(2)?.unwrap_or_default(),
+                row.get::<_, Option<String>>(3)?.unwrap_or_default(),
+                row.get(4)?,
+            ))
+        })?
+        .filter_map(Result::ok)
+        .collect();
+
+    for (created_at, kind, display_name, location, user_id) in events {
+        let Some(time) = parse_time(&created_at) else {
+            continue;
+        };
+        let Some(visit) = timeline.visit_at_mut(time) else {
+            continue;
+        };
+        if !location.is_empty() && location != visit.location {
+            continue;
+        }
+        let user_id = user_id.filter(|id| !id.is_empty());
+        match kind.as_str() {
+            "OnPlayerJoined" => visit.players.push(Presence {
+                user_id,
+                display_name,
+                joined_at: time,
+                left_at: None,
+            }),
+            "OnPlayerLeft" => {
+                if let Some(presence) = visit.players.iter_mut().rev().find(|p| {
+                    p.left_at.is_none()
+                        && match (&p.user_id, &user_id) {
+                            (Some(a), Some(b)) => a == b,
+                            _ => p.display_name == display_name,
+                        }
+                }) {
+                    presence.left_at = Some(time);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(timeline)
+}
+
+/// VRCXのデータベースから、メタデータの無い写真のワールド・同席者を推定して登録する
+#[tauri::command]
+pub async fn import_vrcx_database(
+    app: AppHandle,
+    db_path: Option<String>,
+) -> Result<usize, String> {
+    let path = match db_path {
+        Some(path) => PathBuf::from(path),
+        // VRCXの既定の保存先（%AppData%\VRCX\VRCX.sqlite3）
+        None => app
+            .path()
+            .config_dir()
+            .map_err(|e| e.to_string())?
+            .join("VRCX")
+            .join("VRCX.sqlite3"),
+    };
+    if !path.is_file() {
+        return Err(format!(
+            "VRCXのデータベースが見つかりません: {}",
+            path.display()
+        ));
+    }
+
+    task::spawn_blocking(move || {
+        let vrcx = open_vrcx_db(&path).map_err(|e| format!("VRCXデータベース接続エラー: {}", e))?;
+        let timeline =
+            load_timeline(&vrcx).map_err(|e| format!("VRCXデータベース読み取りエラー: {}", e))?;
+        if timeline.is_empty() {
+            return Ok(0);
+        }
+
+        apply_timeline_to_index(&app, &timeline, MetadataSource::VrcxDatabase)
+    })
+    .await
+    .map_err(|e| format!("インポートエラー: {:?}", e))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backfill::apply_timeline;
+    use crate::db::{migrate, SQL_QUERIES};
+
+    /// VRCXと同じテーブル構成のフィクスチャ
+    fn fixture_vrcx_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE gamelog_location (
+                 id INTEGER PRIMARY KEY, created_at TEXT, location TEXT, world_id TEXT,
+                 world_name TEXT, time INTEGER, group_name TEXT, UNIQUE(created_at, location));
+             CREATE TABLE gamelog_join_leave (
+                 id INTEGER PRIMARY KEY, created_at TEXT, type TEXT, display_name TEXT,
+                 location TEXT, user_id TEXT, time INTEGER, UNIQUE(created_at, type, display_name));
+             INSERT INTO gamelog_location (created_at, location, world_id, world_name, time) VALUES
+                 ('2023-05-01T10:00:00.000Z', 'wrld_a:100~region(jp)', 'wrld_a', 'World A', 0),
+                 ('2023-05-01T11:00:00.000Z', 'wrld_b:200~friends(usr_x)', 'wrld_b', 'World B', 1800000);
+             INSERT INTO gamelog_join_leave (created_at, type, display_name, location, user_id, time) VALUES
+                 ('2023-05-01T10:00:05.000Z', 'OnPlayerJoined', 'Alice', 'wrld_a:100~region(jp)', 'usr_alice', 0),
+                 ('2023-05-01T10:00:06.000Z', 'OnPlayerJoined', 'Bob', 'wrld_a:100~region(jp)', 'usr_bob', 0),
+                 ('2023-05-01T10:30:00.000Z', 'OnPlayerLeft', 'Bob', 'wrld_a:100~region(jp)', 'usr_bob', 0),
+                 ('2023-05-01T11:00:10.000Z', 'OnPlayerJoined', 'Carol', 'wrld_b:200~friends(usr_x)', '', 0);",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn fixture_index_db() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, SQL_QUERIES.migrations).unwrap();
+        conn.execute_batch(
+            "INSERT INTO search_folders (path, uuid) VALUES ('/photos', 'u');
+             INSERT INTO images (folder_id, file_path, metadata_json, metadata_source, file_created_at,
+                                 created_at, updated_at) VALUES
+                 (1, 'a1.png', '', NULL, '2023-05-01T10:15:00+00:00', '', ''),
+                 (1, 'a2.png', '', NULL, '2023-05-01T10:45:00+00:00', '', ''),
+                 (1, 'b1.png', '', NULL, '2023-05-01T11:10:00+00:00', '', ''),
+                 (1, 'after.png', '', NULL, '2023-05-01T12:00:00+00:00', '', ''),
+                 (1, 'vrcx.png', '{\"world\":{\"name\":\"Real\"}}', 'vrcx', '2023-05-01T10:15:00+00:00', '', '');",
+        )
+        .unwrap();
+        conn
+    }
+
+    fn metadata_of(conn: &Connection, file_path: &str) -> (String, Option<String>) {
+        conn.query_row(
+            "SELECT metadata_json, metadata_source FROM images WHERE file_path = ?",
+            [file_path],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn infers_world_and_players_from_game_log() {
+        let timeline = load_timeline(&fixture_vrcx_db()).unwrap();
+        let index = fixture_index_db();
+        let updated = apply_timeline(&index, &timeline, MetadataSource::VrcxDatabase).unwrap();
+        assert_eq!(updated, 3);
+
+        let (json, source) = metadata_of(&index, "a1.png");
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(source.as_deref(), Some("vrcx_db"));
+        assert_eq!(value["world"]["name"], "World A");
+        assert_eq!(value["world"]["instanceId"], "wrld_a:100~region(jp)");
+        assert_eq!(value["inferred"], true);
+        assert_eq!(value["players"].as_array().unwrap().len(), 2);
+
+        // Bobは退出済み
+        let (json, _) = metadata_of(&index, "a2.png");
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["players"][0]["displayName"], "Alice");
+        assert_eq!(value["players"].as_array().unwrap().len(), 1);
+
+        let (json, _) = metadata_of(&index, "b1.png");
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["world"]["id"], "wrld_b");
+        assert_eq!(value["players"][0]["id"], "");
+    }
+
+    #[test]
+    fn keeps_embedded_metadata_and_unknown_times() {
+        let timeline = load_timeline(&fixture_vrcx_db()).unwrap();
+        let index = fixture_index_db();
+        apply_timeline(&index, &timeline, MetadataSource::VrcxDatabase).unwrap();
+
+        // 滞在時間（30分）を過ぎた後の写真は推定しない
+        assert_eq!(metadata_of(&index, "after.png"), (String::new(), None));
+        let (json, source) = metadata_of(&index, "vrcx.png");
+        assert_eq!(source.as_deref(), Some("vrcx"));
+        assert!(json.contains("Real"));
+    }
+}
@@ -1,12 +1,15 @@
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tauri::{AppHandle, Manager};
 
 // カスタム設定の構造体
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Config {
     feature_flags: FeatureFlags,
+    /// 同じインスタンスでこれ以上撮影の間隔が空いたら別の滞在とみなす（分）
+    #[serde(default = "default_session_gap_minutes")]
+    pub session_gap_minutes: u32,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -15,13 +18,37 @@ pub struct FeatureFlags {
     pub language: String,
 }
 
-fn default_config() -> Config {
+impl Config {
+    /// 同じインスタンスで別の滞在とみなす撮影間隔
+    pub(crate) fn session_gap(&self) -> chrono::Duration {
+        chrono::Duration::minutes(self.session_gap_minutes as i64)
+    }
+}
+
+fn default_session_gap_minutes() -> u32 {
+    30
+}
+
+pub(crate) fn default_config() -> Config {
     Config {
         feature_flags: FeatureFlags {
             update_db_when_startup: false,
             language: "ja".to_string(),
         },
+        session_gap_minutes: default_session_gap_minutes(),
+    }
+}
+
+/// 設定ファイルを読み込む（無い場合は既定の設定を返し、保存はしない）
+///
+/// 壊れている場合はパニックせずにエラーを返す。
+pub(crate) fn read_config(config_path: &Path) -> Result<Config, String> {
+    let path = config_path.join("config.json");
+    if !path.exists() {
+        return Ok(default_config());
     }
+    let config_file = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    serde_json::from_str(&config_file).map_err(|e| e.to_string())
 }
 
 pub(crate) fn load_config(config_path: &PathBuf) -> Config {
@@ -66,3 +93,25 @@ pub fn set_config(app_handle: AppHandle, config: Config) {
         &config,
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_config_without_panicking() {
+        let dir =
+            std::env::temp_dir().join(format!("vrcxphotosearcher-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        // 設定ファイルが無い場合は既定の設定
+        let config = read_config(&dir).unwrap();
+        assert_eq!(config.session_gap_minutes, default_session_gap_minutes());
+        assert!(!dir.join("config.json").exists());
+
+        fs::write(dir.join("config.json"), "{ broken").unwrap();
+        assert!(read_config(&dir).is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,30 +1,68 @@
-use crate::model::search::SearchFolder;
-use base64::engine::general_purpose::STANDARD;
+use crate::image_hash::dhash;
+use crate::metadata::{
+    parse_instance_id, parse_legacy_metadata, parse_vrchat_file_name, CaptureTimeSource,
+    MetadataSource,
+};
+use crate::model::search::{PlayerName, SearchFolder, SearchPage, SearchPageRequest, SortKey};
+use crate::search::{compile, parse, Expr, QueryError};
+use crate::watcher::refresh_watcher;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use base64::Engine;
-use image::GenericImageView;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Result};
+use serde_json::{json, Number, Value};
 use std::collections::{HashMap, HashSet};
-// サムネイル生成用
-use chrono::{DateTime, Utc};
-use png::Decoder;
-use rusqlite::{params, params_from_iter, Connection, OpenFlags, OptionalExtension, Result};
-use serde_json::{Number, Value};
 use std::fs;
-use std::fs::{create_dir_all, File};
+use std::fs::create_dir_all;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
+use std::time::Duration;
 use tauri::{AppHandle, Emitter, Manager};
 use tokio::sync::Mutex;
 use tokio::task;
 use walkdir::WalkDir;
 
 // Queries構造体をインポート
+mod albums;
+mod annotations;
+mod cooccurrence;
+mod duplicates;
+mod facets;
+mod fulltext;
+mod ingest;
+mod migration;
 mod query;
+mod sessions;
+mod similar;
+mod thumbnails;
+pub use albums::{
+    add_album_images, create_album, delete_album, get_album_images, get_albums,
+    remove_album_images, rename_album, reorder_album_images, reorder_albums, update_smart_album,
+};
+pub use annotations::{
+    add_image_tags, get_all_tags, get_image_annotations, remove_image_tags, set_image_favorite,
+    set_image_rating,
+};
+pub use cooccurrence::{get_player_cooccurrence, get_player_graph};
+pub use duplicates::find_duplicate_images;
+pub use facets::get_search_facets;
+pub use fulltext::search_images_fulltext;
+use ingest::{
+    analyze_image, analyze_in_parallel, apply_change, detect_change, worker_count, write_images,
+    WRITE_BATCH_SIZE,
+};
+pub(crate) use migration::migrate;
 use query::Queries;
+pub use sessions::{get_session_images, get_sessions, rebuild_sessions};
+pub(crate) use sessions::{session_gap, update_sessions};
+pub(crate) use similar::backfill_descriptors;
+pub use similar::find_similar_images;
+pub(crate) use thumbnails::thumbnail_by_image_id;
+use thumbnails::{load_thumbnail, THUMBNAIL_JOIN};
 
 // グローバルでクエリを一度読み込む
 lazy_static::lazy_static! {
-    static ref SQL_QUERIES: Queries = Queries::load();
+    pub(crate) static ref SQL_QUERIES: Queries = Queries::load();
 }
 
 /// データベースの初期化
@@ -53,33 +91,100 @@ pub fn init_db(app: &AppHandle) -> Result<Connection> {
     // }
 
     // データベースに接続
-    let conn = Connection::open(db_path)?;
+    let mut conn = Connection::open(db_path)?;
+    // フォルダ削除時に画像も削除されるよう外部キー制約を有効にする
+    conn.pragma_update(None, "foreign_keys", "ON")?;
+    // スキャン中も検索できるようにWALモードを使用
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    conn.busy_timeout(Duration::from_secs(5))?;
 
-    // 必要なテーブルを作成
-    conn.execute_batch(SQL_QUERIES.create_tables)?; // クエリを使用
+    // 未適用のマイグレーションを実行してテーブルを最新の構成にする
+    migrate(&mut conn, SQL_QUERIES.migrations)?;
 
     Ok(conn)
 }
 
-fn connect_index_db(app: &AppHandle, uuid: &str) -> Result<Connection> {
-    let db_path = app
-        .path()
-        .app_data_dir()
-        .unwrap_or(std::path::PathBuf::from("."))
-        .join(uuid);
-    let conn = Connection::open(db_path)?;
-    conn.execute_batch(SQL_QUERIES.create_sub_index)?; // クエリを使用
+/// UUIDから登録フォルダのIDを取得する
+pub(crate) fn folder_id_by_uuid(conn: &Connection, uuid: &str) -> Result<i64> {
+    conn.query_row(
+        "SELECT id FROM search_folders WHERE uuid = ?",
+        [uuid],
+        |row| row.get(0),
+    )
+}
+
+/// 画像IDから画像ファイルのパスを取得する
+pub(crate) fn image_path_by_id(conn: &Connection, id: i64) -> Result<Option<String>> {
+    conn.query_row("SELECT file_path FROM images WHERE id = ?", [id], |row| {
+        row.get(0)
+    })
+    .optional()
+}
+
+/// 以前のバージョンで作成されたフォルダごとのデータベースを開き、現在の列構成にそろえる
+fn connect_legacy_index_db(db_path: &Path) -> Result<Connection> {
+    let mut conn = Connection::open(db_path)?;
+    migrate(&mut conn, SQL_QUERIES.sub_index_migrations)?;
     Ok(conn)
 }
 
-fn connect_index_db_ro(app: &AppHandle, uuid: &str) -> Result<Connection> {
-    let db_path = app
+/// フォルダごとに作成していたデータベース（アプリデータフォルダの `<uuid>`）を
+/// 統合データベースへ取り込み、取り込み後に削除する
+///
+/// 取り込み済みのファイルは残らないため、起動のたびに呼び出してよい。
+pub fn migrate_legacy_indexes(app: &AppHandle) -> Result<(), String> {
+    let data_dir = app
         .path()
         .app_data_dir()
-        .unwrap_or(std::path::PathBuf::from("."))
-        .join(uuid);
-    let conn = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
-    Ok(conn)
+        .unwrap_or(std::path::PathBuf::from("."));
+    let mut conn = init_db(app).map_err(|e| e.to_string())?;
+    import_legacy_indexes(&mut conn, &data_dir)
+}
+
+/// `data_dir` にある登録フォルダごとのデータベースを取り込む
+fn import_legacy_indexes(conn: &mut Connection, data_dir: &Path) -> Result<(), String> {
+    let folders: Vec<(i64, String)> = conn
+        .prepare("SELECT id, uuid FROM search_folders")
+        .map_err(|e| e.to_string())?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .map_err(|e| e.to_string())?
+        .filter_map(Result::ok)
+        .collect();
+
+    for (folder_id, uuid) in folders {
+        let legacy_path = data_dir.join(&uuid);
+        if !legacy_path.is_file() {
+            continue;
+        }
+        // 古い列構成のまま取り込まないよう、先にスキーマを更新しておく
+        drop(connect_legacy_index_db(&legacy_path).map_err(|e| e.to_string())?);
+
+        conn.execute(
+            "ATTACH DATABASE ? AS legacy",
+            [legacy_path.to_string_lossy().to_string()],
+        )
+        .map_err(|e| e.to_string())?;
+        let imported = (|| -> Result<usize> {
+            let transaction = conn.transaction()?;
+            let count = transaction.execute(SQL_QUERIES.import_legacy_index, params![folder_id])?;
+            transaction.execute(SQL_QUERIES.import_legacy_thumbnails, [])?;
+            transaction.commit()?;
+            Ok(count)
+        })();
+        conn.execute("DETACH DATABASE legacy", [])
+            .map_err(|e| e.to_string())?;
+        let imported = imported.map_err(|e| format!("{}: {}", legacy_path.display(), e))?;
+        println!("旧インデックスを統合: {:?} ({}件)", legacy_path, imported);
+
+        if let Err(err) = fs::remove_file(&legacy_path) {
+            eprintln!(
+                "ファイル '{}' の削除中にエラーが発生しました: {}",
+                legacy_path.display(),
+                err
+            );
+        }
+    }
+    Ok(())
 }
 
 /// フォルダの追加（INSERT）
@@ -91,6 +196,10 @@ pub fn add_folder(app: AppHandle, path: String) -> Result<(), String> {
         params![path, uuid::Uuid::new_v4().to_string()],
     )
     .map_err(|e| e.to_string())?;
+    // 監視対象を更新
+    if let Err(e) = refresh_watcher(&app) {
+        eprintln!("フォルダ監視の更新に失敗: {}", e);
+    }
     Ok(())
 }
 
@@ -103,6 +212,10 @@ pub fn add_ignore_folder(app: AppHandle, path: String) -> Result<(), String> {
         params![path, uuid::Uuid::new_v4().to_string()],
     )
     .map_err(|e| e.to_string())?;
+    // 監視対象を更新
+    if let Err(e) = refresh_watcher(&app) {
+        eprintln!("フォルダ監視の更新に失敗: {}", e);
+    }
     Ok(())
 }
 
@@ -154,34 +267,27 @@ pub fn get_all_ignore_folders(app: AppHandle) -> Result<Vec<SearchFolder>, Strin
 /// フォルダの削除（DELETE）
 #[tauri::command]
 pub fn delete_folder(app: AppHandle, id: i32) -> Result<(), String> {
-    let conn = init_db(&app).map_err(|e| e.to_string())?;
-    let uuid: String = conn
-        .prepare("SELECT uuid From search_folders where id = ?")
-        .map_err(|e| e.to_string())?
-        .query_map(params![id], |row| row.get(0))
-        .map_err(|e| e.to_string())?
-        .filter_map(Result::ok)
-        .next()
-        .unwrap();
-    let db_path = app
-        .path()
-        .app_data_dir()
-        .unwrap_or(std::path::PathBuf::from("."))
-        .join(uuid);
-    match fs::remove_file(db_path.clone()) {
-        Ok(_) => {}
-        Err(err) => eprintln!(
-            "ファイル '{}' の削除中にエラーが発生しました: {}",
-            db_path.display(),
-            err
-        ),
+    let mut conn = init_db(&app).map_err(|e| e.to_string())?;
+    remove_folder(&mut conn, id).map_err(|e| e.to_string())?;
+    // 監視対象を更新
+    if let Err(e) = refresh_watcher(&app) {
+        eprintln!("フォルダ監視の更新に失敗: {}", e);
     }
-    conn.execute(
+    Ok(())
+}
+
+/// 登録フォルダを削除する
+///
+/// 内側に別の登録フォルダがある画像はそのフォルダへ付け替え、
+/// それ以外の画像は外部キー制約（ON DELETE CASCADE）で一緒に削除される。
+fn remove_folder(conn: &mut Connection, id: i32) -> Result<()> {
+    let transaction = conn.transaction()?;
+    transaction.execute(SQL_QUERIES.reassign_folder_images, params![id])?;
+    transaction.execute(
         SQL_QUERIES.delete_folder, // クエリを使用
         params![id],
-    )
-    .map_err(|e| e.to_string())?;
-    Ok(())
+    )?;
+    transaction.commit()
 }
 
 #[tauri::command]
@@ -192,6 +298,10 @@ pub fn delete_ignore_folder(app: AppHandle, id: i32) -> Result<(), String> {
         params![id],
     )
     .map_err(|e| e.to_string())?;
+    // 監視対象を更新
+    if let Err(e) = refresh_watcher(&app) {
+        eprintln!("フォルダ監視の更新に失敗: {}", e);
+    }
     Ok(())
 }
 
@@ -248,7 +358,7 @@ pub fn search_files_in_folders(app: AppHandle) -> Result<Vec<(String, String)>,
     Ok(all_files)
 }
 
-fn is_ignored(path: &Path, ignore_dirs: &HashSet<PathBuf>) -> bool {
+pub(crate) fn is_ignored(path: &Path, ignore_dirs: &HashSet<PathBuf>) -> bool {
     ignore_dirs
         .iter()
         .any(|ignore_dir| path.starts_with(ignore_dir))
@@ -288,6 +398,7 @@ pub fn scan_and_register_images(app: AppHandle) -> Result<(), String> {
         .filter_map(Result::ok)
         .collect();
 
+    let gap = session_gap(&app);
     // 各フォルダ内を再帰探索
     for (folder, uuid) in folders {
         let path = Path::new(&folder);
@@ -312,7 +423,7 @@ pub fn scan_and_register_images(app: AppHandle) -> Result<(), String> {
 
             if is_image_file(file_path) {
                 // 画像ファイルを処理する
-                match process_image_file(&app, file_path, &uuid) {
+                match process_image_file(&app, file_path, &uuid, gap) {
                     Ok(_) => println!("登録成功: {:?}", file_path),
                     Err(e) => eprintln!("登録失敗: {:?}, エラー: {}", file_path, e),
                 }
@@ -373,6 +484,8 @@ pub async fn scan_and_register_images_with_progress(
 
     println!("{:?}", folders);
     let mut i = 1;
+    // 滞在の区切りはスキャンの開始時に1回だけ読む
+    let gap = session_gap(&app);
 
     for (folder, uuid) in folders {
         let path = PathBuf::from(&folder);
@@ -412,9 +525,14 @@ pub async fn scan_and_register_images_with_progress(
             let app_clone = app.clone();
             let uuid_clone = uuid.clone();
 
-            let res =
-                process_images_in_transaction_async(image_files, uuid_clone, Arc::new(app_clone))
-                    .await;
+            let res = process_images_in_transaction_async(
+                image_files,
+                uuid_clone,
+                event_id.clone(),
+                gap,
+                Arc::new(app_clone),
+            )
+            .await;
             // トランザクションを使用してフォルダ内の画像を一括登録
             if let Err(e) = res {
                 eprintln!("フォルダ処理失敗: {} - エラー: {}", folder, e);
@@ -446,7 +564,7 @@ pub async fn scan_and_register_images_with_progress(
 }
 
 /// ファイルが画像かどうかを判定
-fn is_image_file(path: &Path) -> bool {
+pub(crate) fn is_image_file(path: &Path) -> bool {
     if let Some(ext) = path.extension() {
         matches!(
             ext.to_string_lossy().to_lowercase().as_str(),
@@ -457,88 +575,29 @@ fn is_image_file(path: &Path) -> bool {
     }
 }
 
-fn process_image_file(app: &AppHandle, file_path: &Path, uuid: &str) -> Result<(), String> {
-    let conn = connect_index_db(app, uuid).expect("sub index db not found");
-
-    let db_meta = conn
-        .prepare("SELECT file_size,length(thumbnail),updated_at FROM images WHERE file_path = ?")
-        .map_err(|e| e.to_string())?
-        .query_row([file_path.to_string_lossy().to_string()], |row| {
-            let file_size: i32 = row.get(0)?;
-            let thumbnail_size: i32 = row.get(1)?;
-            let updated_at: String = row.get(2)?;
-            Ok((file_size, thumbnail_size, updated_at))
-        })
-        .optional()
-        .map_err(|e| e.to_string());
-
-    let metadata = fs::metadata(file_path).map_err(|e| e.to_string())?;
-    let file_size = metadata.len() as i32;
-    match db_meta {
-        Ok(Some(db_meta)) => {
-            if file_size == db_meta.0
-                && db_meta.1 > 0
-                && SystemTime::UNIX_EPOCH
-                    .checked_add(Duration::from_secs(
-                        db_meta.2.parse::<DateTime<Utc>>().unwrap().timestamp() as u64,
-                    ))
-                    .unwrap()
-                    <= metadata.modified().unwrap()
-            {
-                return Ok(());
-            }
-        }
-        Ok(None) => {}
-        Err(e) => return Err(e.to_string()),
+pub(crate) fn process_image_file(
+    app: &AppHandle,
+    file_path: &Path,
+    uuid: &str,
+    session_gap: chrono::Duration,
+) -> Result<(), String> {
+    // 監視スレッドから呼ばれるため、panicせずにエラーを返す
+    let mut conn = init_db(app).map_err(|e| e.to_string())?;
+    let folder_id = folder_id_by_uuid(&conn, uuid).map_err(|e| e.to_string())?;
+
+    // 変更の無いファイルやメタデータだけが変わったファイルはデコードしない
+    let change = detect_change(&conn, file_path)?;
+    if apply_change(&conn, file_path, &change)? {
+        return Ok(());
     }
 
-    // サムネイル生成
-    let thumbnail = generate_thumbnail(app, file_path).map_err(|e| e.to_string())?;
-
-    // 画像の幅と高さを取得
-    let image = image::open(file_path).map_err(|e| e.to_string())?;
-    let (width, height) = image.dimensions();
-
-    // iTXtチャンクからメタデータを取得
-    let metadata_json = extract_metadata(file_path).unwrap_or(None);
-    let file_created_at_time: DateTime<Utc> = fs::metadata(file_path)
-        .map_err(|e| {
-            format!(
-                "Failed to get metadata for file {}: {}",
-                file_path.to_string_lossy(),
-                e
-            )
-        })?
-        .created()
-        .map_err(|e| {
-            format!(
-                "Failed to get created date for file {}: {}",
-                file_path.to_string_lossy(),
-                e
-            )
-        })?
-        .into();
-    let file_created_at = file_created_at_time.to_rfc3339();
-    // 現在時刻を取得
-    let created_at = Utc::now().to_rfc3339();
-    let updated_at = created_at.clone();
-
-    // 保存処理を実行
-    conn.execute(
-        SQL_QUERIES.insert_image,
-        params![
-            file_path.to_string_lossy().to_string(),
-            thumbnail,
-            width as i32,
-            height as i32,
-            file_size,
-            metadata_json.unwrap_or_default(), // JSONデータがない場合は空文字列
-            file_created_at,
-            created_at,
-            updated_at,
-        ],
-    )
-    .map_err(|e| e.to_string())?;
+    // メタデータ・大きさ・サムネイルなどを取得して保存する
+    let image = analyze_image(file_path)?;
+    write_images(&mut conn, folder_id, &[image]).map_err(|e| e.to_string())?;
+    // 一括登録と同じく、旧形式のメタデータをJSONに変換
+    normalize_legacy_metadata(&conn).map_err(|e| e.to_string())?;
+    index_instances(&conn).map_err(|e| e.to_string())?;
+    update_sessions(&conn, session_gap).map_err(|e| e.to_string())?;
 
     Ok(())
 }
@@ -546,90 +605,57 @@ fn process_image_file(app: &AppHandle, file_path: &Path, uuid: &str) -> Result<(
 async fn process_images_in_transaction_async(
     file_paths: Vec<PathBuf>,
     uuid: String,
+    event_id: String,
+    session_gap: chrono::Duration,
     app: Arc<AppHandle>,
 ) -> std::result::Result<u32, String> {
     // スレッドブロッキング部分
     let transaction_result = task::spawn_blocking(move || {
         let mut i = 1;
-        // 各ファイルの画像処理
-        let chunk_size = 20; // トランザクションごとに処理する件数
-        for chunk in file_paths.chunks(chunk_size) {
-            // トランザクション開始
-            let mut conn = connect_index_db(app.as_ref(), &uuid).map_err(|e| e.to_string())?;
-            let transaction = conn.transaction().map_err(|e| e.to_string())?;
-
-            for file_path in chunk {
-                // データベースのメタデータを取得
-                let db_meta = transaction
-                    .prepare("SELECT file_size,length(thumbnail),updated_at FROM images WHERE file_path = ?")
-                    .map_err(|e| e.to_string())?
-                    .query_row([file_path.to_string_lossy().to_string()], |row| {
-                        let file_size: i32 = row.get(0)?;
-                        let thumbnail_size: i32 = row.get(1)?;
-                        let updated_at: String = row.get(2)?;
-                        Ok((file_size, thumbnail_size, updated_at))
-                    })
-                    .optional()
-                    .map_err(|e| e.to_string());
-
-                let metadata = fs::metadata(file_path).map_err(|e| e.to_string())?;
-                let file_size = metadata.len() as i32;
-
-                match db_meta {
-                    Ok(Some(db_meta)) => {
-                        if file_size == db_meta.0
-                            && db_meta.1 > 0
-                            && SystemTime::UNIX_EPOCH
-                            .checked_add(Duration::from_secs(db_meta.2.parse::<DateTime<Utc>>().unwrap().timestamp() as u64))
-                            .unwrap()
-                            <= metadata.modified().unwrap()
-                        {
-                            continue;
-                        }
-                    }
-                    Ok(None) => {}
-                    Err(e) => {
-                        return Err(e.to_string());
-                    }
-                }
-
-                // サムネイルの生成およびデータの収集
-                let thumbnail = base64::encode(generate_thumbnail(&app, file_path).map_err(|e| e.to_string())?);
-                let image = image::open(file_path).map_err(|e| e.to_string())?;
-                let (width, height) = image.dimensions();
-                let metadata_json = extract_metadata(file_path).unwrap_or(None);
-                let file_created_at_time: DateTime<Utc> = fs::metadata(file_path.clone())
-                    .map_err(|e| format!("Failed to get metadata for file {}: {}", file_path.to_string_lossy(), e))?
-                    .created()
-                    .map_err(|e| format!("Failed to get created date for file {}: {}", file_path.to_string_lossy(), e))?
-                    .into();
-                let file_created_at = file_created_at_time.to_rfc3339();
-                let created_at = Utc::now().to_rfc3339();
-                let updated_at = created_at.clone();
-
-                // データベースに挿入
-                transaction.execute(
-                    SQL_QUERIES.insert_image,
-                    params![
-                file_path.to_string_lossy(),
-                thumbnail,
-                width as i32,
-                height as i32,
-                file_size,
-                metadata_json.unwrap_or_default(),
-                file_created_at,
-                created_at,
-                updated_at
-            ],
-                )
-                    .map_err(|e| e.to_string())?;
-                println!("{:?}",file_path.to_string_lossy());
-                i+=1;
+        let mut conn = init_db(app.as_ref()).map_err(|e| e.to_string())?;
+        let folder_id = folder_id_by_uuid(&conn, &uuid).map_err(|e| e.to_string())?;
+        // 登録済みで変更の無いファイルは開かず、メタデータだけが変わったファイルはデコードしない
+        let mut pending = Vec::new();
+        let transaction = conn.transaction().map_err(|e| e.to_string())?;
+        for file_path in file_paths {
+            // 途中で消えたファイルや読めないファイルは飛ばし、画面にも知らせる
+            let applied = detect_change(&transaction, &file_path)
+                .and_then(|change| apply_change(&transaction, &file_path, &change));
+            match applied {
+                Ok(true) => {}
+                Ok(false) => pending.push(file_path),
+                Err(e) => report_failure(&app, &event_id, i, &file_path, &e)?,
             }
-
-            // トランザクションのコミット
-            transaction.commit().map_err(|e| e.to_string())?;
         }
+        transaction.commit().map_err(|e| e.to_string())?;
+
+        // デコードとサムネイル生成はCPUの数だけのスレッドで並列に行い、
+        // データベースへの書き込みはこのスレッドでまとめて行う
+        let mut batch = Vec::with_capacity(WRITE_BATCH_SIZE);
+        analyze_in_parallel(&pending, worker_count(), |file_path, analyzed| {
+            match analyzed {
+                Ok(image) => {
+                    println!("{:?}", file_path.to_string_lossy());
+                    batch.push(image);
+                    i += 1;
+                }
+                // 読めないファイルは飛ばし、画面にも知らせる
+                Err(e) => report_failure(&app, &event_id, i, file_path, &e)?,
+            }
+            if batch.len() >= WRITE_BATCH_SIZE {
+                write_images(&mut conn, folder_id, &batch).map_err(|e| e.to_string())?;
+                batch.clear();
+            }
+            Ok(())
+        })?;
+        write_images(&mut conn, folder_id, &batch).map_err(|e| e.to_string())?;
+
+        // 以前のバージョンで文字列のまま登録された旧形式のメタデータを変換
+        normalize_legacy_metadata(&conn).map_err(|e| e.to_string())?;
+        backfill_capture_times(&conn).map_err(|e| e.to_string())?;
+        backfill_dhashes(&conn).map_err(|e| e.to_string())?;
+        index_instances(&conn).map_err(|e| e.to_string())?;
+        update_sessions(&conn, session_gap).map_err(|e| e.to_string())?;
         Ok::<u32, String>(i)
     })
         .await
@@ -637,65 +663,136 @@ async fn process_images_in_transaction_async(
     transaction_result?
 }
 
-fn extract_metadata(file_path: &Path) -> Result<Option<String>, String> {
-    // ファイルを開く
-    let file = File::open(file_path).map_err(|e| format!("ファイルを開けませんでした: {}", e))?;
+/// 登録できなかったファイルをスキャンの進捗として画面に知らせる
+fn report_failure(
+    app: &AppHandle,
+    event_id: &str,
+    progress: u32,
+    file_path: &Path,
+    error: &str,
+) -> std::result::Result<(), String> {
+    eprintln!("登録失敗: {:?}, エラー: {}", file_path, error);
+    let message = format!("登録失敗: {} - エラー: {}", file_path.display(), error);
+    let event_data = json!({
+        "event_id": event_id,
+        "progress": progress,
+        "message": message,
+    });
+    app.emit("scan_progress", &event_data)
+        .map_err(|e| e.to_string())
+}
 
-    // PNGデコーダーを作成
-    let decoder = Decoder::new(file);
-    let reader = decoder
-        .read_info()
-        .map_err(|e| format!("PNG解析エラー: {}", e))?;
+/// 文字列のまま保存されている旧形式（lfs|2|...）のメタデータをJSONに変換する
+fn normalize_legacy_metadata(conn: &Connection) -> Result<usize> {
+    let rows: Vec<(i64, String)> = conn
+        .prepare("SELECT id, metadata_json FROM images WHERE metadata_json LIKE 'lfs|%'")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .filter_map(Result::ok)
+        .collect();
 
-    // PNGのメタデータチャンク（iTXt）を検索
-    if let Some(itxt) = reader
-        .info()
-        .utf8_text
-        .iter()
-        .find(|t| t.keyword == "Description")
-    {
-        // iTXtの内容を取得
-        let data = itxt.get_text().unwrap_or("".parse().unwrap());
-        // JSONとしてパース可能な場合はパース
-        match serde_json::from_str::<Value>(&data) {
-            Ok(parsed_json) => {
-                Ok(Some(parsed_json.to_string())) // JSON文字列として返却
-            }
-            Err(_) => {
-                // JSONでない場合は元データをそのまま返す
-                Ok(Some(data.clone()))
-            }
+    let mut stmt =
+        conn.prepare("UPDATE images SET metadata_json = ?1, metadata_source = ?2 WHERE id = ?3")?;
+    let mut updated = 0;
+    for (id, data) in rows {
+        if let Some(parsed_json) = parse_legacy_metadata(&data) {
+            stmt.execute(params![
+                parsed_json.to_string(),
+                MetadataSource::LegacyLfs.as_str(),
+                id
+            ])?;
+            updated += 1;
         }
-    } else {
-        // iTXtが存在しない場合
-        Ok(None)
     }
+    Ok(updated)
 }
 
-/// サムネイル生成
-fn generate_thumbnail(_app: &AppHandle, file_path: &Path) -> Result<Vec<u8>> {
-    let image = image::open(file_path)
-        .map_err(|_| "画像を開けませんでした")
-        .unwrap();
-    let thumbnail = image.thumbnail(256, 256); // サムネイルサイズは256に縮小
+/// 撮影日時の取得元が記録されていない（以前のバージョンで登録された）画像の撮影日時を
+/// ファイル名から決め直す
+///
+/// ファイル名から決められない画像は、以前のバージョンが保存したファイルの作成日時をそのまま使い、
+/// 取得元を記録して次回以降の対象から外す。
+fn backfill_capture_times(conn: &Connection) -> Result<usize> {
+    let rows: Vec<(i64, String)> = conn
+        .prepare("SELECT id, file_path FROM images WHERE capture_time_source IS NULL")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .filter_map(Result::ok)
+        .collect();
 
-    let mut buffer = Vec::new();
-    thumbnail
-        .write_to(
-            &mut std::io::Cursor::new(&mut buffer),
-            image::ImageFormat::Png,
-        )
-        .map_err(|_| "サムネイルのエンコードに失敗しました")
-        .expect("");
-    //TODO: ERR
-    Ok(buffer)
+    let mut stmt = conn.prepare(
+        "UPDATE images SET file_created_at = ?1, capture_time_source = ?2 WHERE id = ?3",
+    )?;
+    let mut updated = 0;
+    for (id, file_path) in rows {
+        if let Some(captured_at) = parse_vrchat_file_name(Path::new(&file_path)) {
+            stmt.execute(params![
+                captured_at.to_rfc3339(),
+                CaptureTimeSource::FileName.as_str(),
+                id
+            ])?;
+            updated += 1;
+        }
+    }
+    conn.execute(
+        "UPDATE images SET capture_time_source = ?1 WHERE capture_time_source IS NULL",
+        params![CaptureTimeSource::Created.as_str()],
+    )?;
+    Ok(updated)
+}
+
+/// 未解析のインスタンスIDを分解して公開範囲・リージョン・作成者・nonceを設定する
+pub(crate) fn index_instances(conn: &Connection) -> Result<usize> {
+    let rows: Vec<String> = conn
+        .prepare("SELECT instance_id FROM instances WHERE access_type IS NULL")?
+        .query_map([], |row| row.get(0))?
+        .filter_map(Result::ok)
+        .collect();
+
+    let mut stmt = conn.prepare(
+        "UPDATE instances SET access_type = ?1, region = ?2, owner_id = ?3, nonce = ?4
+         WHERE instance_id = ?5",
+    )?;
+    for instance_id in &rows {
+        let instance = parse_instance_id(instance_id);
+        stmt.execute(params![
+            instance.access_type.as_str(),
+            instance.region,
+            instance.owner_id,
+            instance.nonce,
+            instance_id
+        ])?;
+    }
+    Ok(rows.len())
+}
+
+/// 知覚ハッシュが無い（以前のバージョンで登録された）画像のハッシュをサムネイルから計算する
+pub(crate) fn backfill_dhashes(conn: &Connection) -> Result<usize> {
+    let ids: Vec<i64> = conn
+        .prepare(&format!(
+            "SELECT images.id FROM images {} WHERE images.dhash IS NULL AND thumbnails.data IS NOT NULL",
+            THUMBNAIL_JOIN
+        ))?
+        .query_map([], |row| row.get(0))?
+        .filter_map(Result::ok)
+        .collect();
+
+    let mut stmt = conn.prepare("UPDATE images SET dhash = ?1 WHERE id = ?2")?;
+    let mut updated = 0;
+    for id in ids {
+        let Some(image) = load_thumbnail(conn, id)? else {
+            continue;
+        };
+        stmt.execute(params![dhash(&image) as i64, id])?;
+        updated += 1;
+    }
+    Ok(updated)
 }
 
 #[tauri::command]
 pub fn generate_and_get_thumbnails(
     app: AppHandle,
     file_paths: Vec<(String, String)>,
-) -> Result<Vec<(String, String, String)>, String> {
+) -> Result<Vec<(String, i64, String)>, String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
     let mut results = Vec::new();
     for (file_path, uuid) in file_paths {
         let path = Path::new(&file_path);
@@ -705,21 +802,23 @@ pub fn generate_and_get_thumbnails(
         if !is_image_file(path) {
             continue;
         }
-        let conn = connect_index_db_ro(&app, &uuid).map_err(|e| e.to_string())?; // サムネイルがデータベースに存在するか確認
+        // サムネイルがデータベースに存在するか確認
         let mut stmt = conn
-            .prepare("SELECT thumbnail FROM images WHERE file_path = ?")
+            .prepare(
+                "SELECT images.id FROM images
+                 JOIN thumbnails ON thumbnails.image_id = images.id AND thumbnails.variant = 'small'
+                 WHERE images.file_path = ?",
+            )
             .map_err(|e| format!("クエリ準備エラー: {}", e))?;
 
-        let existing_thumbnail: Option<String> = stmt
+        let existing_thumbnail: Option<i64> = stmt
             .query_map([file_path.clone()], |row| row.get(0))
             .map_err(|e| format!("クエリ実行エラー: {}", e))?
             .filter_map(Result::ok)
             .next();
-        if let Some(thumbnail) = existing_thumbnail {
-            let mime_type = "image/png";
-            let base64_formatted = format!("data:{};base64,{}", mime_type, thumbnail);
-            // サムネイルが存在すれば結果に追加
-            results.push((file_path.clone(), base64_formatted, uuid.clone()));
+        if let Some(image_id) = existing_thumbnail {
+            // サムネイルが存在すれば結果に追加（画像は vrcxthumb:// から取得する）
+            results.push((file_path.clone(), image_id, uuid.clone()));
             continue;
         }
     }
@@ -738,10 +837,10 @@ pub fn get_image_metadata(
         return Err("指定されたファイルが存在しません。".to_string());
     }
 
-    // UUIDに基づきデータベースに接続
-    let conn = connect_index_db(&app, &uuid).map_err(|e| {
+    // データベースに接続
+    let conn = init_db(&app).map_err(|e| {
         let msg = format!(
-            "サブデータベース接続エラー: {} (UUID: {}, Path: {:?})",
+            "データベース接続エラー: {} (UUID: {}, Path: {:?})",
             e,
             uuid,
             app.path().app_data_dir()
@@ -750,186 +849,755 @@ pub fn get_image_metadata(
     })?;
 
     // DBからメタデータ取得
-    let (metadata_json, file_created_at): (Option<String>, Option<String>) = conn
+    let row = conn
         .query_row(
-            "SELECT metadata_json, file_created_at FROM images WHERE file_path = ?",
+            "SELECT id, metadata_json, file_created_at, metadata_source, capture_time_source FROM images WHERE file_path = ?",
             params![file_path],
-            |row| Ok((row.get(0)?, row.get(1)?)),
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, Option<String>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                ))
+            },
         )
         .optional()
-        .map_err(|e| format!("クエリエラー: {}", e))?
-        .unwrap_or((None, None));
-
-    if let Some(metadata) = metadata_json {
-        // ファイルを読み取りBase64エンコード
-        return match fs::read(path) {
-            Ok(file_data) => {
-                let base64_content = STANDARD.encode(&file_data);
-                let mime_type = mime_guess::from_path(&file_path)
-                    .first_or_octet_stream()
-                    .essence_str()
-                    .to_string();
-                let data_url = format!("data:{};base64,{}", mime_type, base64_content);
-
-                let metadata_value: Value = serde_json::from_str(&metadata).unwrap_or(Value::Null);
-                let result = Value::Object(
-                    vec![
-                        ("metadata".to_string(), metadata_value),
-                        ("data_url".to_string(), Value::String(data_url)),
-                        (
-                            "file_created_at".to_string(),
-                            Value::String(file_created_at.unwrap_or("".to_string())),
-                        ),
-                    ]
-                    .into_iter()
-                    .collect(),
-                );
-                Ok(result)
-            }
-            Err(e) => Err(format!("ファイル読み取りエラー: {}", e)),
-        };
+        .map_err(|e| format!("クエリエラー: {}", e))?;
+
+    if let Some((image_id, Some(metadata), file_created_at, metadata_source, capture_time_source)) =
+        row
+    {
+        let metadata_value: Value = serde_json::from_str(&metadata).unwrap_or(Value::Null);
+        // 画像そのものは vrcximg:// から取得する
+        let result = Value::Object(
+            vec![
+                ("metadata".to_string(), metadata_value),
+                (
+                    "image_id".to_string(),
+                    Value::Number(Number::from(image_id)),
+                ),
+                (
+                    "file_created_at".to_string(),
+                    Value::String(file_created_at.unwrap_or("".to_string())),
+                ),
+                (
+                    "metadata_source".to_string(),
+                    metadata_source.map(Value::String).unwrap_or(Value::Null),
+                ),
+                (
+                    "capture_time_source".to_string(),
+                    capture_time_source
+                        .map(Value::String)
+                        .unwrap_or(Value::Null),
+                ),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        return Ok(result);
     }
     Err("指定されたファイルのメタデータが見つかりません。".to_string())
 }
 
+/// 条件に一致する画像を撮影日時順に返す（`filter` が `None` の場合は登録済みの全画像）
+fn query_images(conn: &Connection, filter: Option<&Expr>) -> Result<Vec<(String, i64, String)>> {
+    let (source, params) = image_source(filter);
+    let query = format!(
+        "SELECT images.file_path, images.id, search_folders.uuid {} ORDER BY images.file_created_at",
+        source
+    );
+
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params_from_iter(params.iter()), |row| {
+        let file_path: String = row.get(0)?;
+        let image_id: i64 = row.get(1)?;
+        let uuid: String = row.get(2)?;
+        Ok((file_path, image_id, uuid))
+    })?;
+
+    let mut results = Vec::new();
+    // 検索結果を Vec に格納して返却
+    for row in rows {
+        match row {
+            Ok(row) => {
+                results.push(row);
+            }
+            Err(e) => {
+                println!("{:?}", e);
+            }
+        }
+    }
+    Ok(results)
+}
+
 #[tauri::command]
 pub fn search_images(
     app: AppHandle,
     conditions: Vec<HashMap<String, String>>,
-) -> Result<Vec<(String, String, String)>, String> {
-    let con = init_db(&app).map_err(|e| e.to_string())?;
-    let subdb_list: Vec<(String, String)> = con
-        .prepare(SQL_QUERIES.select_all_folders)
-        .map_err(|e| e.to_string())?
-        .query_map([], |row| {
-            let folder: String = row.get(1)?; // フォルダパス
-            let uuid: String = row.get(2)?; // UUID
-            Ok((folder, uuid))
+) -> Result<Vec<(String, i64, String)>, String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    let filter = Expr::from_conditions(&conditions).map_err(|e| e.to_string())?;
+    query_images(&conn, filter.as_ref()).map_err(|_e| String::from("検索クエリエラー"))
+}
+
+/// クエリ文字列（`world:"Great Pug" AND (player:Alice OR player:Bob)` など）で検索する
+///
+/// 構文は `search` モジュールを参照。解析に失敗した場合はエラー位置を含む `QueryError` を返す。
+#[tauri::command]
+pub fn search_images_by_query(
+    app: AppHandle,
+    query: String,
+) -> std::result::Result<Vec<(String, i64, String)>, QueryError> {
+    let filter = parse(&query)?;
+    let conn = init_db(&app)?;
+    Ok(query_images(&conn, filter.as_ref())?)
+}
+
+/// 1ページあたりの件数（既定値と上限）
+const DEFAULT_PAGE_SIZE: u32 = 100;
+const MAX_PAGE_SIZE: u32 = 1000;
+
+/// 並び替えに使う式（カーソルで比較できるよう、NULLにならないようにする）
+///
+/// ワールド名と人数はトリガーで維持している列を使い、インデックスで並び替える。
+fn sort_expression(sort: SortKey) -> &'static str {
+    match sort {
+        SortKey::CapturedAt => "images.file_created_at",
+        SortKey::World => "images.world_name",
+        SortKey::FileSize => "coalesce(images.file_size, 0)",
+        SortKey::PlayerCount => "images.player_count",
+    }
+}
+
+/// ページの最後の画像の並び替えキーとIDをカーソル文字列にする
+fn encode_cursor(key: &SqlValue, id: i64) -> String {
+    let key = match key {
+        SqlValue::Integer(n) => Value::from(*n),
+        SqlValue::Text(text) => Value::from(text.as_str()),
+        _ => Value::Null,
+    };
+    URL_SAFE_NO_PAD.encode(Value::Array(vec![key, Value::from(id)]).to_string())
+}
+
+fn decode_cursor(cursor: &str) -> Option<(SqlValue, i64)> {
+    let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    let value: Value = serde_json::from_slice(&bytes).ok()?;
+    let key = match value.get(0)? {
+        Value::String(text) => SqlValue::Text(text.clone()),
+        Value::Number(n) => SqlValue::Integer(n.as_i64()?),
+        _ => return None,
+    };
+    Some((key, value.get(1)?.as_i64()?))
+}
+
+/// ページの1行（画像ID・ファイルパス・フォルダのUUID・並び替えキー）
+type PageRow = (i64, String, String, SqlValue);
+
+/// 存在するファイルだけで1ページを埋める
+///
+/// `fetch` はカーソル（並び替えキーと画像ID）より後ろの行を、並び順に指定した件数まで返す。
+/// 登録後に削除されたファイルを除いてから件数を数えるため、続きがあるのに短いページは返さない。
+fn fill_page(
+    cursor: Option<(SqlValue, i64)>,
+    limit: u32,
+    mut fetch: impl FnMut(Option<&(SqlValue, i64)>, u32) -> Result<Vec<PageRow>>,
+) -> Result<SearchPage> {
+    let mut cursor = cursor;
+    let mut items = Vec::new();
+    let mut last_item = None;
+    loop {
+        // 次のページがあるかを知るために1件多く取得する
+        let count = limit + 1 - items.len() as u32;
+        let rows = fetch(cursor.as_ref(), count)?;
+        let exhausted = rows.len() < count as usize;
+        for (id, file_path, uuid, key) in rows {
+            cursor = Some((key, id));
+            if !Path::new(&file_path).exists() {
+                continue;
+            }
+            if items.len() == limit as usize {
+                // ページに入りきらない画像が残っている
+                let next_cursor = last_item.map(|(key, id)| encode_cursor(&key, id));
+                return Ok(SearchPage { items, next_cursor });
+            }
+            items.push((file_path, id, uuid));
+            last_item = cursor.clone();
+        }
+        if exhausted {
+            return Ok(SearchPage {
+                items,
+                next_cursor: None,
+            });
+        }
+    }
+}
+
+/// 条件に一致する画像を1ページ分返す
+///
+/// 並び替えキーと画像IDの組でページの続きを指定する（キーセットページネーション）ため、
+/// ページを読む間に画像が追加・削除されても重複や抜けが起きない。
+fn query_image_page(
+    conn: &Connection,
+    filter: Option<&Expr>,
+    request: &SearchPageRequest,
+) -> std::result::Result<SearchPage, QueryError> {
+    let sort_key = sort_expression(request.sort);
+    let (order, comparison) = if request.descending {
+        ("DESC", "<")
+    } else {
+        ("ASC", ">")
+    };
+    let limit = request
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let cursor = match &request.cursor {
+        Some(cursor) => Some(decode_cursor(cursor).ok_or_else(|| QueryError {
+            message: "カーソルが不正です".to_string(),
+            position: None,
+            token: None,
+        })?),
+        None => None,
+    };
+
+    let (source, filter_params) = image_source(filter);
+    let page = fill_page(cursor, limit, |cursor, count| {
+        let mut query = format!(
+            "SELECT images.id, images.file_path, search_folders.uuid, {} {}",
+            sort_key, source
+        );
+        let mut params: Vec<SqlValue> = filter_params.iter().cloned().map(SqlValue::Text).collect();
+        if let Some((key, id)) = cursor {
+            query.push_str(&format!(
+                " AND ({}, images.id) {} (?, ?)",
+                sort_key, comparison
+            ));
+            params.push(key.clone());
+            params.push(SqlValue::Integer(*id));
+        }
+        query.push_str(&format!(
+            " ORDER BY {} {}, images.id {} LIMIT {}",
+            sort_key, order, order, count
+        ));
+
+        let mut stmt = conn.prepare(&query)?;
+        let rows = stmt
+            .query_map(params_from_iter(params.iter()), |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?
+            .collect();
+        rows
+    })?;
+    Ok(page)
+}
+
+/// クエリ文字列または検索画面の条件リストを構文木にする（どちらも無い場合は `None`）
+fn resolve_filter(
+    query: &Option<String>,
+    conditions: &Option<Vec<HashMap<String, String>>>,
+) -> std::result::Result<Option<Expr>, QueryError> {
+    Ok(match (query, conditions) {
+        (Some(query), _) => parse(query)?,
+        (None, Some(conditions)) => Expr::from_conditions(conditions)?,
+        (None, None) => None,
+    })
+}
+
+/// 検索対象の画像を選ぶ `FROM ... WHERE ...` 句とバインド値
+///
+/// 一覧・ページ・集計で同じ画像が対象になるよう、検索はすべてこの句を使う。
+/// 条件がある場合はメタデータを持つ画像だけを対象にし、条件が無い場合は登録済みの全画像を対象にする。
+fn image_source(filter: Option<&Expr>) -> (String, Vec<String>) {
+    let mut source = String::from(
+        "FROM images JOIN search_folders ON search_folders.id = images.folder_id WHERE 1=1",
+    );
+    let mut params = Vec::new();
+    if let Some(filter) = filter {
+        let compiled = compile(filter);
+        source.push_str(" AND json_valid(images.metadata_json) = 1 AND ");
+        source.push_str(&compiled.sql);
+        params = compiled.params;
+    }
+    (source, params)
+}
+
+/// 条件に一致する画像を返すSELECT文とバインド値（集計クエリの `WITH hits AS (...)` に使う）
+fn hit_set(filter: Option<&Expr>) -> (String, Vec<String>) {
+    let (source, params) = image_source(filter);
+    (format!("SELECT images.* {}", source), params)
+}
+
+/// 検索結果を並び替えてページ単位で返す
+#[tauri::command]
+pub fn search_images_page(
+    app: AppHandle,
+    request: SearchPageRequest,
+) -> std::result::Result<SearchPage, QueryError> {
+    let filter = resolve_filter(&request.query, &request.conditions)?;
+    let conn = init_db(&app)?;
+    query_image_page(&conn, filter.as_ref(), &request)
+}
+
+/// ユーザーIDに対して、インデックス内の写真に記録されている表示名をすべて返す
+///
+/// VRChatの表示名は変更できるため、同じユーザーが複数の名前で記録されている場合がある。
+#[tauri::command]
+pub fn get_player_names(app: AppHandle, user_id: String) -> Result<Vec<PlayerName>, String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT image_players.display_name, count(DISTINCT images.id),
+                    min(images.file_created_at), max(images.file_created_at)
+             FROM image_players
+             JOIN images ON images.id = image_players.image_id
+             WHERE image_players.user_id = ?
+             GROUP BY image_players.display_name
+             ORDER BY max(images.file_created_at) DESC",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let names = stmt
+        .query_map([user_id], |row| {
+            Ok(PlayerName {
+                display_name: row.get(0)?,
+                image_count: row.get(1)?,
+                first_seen: row.get(2)?,
+                last_seen: row.get(3)?,
+            })
         })
-        .map_err(|e| e.to_string())? // エラー処理
+        .map_err(|e| e.to_string())?
         .filter_map(Result::ok)
         .collect();
-    let mut query = String::from(
-        "SELECT DISTINCT file_path, thumbnail FROM images WHERE 1=1 AND json_valid(metadata_json) = 1",
-    ); // ベースクエリ
-    let mut params: Vec<String> = vec![]; // バインド用パラメータリスト
-
-    let mut condition_segments: Vec<String> = vec![]; // 個別の条件を保存
-    let mut player_count = 0;
-
-    for condition in conditions.clone() {
-        let logic = match condition
-            .get("logic")
-            .unwrap_or(&"AND".to_string())
-            .to_uppercase()
-            .as_str()
-        {
-            "AND" => "AND",
-            "OR" => "OR",
-            _ => "AND",
-        }
-        .to_string();
-        let default_field = "".to_string();
-        let field = match condition.get("field").unwrap_or(&default_field).as_str() {
-            "player" => "player",
-            "world" => "world",
-            "created_at" => "created_at",
-            _ => "file_path",
-        }
-        .to_string();
-        let default_operator = "=".to_string();
-        let operator = match condition
-            .get("operator")
-            .unwrap_or(&default_operator)
-            .to_uppercase()
-            .as_str()
-        {
-            "EQ" => "=",
-            "NE" => "!=",
-            "GT" => ">",
-            "GE" => ">=",
-            "LT" => "<",
-            "LE" => "<=",
-            "LIKE" => "LIKE",
-            _ => "=",
-        };
-        let default_value = "".to_string();
-        let value = condition.get("value").unwrap_or(&default_value);
-        if value.is_empty() {
-            continue; // 値のない条件は無視
+
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{Field, Op};
+
+    fn open_test_db() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+        migrate(&mut conn, SQL_QUERIES.migrations).unwrap();
+        for (path, uuid) in [
+            ("/photos", "outer"),
+            ("/photos/vrchat", "inner"),
+            ("/photos-old", "old"),
+        ] {
+            conn.execute(SQL_QUERIES.insert_folder, params![path, uuid])
+                .unwrap();
         }
+        conn
+    }
 
-        let mut segment = match field.as_str() {
-            "player" => {
-                player_count += 1;
-                let player_alias = format!("player{}", player_count);
+    fn insert_image(conn: &Connection, file_path: &str, uuid: &str) {
+        let folder_id = folder_id_by_uuid(conn, uuid).unwrap();
+        conn.execute(
+            SQL_QUERIES.insert_image,
+            params![
+                file_path,
+                1,
+                1,
+                1,
+                None::<String>,
+                "2024-01-01T00:00:00+00:00",
+                "2024-01-01T00:00:00+00:00",
+                "2024-01-01T00:00:00+00:00",
+                None::<String>,
+                CaptureTimeSource::Created.as_str(),
+                folder_id,
+                None::<i64>,
+                None::<i64>,
+                None::<Vec<u8>>,
+                None::<i64>,
+                None::<i64>,
+                None::<i64>,
+                None::<i64>,
+                None::<i64>,
+                MetadataSource::inferred_json()
+            ],
+        )
+        .unwrap();
+    }
 
-                format!(
-                    "EXISTS (SELECT 1 FROM json_each(metadata_json, '$.players') AS {} WHERE json_extract({}.value, '$.displayName') {} ?)",
-                    player_alias, player_alias, operator
-                )
-            }
-            "world" => {
-                format!("JSON_EXTRACT(metadata_json, '$.world.name') {} ?", operator)
-            }
-            "created_at" => {
-                format!("file_created_at {} ?", operator)
-            }
-            _ => "file_path LIKE ?".to_string(),
+    fn insert_metadata(conn: &Connection, file_path: &str, metadata_json: &str) {
+        insert_image(conn, file_path, "outer");
+        conn.execute(
+            "UPDATE images SET metadata_json = ?1 WHERE file_path = ?2",
+            params![metadata_json, file_path],
+        )
+        .unwrap();
+    }
+
+    fn search(conn: &Connection, query: &str) -> Vec<String> {
+        let filter = parse(query).unwrap();
+        let mut paths: Vec<String> = query_images(conn, filter.as_ref())
+            .unwrap()
+            .into_iter()
+            .map(|(file_path, _, _)| file_path)
+            .collect();
+        paths.sort();
+        paths
+    }
+
+    fn folder_of(conn: &Connection, file_path: &str) -> Option<String> {
+        conn.query_row(
+            "SELECT search_folders.uuid FROM images
+             JOIN search_folders ON search_folders.id = images.folder_id
+             WHERE images.file_path = ?",
+            [file_path],
+            |row| row.get(0),
+        )
+        .optional()
+        .unwrap()
+    }
+
+    #[test]
+    fn registers_images_in_deepest_folder() {
+        let conn = open_test_db();
+        // 外側のフォルダとしてスキャンされても内側のフォルダに登録する
+        insert_image(&conn, "/photos/vrchat/a.png", "outer");
+        insert_image(&conn, "/photos/b.png", "outer");
+        insert_image(&conn, "/photos-old/c.png", "outer");
+        assert_eq!(
+            folder_of(&conn, "/photos/vrchat/a.png").as_deref(),
+            Some("inner")
+        );
+        assert_eq!(folder_of(&conn, "/photos/b.png").as_deref(), Some("outer"));
+        assert_eq!(
+            folder_of(&conn, "/photos-old/c.png").as_deref(),
+            Some("old")
+        );
+
+        // 内側のフォルダが後から登録された場合も、登録し直すと付け替える
+        conn.execute(
+            SQL_QUERIES.insert_folder,
+            params!["/photos/vrchat/2024", "new"],
+        )
+        .unwrap();
+        insert_image(&conn, "/photos/vrchat/2024/d.png", "inner");
+        conn.execute("UPDATE images SET folder_id = 1", []).unwrap();
+        insert_image(&conn, "/photos/vrchat/2024/d.png", "outer");
+        assert_eq!(
+            folder_of(&conn, "/photos/vrchat/2024/d.png").as_deref(),
+            Some("new")
+        );
+    }
+
+    #[test]
+    fn removing_outer_folder_keeps_images_of_inner_folder() {
+        let mut conn = open_test_db();
+        insert_image(&conn, "/photos/vrchat/a.png", "inner");
+        insert_image(&conn, "/photos/b.png", "outer");
+        // 以前のバージョンで外側のフォルダに登録された画像
+        conn.execute("UPDATE images SET folder_id = 1", []).unwrap();
+
+        remove_folder(&mut conn, 1).unwrap();
+        assert_eq!(
+            folder_of(&conn, "/photos/vrchat/a.png").as_deref(),
+            Some("inner")
+        );
+        assert_eq!(folder_of(&conn, "/photos/b.png"), None);
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM images", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn imports_legacy_indexes_and_removes_them() {
+        let dir =
+            std::env::temp_dir().join(format!("vrcxphotosearcher-legacy-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let mut conn = open_test_db();
+        insert_image(&conn, "/photos/b.png", "outer");
+        thumbnails::save_thumbnail(&conn, "/photos/b.png", b"new").unwrap();
+
+        // metadata_source・capture_time_source列が無い古いデータベース
+        let legacy = Connection::open(dir.join("outer")).unwrap();
+        legacy
+            .execute_batch(
+                "CREATE TABLE images (
+                     id INTEGER PRIMARY KEY AUTOINCREMENT,
+                     file_path TEXT NOT NULL UNIQUE,
+                     thumbnail TEXT,
+                     width INTEGER,
+                     height INTEGER,
+                     file_size INTEGER,
+                     metadata_json TEXT,
+                     file_created_at TEXT NOT NULL,
+                     created_at TEXT NOT NULL,
+                     updated_at TEXT NOT NULL
+                 );
+                 INSERT INTO images (file_path, thumbnail, width, height, file_size, metadata_json,
+                                     file_created_at, created_at, updated_at)
+                 VALUES ('/photos/a.png', 'iVBORw0KGgo=', 1920, 1080, 100, '{}', 't', 't', 't'),
+                        ('/photos/vrchat/c.png', X'FFD8FFE0', 1920, 1080, 100, NULL, 't', 't', 't'),
+                        ('/photos/b.png', 'b2xk', 1920, 1080, 100, NULL, 't', 't', 't');",
+            )
+            .unwrap();
+        drop(legacy);
+
+        import_legacy_indexes(&mut conn, &dir).unwrap();
+        assert!(!dir.join("outer").exists());
+        assert_eq!(folder_of(&conn, "/photos/a.png").as_deref(), Some("outer"));
+        assert_eq!(
+            folder_of(&conn, "/photos/vrchat/c.png").as_deref(),
+            Some("inner")
+        );
+        // 登録済みの画像は上書きしない
+        let width: i64 = conn
+            .query_row(
+                "SELECT width FROM images WHERE file_path = '/photos/b.png'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(width, 1);
+
+        // サムネイルはBase64の文字列・バイト列のどちらからもthumbnailsテーブルに取り込む
+        let thumbnail_of = |file_path: &str| -> (String, Vec<u8>) {
+            conn.query_row(
+                "SELECT thumbnails.mime, thumbnails.data FROM images
+                 JOIN thumbnails ON thumbnails.image_id = images.id
+                 WHERE images.file_path = ?",
+                [file_path],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap()
         };
+        assert_eq!(
+            thumbnail_of("/photos/a.png"),
+            ("image/png".to_string(), b"\x89PNG\r\n\x1a\n".to_vec())
+        );
+        assert_eq!(
+            thumbnail_of("/photos/vrchat/c.png"),
+            ("image/jpeg".to_string(), vec![0xFF, 0xD8, 0xFF, 0xE0])
+        );
+        // 登録済みの画像のサムネイルも上書きしない
+        assert_eq!(thumbnail_of("/photos/b.png").1, b"new");
+
+        // 取り込み済みの場合は何もしない
+        import_legacy_indexes(&mut conn, &dir).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT COUNT(*) FROM images", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
 
-        // プレースホルダーの値を追加
-        params.push(if field == "player" || field == "world" {
-            if operator.to_uppercase().contains("LIKE") {
-                format!("%{}%", value.clone())
-            } else {
-                value.clone()
-            }
-        } else {
-            value.clone()
-        });
+    #[test]
+    fn matches_world_names_from_metadata_and_worlds() {
+        let conn = open_test_db();
+        // XMPのワールド名のみでワールドIDが無い写真
+        insert_metadata(&conn, "/photos/a.png", r#"{"world":{"name":"Great Pug"}}"#);
+        // 改名前の名前で撮影された写真（worlds には最後に登録された名前が残る）
+        insert_metadata(
+            &conn,
+            "/photos/b.png",
+            r#"{"world":{"id":"wrld_pug","name":"The Great Pug"}}"#,
+        );
+        insert_metadata(
+            &conn,
+            "/photos/c.png",
+            r#"{"world":{"id":"wrld_pug","name":"Great Pug"}}"#,
+        );
+        insert_metadata(
+            &conn,
+            "/photos/d.png",
+            r#"{"world":{"id":"wrld_other","name":"Other"}}"#,
+        );
+
+        assert_eq!(
+            search(&conn, r#"world:"Great Pug""#),
+            ["/photos/a.png", "/photos/b.png", "/photos/c.png"]
+        );
+        assert_eq!(search(&conn, r#"world:"The Great Pug""#), ["/photos/b.png"]);
+    }
 
-        // 条件を適切にロジックに基づき追加
-        if !condition_segments.is_empty() {
-            segment = format!("{} {}", logic, segment);
+    #[test]
+    fn matches_players_and_persons() {
+        let conn = open_test_db();
+        insert_metadata(
+            &conn,
+            "/photos/a.png",
+            r#"{"players":[{"id":"usr_alice","displayName":"Alice"},{"id":"","displayName":"Bob"}]}"#,
+        );
+        // 表示名を変更した後の写真
+        insert_metadata(
+            &conn,
+            "/photos/b.png",
+            r#"{"players":[{"id":"usr_alice","displayName":"Alicia"}]}"#,
+        );
+        insert_metadata(
+            &conn,
+            "/photos/c.png",
+            r#"{"players":[{"id":"","displayName":"Bob"}]}"#,
+        );
+
+        assert_eq!(search(&conn, "player:Alice"), ["/photos/a.png"]);
+        assert_eq!(
+            search(&conn, "player:usr_alice"),
+            ["/photos/a.png", "/photos/b.png"]
+        );
+        assert_eq!(
+            search(&conn, "person:Alice"),
+            ["/photos/a.png", "/photos/b.png"]
+        );
+        assert_eq!(
+            search(&conn, "person:Bob"),
+            ["/photos/a.png", "/photos/c.png"]
+        );
+
+        // Alice以外のプレイヤーも写っている a.png は「Aliceがいない」写真に含めない
+        let not_alice = Expr::term(Field::Player, Op::Ne, "Alice");
+        let mut paths: Vec<String> = query_images(&conn, Some(&not_alice))
+            .unwrap()
+            .into_iter()
+            .map(|(file_path, _, _)| file_path)
+            .collect();
+        paths.sort();
+        assert_eq!(paths, ["/photos/b.png", "/photos/c.png"]);
+    }
+
+    #[test]
+    fn cursor_round_trips() {
+        for (key, id) in [
+            (SqlValue::Text("Great Pug".to_string()), 3),
+            (SqlValue::Integer(1024), 7),
+        ] {
+            let cursor = encode_cursor(&key, id);
+            assert_eq!(decode_cursor(&cursor), Some((key, id)));
         }
-        condition_segments.push(segment);
+        assert_eq!(decode_cursor("not a cursor"), None);
+        assert_eq!(decode_cursor(&URL_SAFE_NO_PAD.encode("[null, 1]")), None);
     }
 
-    // 条件をすべて結合
-    if !condition_segments.is_empty() {
-        query.push_str(" AND (");
-        query.push_str(&condition_segments.join(" "));
-        query.push_str(")");
+    /// すべてのページを順に取得し、ファイル名を返す
+    fn page_through(conn: &Connection, sort: SortKey, descending: bool) -> Vec<String> {
+        let mut request = SearchPageRequest {
+            sort,
+            descending,
+            limit: Some(2),
+            ..Default::default()
+        };
+        let mut names = Vec::new();
+        loop {
+            let page = query_image_page(conn, None, &request).unwrap();
+            assert!(page.items.len() <= 2);
+            names.extend(page.items.into_iter().map(|(file_path, _, _)| {
+                Path::new(&file_path)
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string()
+            }));
+            match page.next_cursor {
+                Some(cursor) => request.cursor = Some(cursor),
+                None => return names,
+            }
+        }
     }
 
-    let mut results = Vec::new();
-    // SQLを実行し、結果を取得
-    for (_, uuid) in subdb_list {
-        let conn = connect_index_db_ro(&app, &uuid).map_err(|e| e.to_string())?;
-        let mut stmt = conn
-            .prepare(&query)
-            .map_err(|_e| String::from("検索クエリエラー"))?;
-        let params = params.clone();
-        let rows = stmt.query_map(params_from_iter(params.iter()), |row| {
-            let file_path: String = row.get(0)?;
-            let thumbnail: String = row.get(1)?;
-            let mime_type = "image/png";
-            let base64_formatted = format!("data:{};base64,{}", mime_type, thumbnail);
-            Ok((file_path, base64_formatted, uuid.clone()))
-        });
-        // 検索結果を Vec に格納して返却
-        for row in rows.map_err(|e| e.to_string())? {
-            match row {
-                Ok(row) => {
-                    results.push(row);
-                }
-                Err(e) => {
-                    println!("{:?}", e);
-                }
+    #[test]
+    fn pages_through_ties_on_sort_key() {
+        let dir =
+            std::env::temp_dir().join(format!("vrcxphotosearcher-pages-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let conn = open_test_db();
+        for (name, world, players) in [
+            ("a.png", "Alpha", r#"[{"id":"usr_1","displayName":"One"}]"#),
+            ("b.png", "Alpha", "[]"),
+            ("c.png", "Alpha", r#"[{"id":"usr_1","displayName":"One"}]"#),
+            ("d.png", "Beta", "[]"),
+            ("e.png", "Beta", r#"[{"id":"usr_2","displayName":"Two"}]"#),
+        ] {
+            let file_path = dir.join(name);
+            fs::write(&file_path, b"").unwrap();
+            insert_metadata(
+                &conn,
+                &file_path.to_string_lossy(),
+                &format!(
+                    r#"{{"world":{{"name":"{}"}},"players":{}}}"#,
+                    world, players
+                ),
+            );
+        }
+
+        // 同じキーの画像はIDの順に、重複も抜けもなく返す
+        assert_eq!(
+            page_through(&conn, SortKey::World, false),
+            ["a.png", "b.png", "c.png", "d.png", "e.png"]
+        );
+        assert_eq!(
+            page_through(&conn, SortKey::World, true),
+            ["e.png", "d.png", "c.png", "b.png", "a.png"]
+        );
+        assert_eq!(
+            page_through(&conn, SortKey::PlayerCount, false),
+            ["b.png", "d.png", "a.png", "c.png", "e.png"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn fills_pages_past_missing_files() {
+        let dir =
+            std::env::temp_dir().join(format!("vrcxphotosearcher-missing-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let conn = open_test_db();
+        for (name, exists) in [
+            ("a.png", true),
+            ("b.png", false),
+            ("c.png", false),
+            ("d.png", true),
+            ("e.png", true),
+        ] {
+            let file_path = dir.join(name);
+            if exists {
+                fs::write(&file_path, b"").unwrap();
             }
+            insert_image(&conn, &file_path.to_string_lossy(), "outer");
         }
+
+        let request = SearchPageRequest {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = query_image_page(&conn, None, &request).unwrap();
+        assert_eq!(page.items.len(), 2);
+        assert!(page.items[1].0.ends_with("d.png"));
+        assert_eq!(
+            page_through(&conn, SortKey::CapturedAt, false),
+            ["a.png", "d.png", "e.png"]
+        );
+        fs::remove_dir_all(&dir).unwrap();
     }
 
-    Ok(results)
+    #[test]
+    fn sorts_by_world_and_player_count_with_indexes() {
+        let conn = open_test_db();
+        for sort in [SortKey::World, SortKey::PlayerCount] {
+            let key = sort_expression(sort);
+            let plan: Vec<String> = conn
+                .prepare(&format!(
+                    "EXPLAIN QUERY PLAN SELECT images.id FROM images
+                     WHERE ({key}, images.id) > (?, ?) ORDER BY {key}, images.id LIMIT 10"
+                ))
+                .unwrap()
+                .query_map(params![0, 0], |row| row.get(3))
+                .unwrap()
+                .collect::<Result<_>>()
+                .unwrap();
+            assert!(
+                plan.iter().all(|detail| !detail.contains("TEMP B-TREE")),
+                "{:?}",
+                plan
+            );
+        }
+    }
 }
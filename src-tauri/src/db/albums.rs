@@ -0,0 +1,574 @@
+use super::{
+    decode_cursor, fill_page, init_db, query_image_page, resolve_filter, DEFAULT_PAGE_SIZE,
+    MAX_PAGE_SIZE,
+};
+use crate::model::album::{Album, AlbumRequest};
+use crate::model::search::{SearchPage, SearchPageRequest};
+use chrono::Utc;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Result};
+use std::collections::HashMap;
+use tauri::AppHandle;
+
+const SELECT_ALBUMS: &str = "SELECT albums.id, albums.name, albums.smart, albums.query, albums.conditions_json,
+           CASE WHEN albums.smart = 0
+                THEN (SELECT count(*) FROM album_images WHERE album_images.album_id = albums.id) END,
+           albums.created_at, albums.updated_at
+    FROM albums";
+
+fn album_from_row(row: &rusqlite::Row) -> Result<Album> {
+    let conditions: Option<String> = row.get(4)?;
+    Ok(Album {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        smart: row.get(2)?,
+        query: row.get(3)?,
+        conditions: conditions.and_then(|json| serde_json::from_str(&json).ok()),
+        image_count: row.get(5)?,
+        created_at: row.get(6)?,
+        updated_at: row.get(7)?,
+    })
+}
+
+fn find_album(conn: &Connection, album_id: i64) -> std::result::Result<Album, String> {
+    conn.query_row(
+        &format!("{} WHERE albums.id = ?", SELECT_ALBUMS),
+        [album_id],
+        album_from_row,
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| "アルバムが見つかりません".to_string())
+}
+
+fn list_albums(conn: &Connection) -> Result<Vec<Album>> {
+    conn.prepare(&format!(
+        "{} ORDER BY albums.position, albums.id",
+        SELECT_ALBUMS
+    ))?
+    .query_map([], album_from_row)?
+    .collect()
+}
+
+/// アルバム名（前後の空白を除く）
+fn album_name(name: &str) -> std::result::Result<&str, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("アルバム名を入力してください".to_string());
+    }
+    Ok(name)
+}
+
+/// スマートアルバムの検索条件を検証し、保存する値（クエリ文字列・条件リストのJSON）を返す
+///
+/// 構文エラーは位置を含むメッセージにして返す。
+fn smart_query(
+    query: &Option<String>,
+    conditions: &Option<Vec<HashMap<String, String>>>,
+) -> std::result::Result<(Option<String>, Option<String>), String> {
+    let query = query.as_ref().filter(|q| !q.trim().is_empty()).cloned();
+    let conditions = if query.is_some() {
+        None
+    } else {
+        conditions.clone()
+    };
+    // 構文エラーは保存時に返す
+    let filter = resolve_filter(&query, &conditions).map_err(|e| e.to_string())?;
+    // 空の条件リストや値の無い条件だけでは全画像に一致してしまうため、スマートアルバムにしない
+    if filter.is_none() && (query.is_some() || conditions.is_some()) {
+        return Err("検索条件を指定してください".to_string());
+    }
+    let conditions_json = conditions
+        .map(|conditions| serde_json::to_string(&conditions))
+        .transpose()
+        .map_err(|e| e.to_string())?;
+    Ok((query, conditions_json))
+}
+
+/// 手動アルバムの画像を並び順に1ページ分返す
+fn query_album_page(
+    conn: &Connection,
+    album_id: i64,
+    cursor: Option<&str>,
+    limit: u32,
+) -> std::result::Result<SearchPage, String> {
+    let cursor = match cursor {
+        Some(cursor) => Some(decode_cursor(cursor).ok_or("カーソルが不正です")?),
+        None => None,
+    };
+    fill_page(cursor, limit, |cursor, count| {
+        let mut query = String::from(
+            "SELECT images.id, images.file_path, search_folders.uuid, album_images.position
+             FROM album_images
+             JOIN images ON images.file_path = album_images.file_path
+             JOIN search_folders ON search_folders.id = images.folder_id
+             WHERE album_images.album_id = ?",
+        );
+        let mut params = vec![SqlValue::Integer(album_id)];
+        if let Some((position, id)) = cursor {
+            query.push_str(" AND (album_images.position, images.id) > (?, ?)");
+            params.push(position.clone());
+            params.push(SqlValue::Integer(*id));
+        }
+        query.push_str(&format!(
+            " ORDER BY album_images.position, images.id LIMIT {}",
+            count
+        ));
+        let mut stmt = conn.prepare(&query)?;
+        let rows = stmt
+            .query_map(params_from_iter(params.iter()), |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            })?
+            .collect();
+        rows
+    })
+    .map_err(|e| e.to_string())
+}
+
+fn album_page(
+    conn: &Connection,
+    album_id: i64,
+    cursor: Option<String>,
+    limit: Option<u32>,
+) -> std::result::Result<SearchPage, String> {
+    let album = find_album(conn, album_id)?;
+    if !album.smart {
+        let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+        return query_album_page(conn, album_id, cursor.as_deref(), limit);
+    }
+    let filter = resolve_filter(&album.query, &album.conditions).map_err(|e| e.to_string())?;
+    let request = SearchPageRequest {
+        cursor,
+        limit,
+        ..Default::default()
+    };
+    query_image_page(conn, filter.as_ref(), &request).map_err(|e| e.to_string())
+}
+
+/// 手動アルバムの末尾に画像を追加し、追加した枚数を返す
+fn add_images(
+    conn: &mut Connection,
+    album_id: i64,
+    file_paths: &[String],
+) -> std::result::Result<usize, String> {
+    if find_album(conn, album_id)?.smart {
+        return Err("スマートアルバムには画像を追加できません".to_string());
+    }
+    let now = Utc::now().to_rfc3339();
+    let transaction = conn.transaction().map_err(|e| e.to_string())?;
+    let mut added = 0;
+    {
+        let mut stmt = transaction
+            .prepare(
+                "INSERT OR IGNORE INTO album_images (album_id, file_path, position, added_at)
+                 VALUES (?1, ?2, (SELECT coalesce(max(position) + 1, 0) FROM album_images WHERE album_id = ?1), ?3)",
+            )
+            .map_err(|e| e.to_string())?;
+        for file_path in file_paths {
+            added += stmt
+                .execute(params![album_id, file_path, now])
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    transaction.commit().map_err(|e| e.to_string())?;
+    Ok(added)
+}
+
+/// 手動アルバムの画像を指定した順に並べ替える
+fn reorder_images(conn: &mut Connection, album_id: i64, file_paths: &[String]) -> Result<()> {
+    let transaction = conn.transaction()?;
+    // 指定しなかった画像は、元の順のまま指定した画像の後ろへずらす
+    transaction.execute(
+        "UPDATE album_images SET position = position + ?1 WHERE album_id = ?2",
+        params![file_paths.len() as i64, album_id],
+    )?;
+    {
+        let mut stmt = transaction.prepare(
+            "UPDATE album_images SET position = ?1 WHERE album_id = ?2 AND file_path = ?3",
+        )?;
+        for (position, file_path) in file_paths.iter().enumerate() {
+            stmt.execute(params![position as i64, album_id, file_path])?;
+        }
+    }
+    transaction.commit()
+}
+
+/// アルバムを並び順に返す
+#[tauri::command]
+pub fn get_albums(app: AppHandle) -> std::result::Result<Vec<Album>, String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    list_albums(&conn).map_err(|e| e.to_string())
+}
+
+/// アルバムを作成する（検索条件を指定した場合はスマートアルバム）
+#[tauri::command]
+pub fn create_album(app: AppHandle, request: AlbumRequest) -> std::result::Result<Album, String> {
+    let name = album_name(&request.name)?;
+    let (query, conditions_json) = smart_query(&request.query, &request.conditions)?;
+    let smart = query.is_some() || conditions_json.is_some();
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    let now = Utc::now().to_rfc3339();
+    conn.execute(
+        "INSERT INTO albums (name, smart, query, conditions_json, position, created_at, updated_at)
+         VALUES (?1, ?2, ?3, ?4, (SELECT coalesce(max(position) + 1, 0) FROM albums), ?5, ?5)",
+        params![name, smart, query, conditions_json, now],
+    )
+    .map_err(|e| e.to_string())?;
+    find_album(&conn, conn.last_insert_rowid())
+}
+
+/// アルバム名を変更する
+#[tauri::command]
+pub fn rename_album(
+    app: AppHandle,
+    album_id: i64,
+    name: String,
+) -> std::result::Result<(), String> {
+    let name = album_name(&name)?;
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    let updated = conn
+        .execute(
+            "UPDATE albums SET name = ?1, updated_at = ?2 WHERE id = ?3",
+            params![name, Utc::now().to_rfc3339(), album_id],
+        )
+        .map_err(|e| e.to_string())?;
+    if updated == 0 {
+        return Err("アルバムが見つかりません".to_string());
+    }
+    Ok(())
+}
+
+/// スマートアルバムの検索条件を変更する
+#[tauri::command]
+pub fn update_smart_album(
+    app: AppHandle,
+    album_id: i64,
+    query: Option<String>,
+    conditions: Option<Vec<HashMap<String, String>>>,
+) -> std::result::Result<(), String> {
+    let (query, conditions_json) = smart_query(&query, &conditions)?;
+    if query.is_none() && conditions_json.is_none() {
+        return Err("検索条件を指定してください".to_string());
+    }
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    let updated = conn
+        .execute(
+            "UPDATE albums SET query = ?1, conditions_json = ?2, updated_at = ?3
+             WHERE id = ?4 AND smart = 1",
+            params![query, conditions_json, Utc::now().to_rfc3339(), album_id],
+        )
+        .map_err(|e| e.to_string())?;
+    if updated == 0 {
+        return Err("スマートアルバムが見つかりません".to_string());
+    }
+    Ok(())
+}
+
+/// アルバムを削除する（画像ファイルやインデックスの画像は削除しない）
+#[tauri::command]
+pub fn delete_album(app: AppHandle, album_id: i64) -> std::result::Result<(), String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    let deleted = conn
+        .execute("DELETE FROM albums WHERE id = ?", [album_id])
+        .map_err(|e| e.to_string())?;
+    if deleted == 0 {
+        return Err("アルバムが見つかりません".to_string());
+    }
+    Ok(())
+}
+
+/// アルバムを指定した順に並べ替える
+#[tauri::command]
+pub fn reorder_albums(app: AppHandle, album_ids: Vec<i64>) -> std::result::Result<(), String> {
+    let mut conn = init_db(&app).map_err(|e| e.to_string())?;
+    let transaction = conn.transaction().map_err(|e| e.to_string())?;
+    {
+        let mut stmt = transaction
+            .prepare("UPDATE albums SET position = ?1 WHERE id = ?2")
+            .map_err(|e| e.to_string())?;
+        for (position, album_id) in album_ids.iter().enumerate() {
+            stmt.execute(params![position as i64, album_id])
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    transaction.commit().map_err(|e| e.to_string())
+}
+
+/// 手動アルバムの末尾に画像を追加し、追加した枚数を返す（追加済みの画像は無視する）
+#[tauri::command]
+pub fn add_album_images(
+    app: AppHandle,
+    album_id: i64,
+    file_paths: Vec<String>,
+) -> std::result::Result<usize, String> {
+    let mut conn = init_db(&app).map_err(|e| e.to_string())?;
+    add_images(&mut conn, album_id, &file_paths)
+}
+
+/// 手動アルバムから画像を外し、外した枚数を返す
+#[tauri::command]
+pub fn remove_album_images(
+    app: AppHandle,
+    album_id: i64,
+    file_paths: Vec<String>,
+) -> std::result::Result<usize, String> {
+    let mut conn = init_db(&app).map_err(|e| e.to_string())?;
+    let transaction = conn.transaction().map_err(|e| e.to_string())?;
+    let mut removed = 0;
+    {
+        let mut stmt = transaction
+            .prepare("DELETE FROM album_images WHERE album_id = ?1 AND file_path = ?2")
+            .map_err(|e| e.to_string())?;
+        for file_path in &file_paths {
+            removed += stmt
+                .execute(params![album_id, file_path])
+                .map_err(|e| e.to_string())?;
+        }
+    }
+    transaction.commit().map_err(|e| e.to_string())?;
+    Ok(removed)
+}
+
+/// 手動アルバムの画像を指定した順に並べ替える（指定しなかった画像はその後ろに元の順で並ぶ）
+#[tauri::command]
+pub fn reorder_album_images(
+    app: AppHandle,
+    album_id: i64,
+    file_paths: Vec<String>,
+) -> std::result::Result<(), String> {
+    let mut conn = init_db(&app).map_err(|e| e.to_string())?;
+    reorder_images(&mut conn, album_id, &file_paths).map_err(|e| e.to_string())
+}
+
+/// アルバムの画像を1ページ分返す
+///
+/// 手動アルバムは並べた順、スマートアルバムは保存した検索条件をその時点のインデックスで評価した結果を返す。
+#[tauri::command]
+pub fn get_album_images(
+    app: AppHandle,
+    album_id: i64,
+    cursor: Option<String>,
+    limit: Option<u32>,
+) -> std::result::Result<SearchPage, String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    album_page(&conn, album_id, cursor, limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{migrate, SQL_QUERIES};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    struct TestAlbums {
+        conn: Connection,
+        dir: PathBuf,
+    }
+
+    impl TestAlbums {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!(
+                "vrcxphotosearcher-albums-{}-{}",
+                name,
+                std::process::id()
+            ));
+            fs::create_dir_all(&dir).unwrap();
+            let mut conn = Connection::open_in_memory().unwrap();
+            migrate(&mut conn, SQL_QUERIES.migrations).unwrap();
+            conn.execute(
+                SQL_QUERIES.insert_folder,
+                params![dir.to_string_lossy(), "u"],
+            )
+            .unwrap();
+            TestAlbums { conn, dir }
+        }
+
+        /// 画像を登録し、そのパスを返す（`exists` が false の場合はファイルを作らない）
+        fn image(&self, name: &str, exists: bool) -> String {
+            let file_path = self.dir.join(name);
+            if exists {
+                fs::write(&file_path, b"").unwrap();
+            }
+            let file_path = file_path.to_string_lossy().to_string();
+            self.conn
+                .execute(
+                    "INSERT INTO images (folder_id, file_path, metadata_json, file_created_at, created_at, updated_at)
+                     VALUES (1, ?1, '{}', 't', 't', 't')",
+                    [&file_path],
+                )
+                .unwrap();
+            file_path
+        }
+
+        fn album(&self, smart: bool, query: Option<&str>) -> i64 {
+            self.conn
+                .execute(
+                    "INSERT INTO albums (name, smart, query, position, created_at, updated_at)
+                     VALUES ('album', ?1, ?2, 0, 't', 't')",
+                    params![smart, query],
+                )
+                .unwrap();
+            self.conn.last_insert_rowid()
+        }
+
+        fn positions(&self, album_id: i64) -> Vec<(String, i64)> {
+            self.conn
+                .prepare(
+                    "SELECT file_path, position FROM album_images
+                     WHERE album_id = ? ORDER BY position",
+                )
+                .unwrap()
+                .query_map([album_id], |row| Ok((row.get(0)?, row.get(1)?)))
+                .unwrap()
+                .map(|row| {
+                    let (file_path, position): (String, i64) = row.unwrap();
+                    (file_name(&file_path), position)
+                })
+                .collect()
+        }
+
+        /// アルバムを最後のページまで読み、ページごとのファイル名を返す
+        fn pages(&self, album_id: i64, limit: u32) -> Vec<Vec<String>> {
+            let mut pages = Vec::new();
+            let mut cursor = None;
+            loop {
+                let page = album_page(&self.conn, album_id, cursor, Some(limit)).unwrap();
+                pages.push(
+                    page.items
+                        .iter()
+                        .map(|(file_path, _, _)| file_name(file_path))
+                        .collect(),
+                );
+                match page.next_cursor {
+                    Some(next) => cursor = Some(next),
+                    None => return pages,
+                }
+            }
+        }
+    }
+
+    impl Drop for TestAlbums {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn file_name(file_path: &str) -> String {
+        Path::new(file_path)
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn appends_images_to_end_of_album() {
+        let mut albums = TestAlbums::new("add");
+        let a = albums.image("a.png", true);
+        let b = albums.image("b.png", true);
+        let c = albums.image("c.png", true);
+        let album_id = albums.album(false, None);
+
+        assert_eq!(
+            add_images(&mut albums.conn, album_id, &[a, b.clone()]).unwrap(),
+            2
+        );
+        // 追加済みの画像は無視し、新しい画像だけ末尾に並べる
+        assert_eq!(add_images(&mut albums.conn, album_id, &[b, c]).unwrap(), 1);
+        assert_eq!(
+            albums.positions(album_id),
+            [
+                ("a.png".to_string(), 0),
+                ("b.png".to_string(), 1),
+                ("c.png".to_string(), 2)
+            ]
+        );
+
+        let smart_id = albums.album(true, Some("path~a"));
+        let d = albums.image("d.png", true);
+        assert!(add_images(&mut albums.conn, smart_id, &[d]).is_err());
+    }
+
+    #[test]
+    fn reorders_images_and_keeps_the_rest_in_order() {
+        let mut albums = TestAlbums::new("reorder");
+        let paths: Vec<String> = ["a.png", "b.png", "c.png", "d.png"]
+            .iter()
+            .map(|name| albums.image(name, true))
+            .collect();
+        let album_id = albums.album(false, None);
+        add_images(&mut albums.conn, album_id, &paths).unwrap();
+
+        reorder_images(
+            &mut albums.conn,
+            album_id,
+            &[paths[2].clone(), paths[0].clone()],
+        )
+        .unwrap();
+        let order: Vec<String> = albums
+            .positions(album_id)
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(order, ["c.png", "a.png", "b.png", "d.png"]);
+        assert_eq!(albums.pages(album_id, 10), [order]);
+    }
+
+    #[test]
+    fn pages_through_album_past_missing_files() {
+        let mut albums = TestAlbums::new("pages");
+        let paths: Vec<String> = [
+            ("a.png", true),
+            ("b.png", false),
+            ("c.png", true),
+            ("d.png", false),
+            ("e.png", true),
+            ("f.png", true),
+        ]
+        .iter()
+        .map(|(name, exists)| albums.image(name, *exists))
+        .collect();
+        let album_id = albums.album(false, None);
+        add_images(&mut albums.conn, album_id, &paths).unwrap();
+
+        // 削除されたファイルを除いても、最後以外のページは件数どおりに埋まる
+        assert_eq!(
+            albums.pages(album_id, 2),
+            [vec!["a.png", "c.png"], vec!["e.png", "f.png"]]
+        );
+        assert_eq!(
+            albums.pages(album_id, 3),
+            [vec!["a.png", "c.png", "e.png"], vec!["f.png"]]
+        );
+        assert!(album_page(&albums.conn, album_id, Some("invalid".to_string()), None).is_err());
+    }
+
+    #[test]
+    fn evaluates_smart_album_against_current_index() {
+        let albums = TestAlbums::new("smart");
+        albums.image("sunset-1.png", true);
+        albums.image("night.png", true);
+        let album_id = albums.album(true, Some("path~sunset"));
+        assert_eq!(albums.pages(album_id, 10), [vec!["sunset-1.png"]]);
+
+        // 後から登録された画像も含める
+        albums.image("sunset-2.png", true);
+        assert_eq!(
+            albums.pages(album_id, 10),
+            [vec!["sunset-1.png", "sunset-2.png"]]
+        );
+    }
+
+    #[test]
+    fn rejects_smart_album_without_conditions() {
+        let empty_value = HashMap::from([
+            ("field".to_string(), "world".to_string()),
+            ("value".to_string(), String::new()),
+        ]);
+        assert!(smart_query(&None, &Some(vec![])).is_err());
+        assert!(smart_query(&None, &Some(vec![empty_value])).is_err());
+        // 条件を指定しない場合は手動アルバム
+        assert_eq!(smart_query(&None, &None), Ok((None, None)));
+    }
+}
@@ -0,0 +1,312 @@
+use super::init_db;
+use crate::model::annotation::ImageAnnotations;
+use crate::model::search::FacetCount;
+use chrono::Utc;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use tauri::AppHandle;
+
+/// 前後の空白を除いた空でないタグ
+fn normalize_tags(tags: &[String]) -> Vec<&str> {
+    tags.iter()
+        .map(|tag| tag.trim())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+/// 画像にタグを付け、新しく付いたタグの数を返す
+fn add_tags(conn: &Connection, file_paths: &[String], tags: &[String]) -> Result<usize> {
+    let tags = normalize_tags(tags);
+    let now = Utc::now().to_rfc3339();
+    let tx = conn.unchecked_transaction()?;
+    let mut added = 0;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT OR IGNORE INTO image_tags (file_path, tag, created_at) VALUES (?1, ?2, ?3)",
+        )?;
+        for file_path in file_paths {
+            for tag in &tags {
+                added += stmt.execute(params![file_path, tag, now])?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(added)
+}
+
+/// 画像からタグを外し、外したタグの数を返す
+fn remove_tags(conn: &Connection, file_paths: &[String], tags: &[String]) -> Result<usize> {
+    let tags = normalize_tags(tags);
+    let tx = conn.unchecked_transaction()?;
+    let mut removed = 0;
+    {
+        let mut stmt = tx.prepare("DELETE FROM image_tags WHERE file_path = ?1 AND tag = ?2")?;
+        for file_path in file_paths {
+            for tag in &tags {
+                removed += stmt.execute(params![file_path, tag])?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(removed)
+}
+
+/// お気に入り・評価の列をまとめて更新する（`column` は呼び出し側で固定の列名）
+fn set_annotation(
+    conn: &Connection,
+    file_paths: &[String],
+    column: &str,
+    value: Option<i64>,
+) -> Result<usize> {
+    let now = Utc::now().to_rfc3339();
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare(&format!(
+            "INSERT INTO image_annotations (file_path, {c}, updated_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT (file_path) DO UPDATE SET {c} = excluded.{c}, updated_at = excluded.updated_at",
+            c = column
+        ))?;
+        for file_path in file_paths {
+            stmt.execute(params![file_path, value, now])?;
+        }
+    }
+    tx.commit()?;
+    Ok(file_paths.len())
+}
+
+fn annotations(conn: &Connection, file_path: &str) -> Result<ImageAnnotations> {
+    let tags = conn
+        .prepare("SELECT tag FROM image_tags WHERE file_path = ? ORDER BY tag")?
+        .query_map([file_path], |row| row.get(0))?
+        .collect::<Result<Vec<String>>>()?;
+    let (favorite, rating) = conn
+        .query_row(
+            "SELECT favorite, rating FROM image_annotations WHERE file_path = ?",
+            [file_path],
+            |row| Ok((row.get::<_, bool>(0)?, row.get::<_, Option<u8>>(1)?)),
+        )
+        .optional()?
+        .unwrap_or_default();
+    Ok(ImageAnnotations {
+        tags,
+        favorite,
+        rating,
+    })
+}
+
+/// 画像にタグを付ける（複数の画像・タグをまとめて指定できる）
+#[tauri::command]
+pub fn add_image_tags(
+    app: AppHandle,
+    file_paths: Vec<String>,
+    tags: Vec<String>,
+) -> std::result::Result<usize, String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    add_tags(&conn, &file_paths, &tags).map_err(|e| e.to_string())
+}
+
+/// 画像からタグを外す
+#[tauri::command]
+pub fn remove_image_tags(
+    app: AppHandle,
+    file_paths: Vec<String>,
+    tags: Vec<String>,
+) -> std::result::Result<usize, String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    remove_tags(&conn, &file_paths, &tags).map_err(|e| e.to_string())
+}
+
+/// 画像をお気に入りに追加・解除する
+#[tauri::command]
+pub fn set_image_favorite(
+    app: AppHandle,
+    file_paths: Vec<String>,
+    favorite: bool,
+) -> std::result::Result<usize, String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    set_annotation(&conn, &file_paths, "favorite", Some(favorite as i64)).map_err(|e| e.to_string())
+}
+
+/// 画像の評価（1〜5）を設定する（`None` で評価を消す）
+#[tauri::command]
+pub fn set_image_rating(
+    app: AppHandle,
+    file_paths: Vec<String>,
+    rating: Option<u8>,
+) -> std::result::Result<usize, String> {
+    if rating.is_some_and(|rating| !(1..=5).contains(&rating)) {
+        return Err("評価は1〜5で指定してください".to_string());
+    }
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    set_annotation(&conn, &file_paths, "rating", rating.map(i64::from)).map_err(|e| e.to_string())
+}
+
+/// 画像のタグ・お気に入り・評価を返す
+#[tauri::command]
+pub fn get_image_annotations(
+    app: AppHandle,
+    file_path: String,
+) -> std::result::Result<ImageAnnotations, String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    annotations(&conn, &file_path).map_err(|e| e.to_string())
+}
+
+/// 登録されている画像に使われているタグと、その枚数
+///
+/// 注釈はファイルパスで残しているため、インデックスから外れた画像のタグは数えない。
+fn tag_counts(conn: &Connection) -> Result<Vec<FacetCount>> {
+    conn.prepare(
+        "SELECT image_tags.tag, image_tags.tag, count(*) AS n FROM image_tags
+         JOIN images ON images.file_path = image_tags.file_path
+         GROUP BY image_tags.tag ORDER BY n DESC, image_tags.tag",
+    )?
+    .query_map([], |row| {
+        Ok(FacetCount {
+            value: row.get(0)?,
+            label: row.get(1)?,
+            count: row.get(2)?,
+        })
+    })?
+    .collect()
+}
+
+/// 使われているタグと、そのタグが付いた画像の枚数を返す
+#[tauri::command]
+pub fn get_all_tags(app: AppHandle) -> std::result::Result<Vec<FacetCount>, String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    tag_counts(&conn).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{migrate, SQL_QUERIES};
+    use crate::metadata::{CaptureTimeSource, MetadataSource};
+
+    fn open_test_db() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, SQL_QUERIES.migrations).unwrap();
+        conn.execute(SQL_QUERIES.insert_folder, params!["/photos", "u"])
+            .unwrap();
+        conn
+    }
+
+    /// 登録・再スキャンと同じ upsert で画像を登録する
+    fn upsert_image(conn: &Connection, file_path: &str, updated_at: &str) {
+        conn.execute(
+            SQL_QUERIES.insert_image,
+            params![
+                file_path,
+                1,
+                1,
+                1,
+                None::<String>,
+                "2024-01-01T00:00:00+00:00",
+                "2024-01-01T00:00:00+00:00",
+                updated_at,
+                None::<String>,
+                CaptureTimeSource::Created.as_str(),
+                1,
+                None::<i64>,
+                None::<i64>,
+                None::<Vec<u8>>,
+                None::<i64>,
+                None::<i64>,
+                None::<i64>,
+                None::<i64>,
+                None::<i64>,
+                MetadataSource::inferred_json()
+            ],
+        )
+        .unwrap();
+    }
+
+    fn paths(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    fn tag_list(conn: &Connection) -> Vec<(String, i64)> {
+        tag_counts(conn)
+            .unwrap()
+            .into_iter()
+            .map(|tag| (tag.value, tag.count))
+            .collect()
+    }
+
+    #[test]
+    fn adds_and_removes_tags() {
+        let conn = open_test_db();
+        let images = paths(&["/photos/a.png", "/photos/b.png"]);
+        let tags = paths(&[" sunset ", "group", ""]);
+        assert_eq!(add_tags(&conn, &images, &tags).unwrap(), 4);
+        // 付いているタグは数えない
+        assert_eq!(add_tags(&conn, &images, &tags).unwrap(), 0);
+
+        assert_eq!(
+            remove_tags(&conn, &images[..1], &paths(&["group", "missing"])).unwrap(),
+            1
+        );
+        assert_eq!(
+            annotations(&conn, "/photos/a.png").unwrap().tags,
+            ["sunset"]
+        );
+        assert_eq!(
+            annotations(&conn, "/photos/b.png").unwrap().tags,
+            ["group", "sunset"]
+        );
+    }
+
+    #[test]
+    fn sets_favorite_and_rating() {
+        let conn = open_test_db();
+        let images = paths(&["/photos/a.png"]);
+        set_annotation(&conn, &images, "favorite", Some(1)).unwrap();
+        set_annotation(&conn, &images, "rating", Some(4)).unwrap();
+        let annotation = annotations(&conn, "/photos/a.png").unwrap();
+        assert!(annotation.favorite);
+        assert_eq!(annotation.rating, Some(4));
+
+        // 片方を変えてももう片方は残る
+        set_annotation(&conn, &images, "rating", None).unwrap();
+        let annotation = annotations(&conn, "/photos/a.png").unwrap();
+        assert!(annotation.favorite);
+        assert_eq!(annotation.rating, None);
+
+        let annotation = annotations(&conn, "/photos/b.png").unwrap();
+        assert!(!annotation.favorite);
+        assert!(annotation.tags.is_empty());
+    }
+
+    #[test]
+    fn keeps_annotations_across_rescan() {
+        let conn = open_test_db();
+        upsert_image(&conn, "/photos/a.png", "2024-01-01T00:00:00+00:00");
+        let images = paths(&["/photos/a.png"]);
+        add_tags(&conn, &images, &paths(&["sunset"])).unwrap();
+        set_annotation(&conn, &images, "favorite", Some(1)).unwrap();
+        set_annotation(&conn, &images, "rating", Some(5)).unwrap();
+
+        // 再スキャンで同じパスの画像が更新される
+        upsert_image(&conn, "/photos/a.png", "2024-02-01T00:00:00+00:00");
+        let annotation = annotations(&conn, "/photos/a.png").unwrap();
+        assert_eq!(annotation.tags, ["sunset"]);
+        assert!(annotation.favorite);
+        assert_eq!(annotation.rating, Some(5));
+        assert_eq!(tag_list(&conn), [("sunset".to_string(), 1)]);
+    }
+
+    #[test]
+    fn counts_tags_of_indexed_images_only() {
+        let conn = open_test_db();
+        upsert_image(&conn, "/photos/a.png", "2024-01-01T00:00:00+00:00");
+        add_tags(
+            &conn,
+            &paths(&["/photos/a.png", "/photos/removed.png"]),
+            &paths(&["sunset", "old"]),
+        )
+        .unwrap();
+        assert_eq!(
+            tag_list(&conn),
+            [("old".to_string(), 1), ("sunset".to_string(), 1)]
+        );
+    }
+}
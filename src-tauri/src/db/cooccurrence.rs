@@ -0,0 +1,280 @@
+use super::{hit_set, init_db};
+use crate::model::search::{CooccurrenceRequest, FacetCount, GraphEdge, PlayerGraph, PlayerPair};
+use crate::search::{parse_date, Expr, Field, Op, QueryError};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection, Result};
+use std::collections::HashSet;
+use tauri::AppHandle;
+
+/// 組み合わせの最大件数の既定値
+const DEFAULT_PAIR_LIMIT: u32 = 100;
+
+/// 写真ごとのプレイヤー（IDの無い古いデータは表示名でまとめる）
+const IMAGE_PLAYERS: &str = "SELECT DISTINCT image_players.image_id,
+           coalesce(image_players.user_id, image_players.display_name) AS player
+    FROM hits JOIN image_players ON image_players.image_id = hits.id";
+
+/// プレイヤーの表示名（ユーザーIDなら最新の表示名、そうでなければそのまま）
+fn label(column: &str) -> String {
+    format!(
+        "coalesce((SELECT display_name FROM players WHERE user_id = {c}), {c})",
+        c = column
+    )
+}
+
+/// ワールド・撮影日時の条件を構文木にする
+fn filter_of(request: &CooccurrenceRequest) -> std::result::Result<Option<Expr>, QueryError> {
+    let mut terms = Vec::new();
+    if let Some(world) = request.world.as_deref().filter(|w| !w.is_empty()) {
+        terms.push(Expr::term(Field::World, Op::Eq, world));
+    }
+    for (value, op) in [(&request.after, Op::Ge), (&request.before, Op::Lt)] {
+        if let Some(value) = value.as_deref().filter(|v| !v.is_empty()) {
+            let time = parse_date(value).ok_or_else(|| QueryError {
+                message: "日時として解釈できません".to_string(),
+                position: None,
+                token: Some(value.to_string()),
+            })?;
+            terms.push(Expr::term(Field::CapturedAt, op, time.to_rfc3339()));
+        }
+    }
+    Ok(terms.into_iter().reduce(Expr::and))
+}
+
+/// 一緒に写っているプレイヤーの組み合わせを枚数の多い順に返す
+///
+/// `player` を指定した場合は、そのプレイヤー（表示名で指定した場合は同じユーザーIDの写真も含む）と
+/// 一緒に写っている相手を返す。指定しない場合は同じ組み合わせを1度だけ返す。
+fn player_pairs(
+    conn: &Connection,
+    filter: Option<&Expr>,
+    player: Option<&str>,
+    min_count: u32,
+    limit: u32,
+) -> Result<Vec<PlayerPair>> {
+    let (hits, params) = hit_set(filter);
+    let mut params: Vec<SqlValue> = params.into_iter().map(SqlValue::Text).collect();
+
+    let condition = match player {
+        Some(player) => {
+            params.push(SqlValue::Text(player.to_string()));
+            params.push(SqlValue::Text(player.to_string()));
+            "a.player != b.player AND (a.player = ? OR a.player IN \
+             (SELECT user_id FROM image_players WHERE user_id IS NOT NULL AND display_name = ?))"
+        }
+        None => "a.player < b.player",
+    };
+    params.push(SqlValue::Integer(min_count as i64));
+    params.push(SqlValue::Integer(limit as i64));
+
+    let query = format!(
+        "WITH hits AS ({}), ip AS ({})
+         SELECT player, {}, other, {}, n
+         FROM (SELECT a.player AS player, b.player AS other, count(*) AS n
+               FROM ip AS a JOIN ip AS b ON b.image_id = a.image_id
+               WHERE {}
+               GROUP BY a.player, b.player HAVING n >= ?)
+         ORDER BY n DESC, player, other LIMIT ?",
+        hits,
+        IMAGE_PLAYERS,
+        label("player"),
+        label("other"),
+        condition
+    );
+    conn.prepare(&query)?
+        .query_map(params_from_iter(params), |row| {
+            Ok(PlayerPair {
+                player: row.get(0)?,
+                player_label: row.get(1)?,
+                other: row.get(2)?,
+                other_label: row.get(3)?,
+                count: row.get(4)?,
+            })
+        })?
+        .collect()
+}
+
+/// 組み合わせを辺、辺に含まれるプレイヤーを頂点とするグラフを作る
+fn player_graph(
+    conn: &Connection,
+    filter: Option<&Expr>,
+    min_count: u32,
+    limit: u32,
+) -> Result<PlayerGraph> {
+    let pairs = player_pairs(conn, filter, None, min_count, limit)?;
+    let members: HashSet<&str> = pairs
+        .iter()
+        .flat_map(|pair| [pair.player.as_str(), pair.other.as_str()])
+        .collect();
+
+    // 頂点の重みはそのプレイヤーが写っている写真の枚数
+    let (hits, params) = hit_set(filter);
+    let query = format!(
+        "WITH hits AS ({}), ip AS ({})
+         SELECT player, {}, count(*) AS n FROM ip GROUP BY player ORDER BY n DESC, player",
+        hits,
+        IMAGE_PLAYERS,
+        label("player")
+    );
+    let nodes = conn
+        .prepare(&query)?
+        .query_map(params_from_iter(params.iter()), |row| {
+            Ok(FacetCount {
+                value: row.get(0)?,
+                label: row.get(1)?,
+                count: row.get(2)?,
+            })
+        })?
+        .filter(|node| {
+            node.as_ref()
+                .map_or(true, |node| members.contains(node.value.as_str()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let edges = pairs
+        .into_iter()
+        .map(|pair| GraphEdge {
+            source: pair.player,
+            target: pair.other,
+            weight: pair.count,
+        })
+        .collect();
+    Ok(PlayerGraph { nodes, edges })
+}
+
+/// 一緒に写っているプレイヤーの組み合わせと枚数を返す（ワールド・撮影日時で絞り込める）
+#[tauri::command]
+pub fn get_player_cooccurrence(
+    app: AppHandle,
+    request: CooccurrenceRequest,
+) -> std::result::Result<Vec<PlayerPair>, QueryError> {
+    let filter = filter_of(&request)?;
+    let conn = init_db(&app)?;
+    let player = request.player.as_deref().filter(|p| !p.is_empty());
+    Ok(player_pairs(
+        &conn,
+        filter.as_ref(),
+        player,
+        request.min_count.unwrap_or(1),
+        request.limit.unwrap_or(DEFAULT_PAIR_LIMIT),
+    )?)
+}
+
+/// プレイヤーの関係グラフ（頂点 = プレイヤー、辺の重み = 一緒に写っている写真の枚数）を返す
+#[tauri::command]
+pub fn get_player_graph(
+    app: AppHandle,
+    request: CooccurrenceRequest,
+) -> std::result::Result<PlayerGraph, QueryError> {
+    let filter = filter_of(&request)?;
+    let conn = init_db(&app)?;
+    Ok(player_graph(
+        &conn,
+        filter.as_ref(),
+        request.min_count.unwrap_or(1),
+        request.limit.unwrap_or(DEFAULT_PAIR_LIMIT),
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{migrate, SQL_QUERIES};
+
+    fn open_test_db() -> Connection {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, SQL_QUERIES.migrations).unwrap();
+        conn.execute(
+            "INSERT INTO search_folders (path, uuid) VALUES ('/photos', 'uuid-1')",
+            [],
+        )
+        .unwrap();
+        for (name, players) in [
+            (
+                "a",
+                r#"[{"id":"usr_1","displayName":"One"},{"id":"usr_2","displayName":"Two"},{"id":"","displayName":"Bob"}]"#,
+            ),
+            (
+                "b",
+                r#"[{"id":"usr_1","displayName":"One"},{"id":"usr_2","displayName":"Two"}]"#,
+            ),
+            // usr_1 が表示名を変更した後の写真
+            (
+                "c",
+                r#"[{"id":"usr_1","displayName":"Uno"},{"id":"usr_3","displayName":"Three"}]"#,
+            ),
+            (
+                "d",
+                r#"[{"id":"","displayName":"Bob"},{"id":"usr_3","displayName":"Three"}]"#,
+            ),
+        ] {
+            conn.execute(
+                "INSERT INTO images (folder_id, file_path, metadata_json, file_created_at, created_at, updated_at)
+                 VALUES (1, ?1, ?2, 't', 't', 't')",
+                [format!("/photos/{}.png", name), format!(r#"{{"players":{}}}"#, players)],
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    fn counts(pairs: &[PlayerPair]) -> Vec<(&str, &str, i64)> {
+        pairs
+            .iter()
+            .map(|pair| (pair.player.as_str(), pair.other.as_str(), pair.count))
+            .collect()
+    }
+
+    #[test]
+    fn counts_each_pair_once() {
+        let conn = open_test_db();
+        let pairs = player_pairs(&conn, None, None, 1, 100).unwrap();
+        assert_eq!(
+            counts(&pairs),
+            [
+                ("usr_1", "usr_2", 2),
+                ("Bob", "usr_1", 1),
+                ("Bob", "usr_2", 1),
+                ("Bob", "usr_3", 1),
+                ("usr_1", "usr_3", 1),
+            ]
+        );
+        // ユーザーIDのあるプレイヤーは最新の表示名で表示する
+        assert_eq!(pairs[0].player_label, "Uno");
+
+        let pairs = player_pairs(&conn, None, None, 2, 100).unwrap();
+        assert_eq!(counts(&pairs), [("usr_1", "usr_2", 2)]);
+    }
+
+    #[test]
+    fn counts_partners_of_player_across_renames() {
+        let conn = open_test_db();
+        let pairs = player_pairs(&conn, None, Some("One"), 1, 100).unwrap();
+        assert_eq!(
+            counts(&pairs),
+            [
+                ("usr_1", "usr_2", 2),
+                ("usr_1", "Bob", 1),
+                ("usr_1", "usr_3", 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn builds_graph_from_pairs() {
+        let conn = open_test_db();
+        let graph = player_graph(&conn, None, 2, 100).unwrap();
+        let nodes: Vec<(&str, i64)> = graph
+            .nodes
+            .iter()
+            .map(|node| (node.value.as_str(), node.count))
+            .collect();
+        assert_eq!(nodes, [("usr_1", 3), ("usr_2", 2)]);
+        let edges: Vec<(&str, &str, i64)> = graph
+            .edges
+            .iter()
+            .map(|edge| (edge.source.as_str(), edge.target.as_str(), edge.weight))
+            .collect();
+        assert_eq!(edges, [("usr_1", "usr_2", 2)]);
+    }
+}
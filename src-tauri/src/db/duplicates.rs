@@ -0,0 +1,258 @@
+use super::init_db;
+use crate::image_hash::hamming_distance;
+use crate::metadata::MetadataSource;
+use crate::model::duplicate::{DuplicateGroup, DuplicateImage};
+use rusqlite::{Connection, Result};
+use std::collections::HashMap;
+use tauri::AppHandle;
+
+/// 重複とみなす知覚ハッシュの距離の既定値
+const DEFAULT_MAX_DISTANCE: u32 = 6;
+/// 候補を絞り込むためにハッシュを分割する数
+///
+/// 距離が `BANDS - 1` 以下のハッシュは、鳩の巣原理でいずれかの区間が一致する。
+const BANDS: u32 = 8;
+
+struct Candidate {
+    image: DuplicateImage,
+    hash: u64,
+}
+
+impl Candidate {
+    /// 残す画像の優先順位（解像度 → メタデータの有無 → ファイルサイズ）
+    fn rank(&self) -> (i64, bool, i64) {
+        (
+            self.image.width * self.image.height,
+            self.image.has_metadata,
+            self.image.file_size,
+        )
+    }
+}
+
+/// 素集合（Union-Find）
+struct DisjointSet {
+    parent: Vec<usize>,
+}
+
+impl DisjointSet {
+    fn new(size: usize) -> Self {
+        DisjointSet {
+            parent: (0..size).collect(),
+        }
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parent[i] != i {
+            self.parent[i] = self.parent[self.parent[i]];
+            i = self.parent[i];
+        }
+        i
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parent[b] = a;
+        }
+    }
+}
+
+fn candidates(conn: &Connection) -> Result<Vec<Candidate>> {
+    let inferred: Vec<&str> = MetadataSource::INFERRED
+        .iter()
+        .map(|source| source.as_str())
+        .collect();
+    conn.prepare(
+        "SELECT images.file_path, search_folders.uuid, coalesce(images.width, 0), coalesce(images.height, 0),
+                coalesce(images.file_size, 0), images.metadata_source, images.dhash
+         FROM images JOIN search_folders ON search_folders.id = images.folder_id
+         WHERE images.dhash IS NOT NULL",
+    )?
+    .query_map([], |row| {
+        let source: Option<String> = row.get(5)?;
+        let hash: i64 = row.get(6)?;
+        Ok(Candidate {
+            image: DuplicateImage {
+                file_path: row.get(0)?,
+                uuid: row.get(1)?,
+                width: row.get(2)?,
+                height: row.get(3)?,
+                file_size: row.get(4)?,
+                has_metadata: source.is_some_and(|source| !inferred.contains(&source.as_str())),
+                distance: 0,
+            },
+            hash: hash as u64,
+        })
+    })?
+    .collect()
+}
+
+/// 残す画像との知覚ハッシュの距離が `max_distance` 以下の画像をまとめる
+///
+/// 似た画像を連鎖的にたどったまとまり（AとB、BとCが近い）は、残す画像から遠い画像を
+/// 別のまとまりに分けるため、どの画像も残す画像との距離が `max_distance` を超えない。
+fn group_duplicates(candidates: Vec<Candidate>, max_distance: u32) -> Vec<DuplicateGroup> {
+    let band_bits = 64 / BANDS;
+    let mut set = DisjointSet::new(candidates.len());
+
+    // 同じ区間の値を持つ画像だけを比較する
+    for band in 0..BANDS {
+        let mut buckets: HashMap<u64, Vec<usize>> = HashMap::new();
+        for (i, candidate) in candidates.iter().enumerate() {
+            let key = (candidate.hash >> (band * band_bits)) & ((1 << band_bits) - 1);
+            buckets.entry(key).or_default().push(i);
+        }
+        for bucket in buckets.values().filter(|bucket| bucket.len() > 1) {
+            for (n, &a) in bucket.iter().enumerate() {
+                for &b in &bucket[n + 1..] {
+                    if hamming_distance(candidates[a].hash, candidates[b].hash) <= max_distance {
+                        set.union(a, b);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..candidates.len() {
+        members.entry(set.find(i)).or_default().push(i);
+    }
+    let mut candidates: Vec<Option<Candidate>> = candidates.into_iter().map(Some).collect();
+    let mut groups: Vec<DuplicateGroup> = Vec::new();
+    for indices in members.into_values().filter(|indices| indices.len() > 1) {
+        let mut rest: Vec<Candidate> = indices
+            .into_iter()
+            .filter_map(|i| candidates[i].take())
+            .collect();
+        rest.sort_by(|a, b| {
+            b.rank()
+                .cmp(&a.rank())
+                .then_with(|| a.image.file_path.cmp(&b.image.file_path))
+        });
+        // 残す画像に近い画像だけをまとめ、残りからまた残す画像を選ぶ
+        while rest.len() > 1 {
+            let keeper_hash = rest[0].hash;
+            let (group, far): (Vec<Candidate>, Vec<Candidate>) =
+                rest.into_iter().partition(|candidate| {
+                    hamming_distance(keeper_hash, candidate.hash) <= max_distance
+                });
+            rest = far;
+            if group.len() < 2 {
+                continue;
+            }
+            let images: Vec<DuplicateImage> = group
+                .into_iter()
+                .map(|candidate| DuplicateImage {
+                    distance: hamming_distance(keeper_hash, candidate.hash),
+                    ..candidate.image
+                })
+                .collect();
+            groups.push(DuplicateGroup {
+                keeper: images[0].file_path.clone(),
+                images,
+            });
+        }
+    }
+    // 画像の多いまとまりから
+    groups.sort_by(|a, b| {
+        b.images
+            .len()
+            .cmp(&a.images.len())
+            .then_with(|| a.keeper.cmp(&b.keeper))
+    });
+    groups
+}
+
+/// 同じ写真・ほぼ同じ写真のまとまりと、残すことを勧める画像を返す
+///
+/// `max_distance` は知覚ハッシュ（64ビット）の異なるビット数の上限（最大7）で、0 なら見た目が同じ画像だけをまとめる。
+/// 以前のバージョンで登録された画像は、起動時にハッシュを計算し終えるまで対象にならない。
+#[tauri::command]
+pub fn find_duplicate_images(
+    app: AppHandle,
+    max_distance: Option<u32>,
+) -> std::result::Result<Vec<DuplicateGroup>, String> {
+    let max_distance = max_distance.unwrap_or(DEFAULT_MAX_DISTANCE).min(BANDS - 1);
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    let candidates = candidates(&conn).map_err(|e| e.to_string())?;
+    Ok(group_duplicates(candidates, max_distance))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(file_path: &str, width: i64, has_metadata: bool, hash: u64) -> Candidate {
+        Candidate {
+            image: DuplicateImage {
+                file_path: file_path.to_string(),
+                uuid: "u".to_string(),
+                width,
+                height: width * 9 / 16,
+                file_size: 0,
+                has_metadata,
+                distance: 0,
+            },
+            hash,
+        }
+    }
+
+    #[test]
+    fn keeps_largest_image_with_metadata() {
+        let hash = 0x0123_4567_89ab_cdef;
+        let groups = group_duplicates(
+            vec![
+                candidate("copy.png", 1920, false, hash),
+                candidate("original.png", 1920, true, hash ^ 0b11),
+                candidate("small.png", 1280, true, hash ^ 0b1),
+                candidate("other.png", 1920, true, !hash),
+            ],
+            4,
+        );
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].keeper, "original.png");
+        let distances: Vec<(&str, u32)> = groups[0]
+            .images
+            .iter()
+            .map(|image| (image.file_path.as_str(), image.distance))
+            .collect();
+        assert_eq!(
+            distances,
+            vec![("original.png", 0), ("copy.png", 2), ("small.png", 1)]
+        );
+    }
+
+    #[test]
+    fn splits_chains_far_from_keeper() {
+        // a〜b、b〜c はそれぞれ距離4だが、a〜c は距離8
+        let a = 0u64;
+        let b = 0xf;
+        let c = 0xff;
+        let groups = group_duplicates(
+            vec![
+                candidate("a.png", 1920, true, a),
+                candidate("b.png", 1280, true, b),
+                candidate("c.png", 1920, false, c),
+                candidate("d.png", 1280, false, c ^ 0b1),
+            ],
+            4,
+        );
+        let groups: Vec<Vec<(&str, u32)>> = groups
+            .iter()
+            .map(|group| {
+                group
+                    .images
+                    .iter()
+                    .map(|image| (image.file_path.as_str(), image.distance))
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            groups,
+            vec![
+                vec![("a.png", 0), ("b.png", 4)],
+                vec![("c.png", 0), ("d.png", 1)]
+            ]
+        );
+    }
+}
@@ -0,0 +1,145 @@
+use super::{hit_set, init_db, resolve_filter};
+use crate::model::search::{FacetCount, FacetRequest, SearchFacets};
+use crate::search::{Expr, QueryError};
+use rusqlite::{params_from_iter, Connection, Result};
+use tauri::AppHandle;
+
+/// ファセットごとの最大件数の既定値
+const DEFAULT_FACET_LIMIT: u32 = 50;
+
+/// ワールド（件数の多い順）
+const WORLDS: &str = "SELECT hits.world_id, coalesce(worlds.name, ''), count(*) AS n
+    FROM hits LEFT JOIN worlds ON worlds.world_id = hits.world_id
+    WHERE hits.world_id IS NOT NULL
+    GROUP BY hits.world_id ORDER BY n DESC, 2";
+
+/// 同じインスタンスにいたプレイヤー（IDの無い古いデータは表示名でまとめる）
+const PLAYERS: &str = "SELECT coalesce(image_players.user_id, image_players.display_name),
+           coalesce(max(players.display_name), max(image_players.display_name)),
+           count(DISTINCT image_players.image_id) AS n
+    FROM hits
+    JOIN image_players ON image_players.image_id = hits.id
+    LEFT JOIN players ON players.user_id = image_players.user_id
+    GROUP BY coalesce(image_players.user_id, image_players.display_name) ORDER BY n DESC, 2";
+
+/// 撮影者
+const AUTHORS: &str = "SELECT hits.author_id, coalesce(players.display_name, ''), count(*) AS n
+    FROM hits LEFT JOIN players ON players.user_id = hits.author_id
+    WHERE hits.author_id IS NOT NULL
+    GROUP BY hits.author_id ORDER BY n DESC, 2";
+
+/// インスタンスの公開範囲
+const INSTANCE_TYPES: &str = "SELECT instances.access_type, instances.access_type, count(*) AS n
+    FROM hits JOIN instances ON instances.instance_id = hits.instance_id
+    WHERE instances.access_type IS NOT NULL
+    GROUP BY instances.access_type ORDER BY n DESC, 2";
+
+/// インスタンスのリージョン
+const REGIONS: &str = "SELECT instances.region, instances.region, count(*) AS n
+    FROM hits JOIN instances ON instances.instance_id = hits.instance_id
+    WHERE instances.region IS NOT NULL
+    GROUP BY instances.region ORDER BY n DESC, 2";
+
+/// 画像に付けたタグ
+const TAGS: &str = "SELECT image_tags.tag, image_tags.tag, count(*) AS n
+    FROM hits JOIN image_tags ON image_tags.file_path = hits.file_path
+    GROUP BY image_tags.tag ORDER BY n DESC, 2";
+
+/// 撮影月（ローカル時刻、新しい順）
+const MONTHS: &str = "SELECT month, month, count(*)
+    FROM (SELECT strftime('%Y-%m', hits.file_created_at, 'localtime') AS month FROM hits)
+    WHERE month IS NOT NULL
+    GROUP BY month ORDER BY month DESC";
+
+/// 検索結果（`hits`）に対して集計クエリを実行する
+fn facet(
+    conn: &Connection,
+    hits: &str,
+    params: &[String],
+    select: &str,
+    limit: i64,
+) -> Result<Vec<FacetCount>> {
+    let query = format!("WITH hits AS ({}) {} LIMIT {}", hits, select, limit);
+    conn.prepare(&query)?
+        .query_map(params_from_iter(params.iter()), |row| {
+            Ok(FacetCount {
+                value: row.get(0)?,
+                label: row.get(1)?,
+                count: row.get(2)?,
+            })
+        })?
+        .collect()
+}
+
+fn search_facets(conn: &Connection, filter: Option<&Expr>, limit: u32) -> Result<SearchFacets> {
+    let (hits, params) = hit_set(filter);
+    let limit = limit as i64;
+
+    Ok(SearchFacets {
+        worlds: facet(conn, &hits, &params, WORLDS, limit)?,
+        players: facet(conn, &hits, &params, PLAYERS, limit)?,
+        authors: facet(conn, &hits, &params, AUTHORS, limit)?,
+        instance_types: facet(conn, &hits, &params, INSTANCE_TYPES, limit)?,
+        regions: facet(conn, &hits, &params, REGIONS, limit)?,
+        tags: facet(conn, &hits, &params, TAGS, limit)?,
+        months: facet(conn, &hits, &params, MONTHS, -1)?,
+    })
+}
+
+/// 検索結果に含まれるワールド・プレイヤー・撮影者・インスタンスの種類・リージョン・タグ・撮影月と、その枚数を返す
+#[tauri::command]
+pub fn get_search_facets(
+    app: AppHandle,
+    request: FacetRequest,
+) -> std::result::Result<SearchFacets, QueryError> {
+    let filter = resolve_filter(&request.query, &request.conditions)?;
+    let conn = init_db(&app)?;
+    let limit = request.limit.unwrap_or(DEFAULT_FACET_LIMIT);
+    Ok(search_facets(&conn, filter.as_ref(), limit)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{migrate, query_images, SQL_QUERIES};
+    use crate::search::parse;
+
+    #[test]
+    fn facet_totals_match_search_results() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, SQL_QUERIES.migrations).unwrap();
+        conn.execute_batch(
+            r#"INSERT INTO search_folders (path, uuid) VALUES ('/photos', 'uuid-1');
+               INSERT INTO images (folder_id, file_path, metadata_json, file_created_at, created_at, updated_at)
+               VALUES (1, '/photos/a.png',
+                       '{"world":{"id":"wrld_a","name":"Alpha"},"players":[{"id":"usr_1","displayName":"One"}]}',
+                       '2024-01-01T12:00:00+00:00', 't', 't'),
+                      (1, '/photos/b.png', '{"world":{"id":"wrld_a","name":"Alpha"},"players":[]}',
+                       '2024-02-01T12:00:00+00:00', 't', 't'),
+                      (1, '/photos/c.png', '{"world":{"id":"wrld_b","name":"Beta"},"players":[]}',
+                       '2024-02-02T12:00:00+00:00', 't', 't'),
+                      (1, '/photos/d.png', NULL, '2024-03-01T12:00:00+00:00', 't', 't'),
+                      (1, '/photos/e.png', 'lfs|broken', '2024-03-02T12:00:00+00:00', 't', 't');"#,
+        )
+        .unwrap();
+
+        // メタデータの無い画像は条件がある場合だけ除かれる
+        for (query, expected) in [
+            ("", 5),
+            ("world:Alpha", 2),
+            ("player:One", 1),
+            ("-world:Alpha", 1),
+        ] {
+            let filter = parse(query).unwrap();
+            let results = query_images(&conn, filter.as_ref()).unwrap();
+            assert_eq!(results.len(), expected, "{}", query);
+            let facets = search_facets(&conn, filter.as_ref(), DEFAULT_FACET_LIMIT).unwrap();
+            let months: i64 = facets.months.iter().map(|month| month.count).sum();
+            assert_eq!(months as usize, results.len(), "{}", query);
+        }
+
+        let facets = search_facets(&conn, parse("-world:Alpha").unwrap().as_ref(), 50).unwrap();
+        assert_eq!(facets.worlds.len(), 1);
+        assert_eq!(facets.worlds[0].label, "Beta");
+    }
+}
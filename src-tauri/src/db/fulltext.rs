@@ -0,0 +1,244 @@
+use super::{init_db, MAX_PAGE_SIZE};
+use crate::model::search::FullTextHit;
+use crate::search::escape_like;
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params_from_iter, Connection, Result};
+use std::path::Path;
+use tauri::AppHandle;
+
+/// 返す件数の既定値
+const DEFAULT_FULLTEXT_LIMIT: u32 = 100;
+/// trigram トークナイザーで索引を使える語の最小の長さ（文字数）
+const TRIGRAM_LENGTH: usize = 3;
+/// 全文検索の列（`images_fts` の列順）
+const COLUMNS: [&str; 5] = ["world", "author", "players", "file_name", "tags"];
+
+/// HTMLエスケープし、語に一致した部分（大文字・小文字は区別しない）を `<mark>` で囲む
+fn highlight(text: &str, terms: &[&str]) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let lower: Vec<char> = chars.iter().map(|c| fold(*c)).collect();
+    let mut marked = vec![false; chars.len()];
+    for term in terms {
+        let term: Vec<char> = term.chars().map(fold).collect();
+        if term.is_empty() || term.len() > lower.len() {
+            continue;
+        }
+        for start in 0..=lower.len() - term.len() {
+            if lower[start..start + term.len()] == term[..] {
+                marked[start..start + term.len()].fill(true);
+            }
+        }
+    }
+
+    let mut html = String::new();
+    for (i, c) in chars.iter().enumerate() {
+        if marked[i] && (i == 0 || !marked[i - 1]) {
+            html.push_str("<mark>");
+        }
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            _ => html.push(*c),
+        }
+        if marked[i] && (i + 1 == chars.len() || !marked[i + 1]) {
+            html.push_str("</mark>");
+        }
+    }
+    html
+}
+
+fn fold(c: char) -> char {
+    c.to_lowercase().next().unwrap_or(c)
+}
+
+/// 語をすべて含む画像を一致度の高い順に返す
+///
+/// 3文字以上の語は FTS5 の索引で探して bm25 で順位を付け、
+/// 索引を使えない2文字以下の語（日本語の短い名前など）は各列の部分一致で絞り込む。
+/// 登録後に削除されたファイルは `limit` に数えずに除く。
+fn search_fulltext(conn: &Connection, text: &str, limit: u32) -> Result<Vec<FullTextHit>> {
+    let terms: Vec<&str> = text.split_whitespace().collect();
+    if terms.is_empty() {
+        return Ok(Vec::new());
+    }
+    let (long, short): (Vec<&str>, Vec<&str>) = terms
+        .iter()
+        .partition(|term| term.chars().count() >= TRIGRAM_LENGTH);
+
+    let mut conditions = Vec::new();
+    let mut params: Vec<SqlValue> = Vec::new();
+    if !long.is_empty() {
+        // 各語を "..." で囲み、FTS5の演算子として解釈されないようにする
+        let query = long
+            .iter()
+            .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" ");
+        conditions.push("images_fts MATCH ?".to_string());
+        params.push(SqlValue::Text(query));
+    }
+    for term in &short {
+        let like = COLUMNS
+            .iter()
+            .map(|column| format!("images_fts.{} LIKE ? ESCAPE '\\'", column))
+            .collect::<Vec<_>>()
+            .join(" OR ");
+        conditions.push(format!("({})", like));
+        params.extend(
+            COLUMNS
+                .iter()
+                .map(|_| SqlValue::Text(format!("%{}%", escape_like(term)))),
+        );
+    }
+    // ワールド名とタグの一致を重く見る
+    let rank = if long.is_empty() {
+        "0.0"
+    } else {
+        "bm25(images_fts, 2.0, 1.0, 1.0, 1.0, 2.0)"
+    };
+    let query = format!(
+        "SELECT images.file_path, images.id, search_folders.uuid, {rank},
+                images_fts.world, images_fts.author, images_fts.players, images_fts.file_name, images_fts.tags
+         FROM images_fts
+         JOIN images ON images.id = images_fts.rowid
+         JOIN search_folders ON search_folders.id = images.folder_id
+         WHERE {}
+         ORDER BY {rank}, images.file_created_at DESC",
+        conditions.join(" AND "),
+        rank = rank
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params_from_iter(params), |row| {
+        let rank: f64 = row.get(3)?;
+        let column =
+            |i: usize| -> Result<String> { Ok(highlight(&row.get::<_, String>(i)?, &terms)) };
+        Ok(FullTextHit {
+            file_path: row.get(0)?,
+            image_id: row.get(1)?,
+            uuid: row.get(2)?,
+            // bm25 は一致度が高いほど小さい（負の）値になる
+            score: -rank,
+            world: column(4)?,
+            author: column(5)?,
+            players: column(6)?,
+            file_name: column(7)?,
+            tags: column(8)?,
+        })
+    })?;
+
+    let mut hits = Vec::new();
+    for hit in rows {
+        let hit = hit?;
+        if !Path::new(&hit.file_path).exists() {
+            continue;
+        }
+        hits.push(hit);
+        if hits.len() >= limit as usize {
+            break;
+        }
+    }
+    Ok(hits)
+}
+
+/// ワールド名・撮影者・同席者・ファイル名・タグを全文検索する
+///
+/// 空白で区切った語をすべて含む画像を一致度の高い順に返す。
+#[tauri::command]
+pub fn search_images_fulltext(
+    app: AppHandle,
+    text: String,
+    limit: Option<u32>,
+) -> std::result::Result<Vec<FullTextHit>, String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    let limit = limit
+        .unwrap_or(DEFAULT_FULLTEXT_LIMIT)
+        .clamp(1, MAX_PAGE_SIZE);
+    search_fulltext(&conn, &text, limit).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migration::migrate;
+    use crate::db::query::Queries;
+
+    #[test]
+    fn highlights_and_escapes() {
+        assert_eq!(
+            highlight("Great <Pug> & pug", &["pug"]),
+            "Great &lt;<mark>Pug</mark>&gt; &amp; <mark>pug</mark>"
+        );
+        assert_eq!(
+            highlight("ねこ集会", &["ねこ", "集会"]),
+            "<mark>ねこ集会</mark>"
+        );
+    }
+
+    #[test]
+    fn finds_worlds_players_and_tags() {
+        let dir =
+            std::env::temp_dir().join(format!("vrcxphotosearcher-fulltext-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, Queries::load().migrations).unwrap();
+        conn.execute(
+            "INSERT INTO search_folders (path, uuid) VALUES (?, 'u')",
+            [dir.to_string_lossy()],
+        )
+        .unwrap();
+        for (name, metadata, exists) in [
+            (
+                "a.png",
+                r#"{"world":{"name":"ねこ集会所"},"players":[{"displayName":"Alice"}]}"#,
+                true,
+            ),
+            ("b.png", r#"{"world":{"name":"Great Pug"}}"#, true),
+            ("c.png", r#"{"world":{"name":"Great Pug 100%"}}"#, false),
+            ("d.png", r#"{"world":{"name":"Great Pug 100%"}}"#, true),
+        ] {
+            if exists {
+                std::fs::write(path(name), b"").unwrap();
+            }
+            conn.execute(
+                "INSERT INTO images (folder_id, file_path, metadata_json, file_created_at, created_at, updated_at)
+                 VALUES (1, ?1, ?2, 't', 't', 't')",
+                [path(name), metadata.to_string()],
+            )
+            .unwrap();
+        }
+        conn.execute(
+            "INSERT INTO image_tags (file_path, tag, created_at) VALUES (?, 'イベント', 't')",
+            [path("b.png")],
+        )
+        .unwrap();
+
+        let names = |text: &str, limit: u32| -> Vec<String> {
+            search_fulltext(&conn, text, limit)
+                .unwrap()
+                .into_iter()
+                .map(|hit| hit.file_path[dir.to_string_lossy().len() + 1..].to_string())
+                .collect()
+        };
+        assert_eq!(names("集会所", 10), ["a.png"]);
+        assert_eq!(names("ねこ ali", 10), ["a.png"]);
+        assert_eq!(names("ベント", 10), ["b.png"]);
+        // 短い語の % _ は文字どおりに比較する
+        assert_eq!(names("0%", 10), ["d.png"]);
+        assert!(names("_", 10).is_empty());
+        // 削除されたファイル（c.png）は件数に数えない
+        assert_eq!(names("great pug", 2), ["b.png", "d.png"]);
+
+        let hit = &search_fulltext(&conn, "ねこ ali", 10).unwrap()[0];
+        assert_eq!(hit.world, "<mark>ねこ</mark>集会所");
+        assert_eq!(hit.players, "<mark>Ali</mark>ce");
+
+        // タグを外すと全文検索からも消える
+        conn.execute("DELETE FROM image_tags", []).unwrap();
+        assert!(names("ベント", 10).is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
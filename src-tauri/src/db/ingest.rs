@@ -0,0 +1,647 @@
+use super::similar::descriptor_columns;
+use super::thumbnails::{encode_thumbnail, save_thumbnail};
+use super::SQL_QUERIES;
+use crate::fingerprint::{pixel_hash, quick_hash, quick_hash_file, FileStat};
+use crate::image_hash::dhash;
+use crate::metadata::{
+    extract_metadata, resolve_capture_time, CaptureTimeSource, ExtractedMetadata, MetadataSource,
+};
+use chrono::{DateTime, Utc};
+use image::GenericImageView;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use std::fs::{self, File};
+use std::io::{BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::sync_channel;
+use std::thread;
+
+/// 1つのトランザクションで登録する画像の数
+pub(crate) const WRITE_BATCH_SIZE: usize = 64;
+
+/// 登録のために解析した画像
+pub(crate) struct AnalyzedImage {
+    pub file_path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+    pub file_size: u64,
+    pub dhash: u64,
+    pub phash: i64,
+    pub color_histogram: Vec<u8>,
+    pub thumbnail: Vec<u8>,
+    pub metadata: Option<ExtractedMetadata>,
+    pub captured_at: DateTime<Utc>,
+    pub capture_time_source: CaptureTimeSource,
+    pub fingerprint: Fingerprint,
+}
+
+/// 変更の判定に使うファイルの指紋
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Fingerprint {
+    pub stat: FileStat,
+    pub quick_hash: u64,
+    pub pixel_hash: Option<u64>,
+}
+
+/// 登録済みの情報と比べたファイルの変化
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileChange {
+    /// 変わっていない（ファイルを開かずに判定した）
+    Unchanged,
+    /// 中身は同じで、更新日時やinodeだけが変わった（コピーや `touch` など）
+    Touched(Fingerprint),
+    /// 画素は同じで、メタデータのチャンクだけが書き換えられた
+    MetadataOnly(Fingerprint),
+    /// 未登録、または画素が変わった（解析し直す）
+    Modified,
+}
+
+/// 登録済みの画像の指紋（以前のバージョンで登録した画像は `quick_hash` などが `None`）
+struct StoredFingerprint {
+    file_size: i64,
+    mtime_ns: Option<i64>,
+    inode: Option<i64>,
+    device: Option<i64>,
+    quick_hash: Option<i64>,
+    pixel_hash: Option<i64>,
+    updated_at: String,
+    has_thumbnail: bool,
+}
+
+const STORED_FINGERPRINT_QUERY: &str = "SELECT images.file_size, images.mtime_ns, images.inode, images.device,
+            images.quick_hash, images.pixel_hash, images.updated_at, thumbnails.image_id IS NOT NULL
+     FROM images LEFT JOIN thumbnails ON thumbnails.image_id = images.id AND thumbnails.variant = 'small'
+     WHERE images.file_path = ?1";
+
+const UPDATE_FINGERPRINT: &str = "UPDATE images
+     SET file_size = ?2, mtime_ns = ?3, inode = ?4, device = ?5, quick_hash = ?6, pixel_hash = ?7
+     WHERE file_path = ?1";
+
+fn stored_fingerprint(conn: &Connection, file_path: &str) -> Result<Option<StoredFingerprint>> {
+    conn.prepare_cached(STORED_FINGERPRINT_QUERY)?
+        .query_row([file_path], |row| {
+            Ok(StoredFingerprint {
+                file_size: row.get(0)?,
+                mtime_ns: row.get(1)?,
+                inode: row.get(2)?,
+                device: row.get(3)?,
+                quick_hash: row.get(4)?,
+                pixel_hash: row.get(5)?,
+                updated_at: row.get(6)?,
+                has_thumbnail: row.get(7)?,
+            })
+        })
+        .optional()
+}
+
+/// ファイルが登録後にどう変わったかを判定する
+///
+/// サイズ・更新日時・inodeが記録と同じならファイルを開かずに変更なしとする。
+/// 違う場合は先頭と末尾の64KBのハッシュを比べ、それも違えばPNGのチャンクの見出しだけを読んで
+/// 画素が変わったかを調べる。いずれの場合も画像はデコードしない。
+pub(crate) fn detect_change(
+    conn: &Connection,
+    file_path: &Path,
+) -> std::result::Result<FileChange, String> {
+    let stat = FileStat::from_metadata(&fs::metadata(file_path).map_err(|e| e.to_string())?);
+    let Some(stored) =
+        stored_fingerprint(conn, &file_path.to_string_lossy()).map_err(|e| e.to_string())?
+    else {
+        return Ok(FileChange::Modified);
+    };
+    if !stored.has_thumbnail {
+        return Ok(FileChange::Modified);
+    }
+    let same_size = stored.file_size == stat.size as i64;
+    if same_size
+        && stored.mtime_ns == Some(stat.mtime_ns)
+        && stored.inode == stat.inode
+        && stored.device == stat.device
+    {
+        return Ok(FileChange::Unchanged);
+    }
+
+    let fingerprint = Fingerprint {
+        stat,
+        quick_hash: quick_hash_file(file_path).map_err(|e| e.to_string())?,
+        pixel_hash: File::open(file_path)
+            .ok()
+            .and_then(|file| pixel_hash(BufReader::new(file))),
+    };
+    let same_content = match stored.quick_hash {
+        Some(quick_hash) => quick_hash == fingerprint.quick_hash as i64,
+        // 指紋を記録する前に登録した画像は、登録した後に更新されていなければ同じとみなす
+        None => stored
+            .updated_at
+            .parse::<DateTime<Utc>>()
+            .is_ok_and(|indexed_at| DateTime::from_timestamp_nanos(stat.mtime_ns) <= indexed_at),
+    };
+    // 先頭と末尾が同じでも途中の画素が書き換えられている場合があるため、画素のハッシュも比べる
+    let same_pixels = stored.pixel_hash == fingerprint.pixel_hash.map(|hash| hash as i64);
+    // 画素のハッシュを記録する前に登録した画像は、サイズと登録日時が合えば同じとみなして指紋を記録する
+    let unknown_pixels = stored.pixel_hash.is_none();
+    if same_size && same_content && (same_pixels || unknown_pixels) {
+        return Ok(FileChange::Touched(fingerprint));
+    }
+    if fingerprint.pixel_hash.is_some() && same_pixels {
+        return Ok(FileChange::MetadataOnly(fingerprint));
+    }
+    Ok(FileChange::Modified)
+}
+
+/// 画像を解析し直さずに済む変化を反映する（解析し直す必要がある場合は `false` を返す）
+///
+/// メタデータだけが変わった場合は、サムネイル・知覚ハッシュ・類似画像の特徴はそのまま残す。
+pub(crate) fn apply_change(
+    conn: &Connection,
+    file_path: &Path,
+    change: &FileChange,
+) -> std::result::Result<bool, String> {
+    match change {
+        FileChange::Unchanged => Ok(true),
+        FileChange::Touched(fingerprint) => {
+            update_fingerprint(conn, file_path, fingerprint).map_err(|e| e.to_string())?;
+            Ok(true)
+        }
+        FileChange::MetadataOnly(fingerprint) => {
+            update_metadata(conn, file_path, fingerprint)?;
+            Ok(true)
+        }
+        FileChange::Modified => Ok(false),
+    }
+}
+
+fn update_fingerprint(
+    conn: &Connection,
+    file_path: &Path,
+    fingerprint: &Fingerprint,
+) -> Result<()> {
+    conn.prepare_cached(UPDATE_FINGERPRINT)?.execute(params![
+        file_path.to_string_lossy(),
+        fingerprint.stat.size as i64,
+        fingerprint.stat.mtime_ns,
+        fingerprint.stat.inode,
+        fingerprint.stat.device,
+        fingerprint.quick_hash as i64,
+        fingerprint.pixel_hash.map(|hash| hash as i64),
+    ])?;
+    Ok(())
+}
+
+/// メタデータのチャンクだけを読み直して登録する
+fn update_metadata(
+    conn: &Connection,
+    file_path: &Path,
+    fingerprint: &Fingerprint,
+) -> std::result::Result<(), String> {
+    let data = fs::read(file_path).map_err(|e| e.to_string())?;
+    let file_metadata = fs::metadata(file_path).map_err(|e| e.to_string())?;
+    let metadata = extract_metadata(&data).unwrap_or(None);
+    let metadata_time = metadata.as_ref().and_then(|m| m.captured_at);
+    let (captured_at, capture_time_source) =
+        resolve_capture_time(file_path, metadata_time, &file_metadata).ok_or_else(|| {
+            format!(
+                "Failed to get capture time for file {}",
+                file_path.to_string_lossy()
+            )
+        })?;
+    conn.prepare_cached(SQL_QUERIES.update_image_metadata)
+        .and_then(|mut update| {
+            update.execute(params![
+                file_path.to_string_lossy(),
+                metadata
+                    .as_ref()
+                    .map(|m| m.json.as_str())
+                    .unwrap_or_default(),
+                metadata.as_ref().map(|m| m.source.as_str()),
+                captured_at.to_rfc3339(),
+                capture_time_source.as_str(),
+                Utc::now().to_rfc3339(),
+                MetadataSource::inferred_json(),
+            ])
+        })
+        .map_err(|e| e.to_string())?;
+    update_fingerprint(conn, file_path, fingerprint).map_err(|e| e.to_string())
+}
+
+/// 画像ファイルを解析する
+///
+/// ファイルの読み込み、メタデータのチャンクの解析、画素のデコードはそれぞれ1回だけ行い、
+/// デコードした画像から大きさ・知覚ハッシュ・見た目の特徴・サムネイルを求める。
+pub(crate) fn analyze_image(file_path: &Path) -> std::result::Result<AnalyzedImage, String> {
+    let data = fs::read(file_path).map_err(|e| e.to_string())?;
+    let file_metadata = fs::metadata(file_path).map_err(|e| e.to_string())?;
+    let fingerprint = Fingerprint {
+        stat: FileStat::from_metadata(&file_metadata),
+        quick_hash: quick_hash(&data),
+        pixel_hash: pixel_hash(Cursor::new(&data)),
+    };
+    // PNG以外（JPEG）や読めないチャンクはメタデータ無しとして扱う
+    let metadata = extract_metadata(&data).unwrap_or(None);
+    let image = image::load_from_memory(&data).map_err(|e| e.to_string())?;
+    let (width, height) = image.dimensions();
+    let thumbnail = encode_thumbnail(&image).map_err(|e| e.to_string())?;
+    // 類似画像の検索用の特徴（登録済みの画像と同じくサムネイルの大きさで計算する）
+    let (phash, color_histogram) = descriptor_columns(&image.thumbnail(256, 256));
+    // 撮影日時（メタデータ → ファイル名 → 更新日時 → 作成日時の順に決定）
+    let metadata_time = metadata.as_ref().and_then(|m| m.captured_at);
+    let (captured_at, capture_time_source) =
+        resolve_capture_time(file_path, metadata_time, &file_metadata).ok_or_else(|| {
+            format!(
+                "Failed to get capture time for file {}",
+                file_path.to_string_lossy()
+            )
+        })?;
+    Ok(AnalyzedImage {
+        file_path: file_path.to_path_buf(),
+        width,
+        height,
+        file_size: data.len() as u64,
+        dhash: dhash(&image),
+        phash,
+        color_histogram,
+        thumbnail,
+        metadata,
+        captured_at,
+        capture_time_source,
+        fingerprint,
+    })
+}
+
+/// 解析に使うスレッドの数（CPUの論理コア数）
+pub(crate) fn worker_count() -> usize {
+    thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1)
+}
+
+/// 画像を `workers` 個のスレッドで並列に解析し、解析が終わった順に `on_analyzed` に渡す
+///
+/// `on_analyzed` は呼び出し元のスレッドだけで呼ばれるため、データベースへの書き込みはそこで行う。
+/// `on_analyzed` がエラーを返した場合は残りの解析をやめ、そのエラーを返す。
+pub(crate) fn analyze_in_parallel<F>(
+    file_paths: &[PathBuf],
+    workers: usize,
+    mut on_analyzed: F,
+) -> std::result::Result<(), String>
+where
+    F: FnMut(&Path, std::result::Result<AnalyzedImage, String>) -> std::result::Result<(), String>,
+{
+    let workers = workers.max(1);
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    // 書き込みが追いつかない場合に、解析済みの画像（サムネイル）がメモリにたまりすぎないようにする
+    let (sender, receiver) = sync_channel(workers * 2);
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let (next, stop) = (&next, &stop);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(file_path) = file_paths.get(index) else {
+                        break;
+                    };
+                    // 受け取り側が止まった場合は終了する
+                    if sender.send((index, analyze_image(file_path))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (index, analyzed) in receiver {
+            if let Err(e) = on_analyzed(&file_paths[index], analyzed) {
+                stop.store(true, Ordering::Relaxed);
+                return Err(e);
+            }
+        }
+        Ok(())
+    })
+}
+
+/// 解析済みの画像をまとめて登録する（1つのトランザクションで書き込む）
+pub(crate) fn write_images(
+    conn: &mut Connection,
+    folder_id: i64,
+    images: &[AnalyzedImage],
+) -> Result<()> {
+    if images.is_empty() {
+        return Ok(());
+    }
+    let inferred_sources = MetadataSource::inferred_json();
+    let transaction = conn.transaction()?;
+    {
+        let mut insert = transaction.prepare_cached(SQL_QUERIES.insert_image)?;
+        for image in images {
+            let file_path = image.file_path.to_string_lossy();
+            let now = Utc::now().to_rfc3339();
+            let metadata = image.metadata.as_ref();
+            insert.execute(params![
+                file_path,
+                image.width as i32,
+                image.height as i32,
+                image.file_size as i64,
+                metadata.map(|m| m.json.as_str()).unwrap_or_default(), // JSONデータがない場合は空文字列
+                image.captured_at.to_rfc3339(),
+                now,
+                now,
+                metadata.map(|m| m.source.as_str()),
+                image.capture_time_source.as_str(),
+                folder_id,
+                image.dhash as i64,
+                image.phash,
+                image.color_histogram,
+                image.fingerprint.stat.mtime_ns,
+                image.fingerprint.stat.inode,
+                image.fingerprint.stat.device,
+                image.fingerprint.quick_hash as i64,
+                image.fingerprint.pixel_hash.map(|hash| hash as i64),
+                inferred_sources,
+            ])?;
+            save_thumbnail(&transaction, &file_path, &image.thumbnail)?;
+        }
+    }
+    transaction.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+    use std::time::Instant;
+
+    /// テスト用の画像を置くフォルダ（テストごとに別のフォルダを使う）
+    fn fixture_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("vrcxphotosearcher-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// VRCXのメタデータを埋め込んだスクリーンショットのようなPNGを書き出す
+    fn write_screenshot(path: &Path, width: u32, height: u32, seed: u32) {
+        let image = RgbImage::from_fn(width, height, |x, y| {
+            let v = (x * 7 + y * 3 + seed * 31) % 256;
+            Rgb([v as u8, ((x ^ y) + seed) as u8, (255 - v) as u8])
+        });
+        let mut encoder = png::Encoder::new(fs::File::create(path).unwrap(), width, height);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder
+            .add_itxt_chunk(
+                "Description".to_string(),
+                format!("{{\"world\":{{\"name\":\"World {}\"}}}}", seed),
+            )
+            .unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(image.as_raw()).unwrap();
+    }
+
+    fn write_fixtures(dir: &Path, count: u32, width: u32, height: u32) -> Vec<PathBuf> {
+        (0..count)
+            .map(|i| {
+                let path = dir.join(format!(
+                    "VRChat_2024-01-05_21-14-{:02}.000_{}x{}.png",
+                    i % 60,
+                    width,
+                    height
+                ));
+                write_screenshot(&path, width, height, i);
+                path
+            })
+            .collect()
+    }
+
+    #[test]
+    fn analyzes_images_in_parallel() {
+        let dir = fixture_dir("analyze");
+        let mut paths = write_fixtures(&dir, 6, 64, 48);
+        let broken = dir.join("broken.png");
+        fs::write(&broken, b"not a png").unwrap();
+        paths.push(broken.clone());
+
+        let mut analyzed = Vec::new();
+        let mut failed = Vec::new();
+        analyze_in_parallel(&paths, 3, |file_path, result| {
+            match result {
+                Ok(image) => analyzed.push(image),
+                Err(_) => failed.push(file_path.to_path_buf()),
+            }
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(failed, vec![broken]);
+        assert_eq!(analyzed.len(), 6);
+        for image in &analyzed {
+            assert_eq!((image.width, image.height), (64, 48));
+            let metadata = image.metadata.as_ref().unwrap();
+            assert_eq!(metadata.source, MetadataSource::Vrcx);
+            assert!(metadata.json.contains("World"));
+            assert_eq!(image.capture_time_source, CaptureTimeSource::FileName);
+            // サムネイルはJPEG
+            assert_eq!(&image.thumbnail[..3], &[0xFF, 0xD8, 0xFF]);
+        }
+
+        // 書き込み側のエラーで残りの解析をやめる
+        let mut calls = 0;
+        let result = analyze_in_parallel(&paths, 2, |_, _| {
+            calls += 1;
+            Err("書き込み失敗".to_string())
+        });
+        assert_eq!(result, Err("書き込み失敗".to_string()));
+        assert_eq!(calls, 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn detects_changes_without_decoding() {
+        let dir = fixture_dir("detect");
+        let path = write_fixtures(&dir, 1, 32, 24).remove(0);
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::db::migration::migrate(&mut conn, crate::db::query::Queries::load().migrations)
+            .unwrap();
+        conn.execute(
+            "INSERT INTO search_folders (path, uuid) VALUES ('/photos', 'u')",
+            [],
+        )
+        .unwrap();
+
+        assert_eq!(detect_change(&conn, &path), Ok(FileChange::Modified));
+        write_images(&mut conn, 1, &[analyze_image(&path).unwrap()]).unwrap();
+        assert_eq!(detect_change(&conn, &path), Ok(FileChange::Unchanged));
+
+        // 更新日時だけが変わった場合は指紋を記録し直す
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1))
+            .unwrap();
+        drop(file);
+        let change = detect_change(&conn, &path).unwrap();
+        assert!(matches!(change, FileChange::Touched(_)));
+        assert_eq!(apply_change(&conn, &path, &change), Ok(true));
+        assert_eq!(detect_change(&conn, &path), Ok(FileChange::Unchanged));
+
+        // メタデータだけを書き換えた場合は、サムネイルと知覚ハッシュを残してメタデータを登録し直す
+        conn.execute("UPDATE images SET phash = 42", []).unwrap();
+        let image = RgbImage::from_fn(32, 24, |x, y| {
+            let v = (x * 7 + y * 3) % 256;
+            Rgb([v as u8, (x ^ y) as u8, (255 - v) as u8])
+        });
+        let mut encoder = png::Encoder::new(File::create(&path).unwrap(), 32, 24);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder
+            .add_itxt_chunk(
+                "Description".to_string(),
+                "{\"world\":{\"name\":\"Retagged World\"}}".to_string(),
+            )
+            .unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(image.as_raw()).unwrap();
+        writer.finish().unwrap();
+        let change = detect_change(&conn, &path).unwrap();
+        assert!(matches!(change, FileChange::MetadataOnly(_)));
+        assert_eq!(apply_change(&conn, &path, &change), Ok(true));
+        let (metadata_json, phash): (String, i64) = conn
+            .query_row("SELECT metadata_json, phash FROM images", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert!(metadata_json.contains("Retagged World"));
+        assert_eq!(phash, 42);
+        assert_eq!(detect_change(&conn, &path), Ok(FileChange::Unchanged));
+
+        // 先頭と末尾が同じでも、画素のハッシュが記録と違う場合は解析し直す
+        conn.execute("UPDATE images SET pixel_hash = 0", [])
+            .unwrap();
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(2))
+            .unwrap();
+        drop(file);
+        assert_eq!(detect_change(&conn, &path), Ok(FileChange::Modified));
+
+        // 画素が変わった場合は解析し直す
+        write_screenshot(&path, 32, 24, 5);
+        assert_eq!(detect_change(&conn, &path), Ok(FileChange::Modified));
+        assert_eq!(apply_change(&conn, &path, &FileChange::Modified), Ok(false));
+        // 登録し直した後はサイズも新しいファイルのものになり、変更なしと判定される
+        write_images(&mut conn, 1, &[analyze_image(&path).unwrap()]).unwrap();
+        assert_eq!(detect_change(&conn, &path), Ok(FileChange::Unchanged));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn detects_changes_of_images_indexed_without_fingerprints() {
+        let dir = fixture_dir("legacy");
+        let path = write_fixtures(&dir, 1, 32, 24).remove(0);
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::db::migration::migrate(&mut conn, crate::db::query::Queries::load().migrations)
+            .unwrap();
+        conn.execute(
+            "INSERT INTO search_folders (path, uuid) VALUES ('/photos', 'u')",
+            [],
+        )
+        .unwrap();
+        write_images(&mut conn, 1, &[analyze_image(&path).unwrap()]).unwrap();
+        // 指紋を記録する前のバージョンで登録した画像と同じ状態にする
+        let forget_fingerprint = "UPDATE images SET mtime_ns = NULL, inode = NULL, device = NULL,
+             quick_hash = NULL, pixel_hash = NULL";
+        conn.execute(forget_fingerprint, []).unwrap();
+
+        // 登録した後に更新されていなければ、デコードせずに指紋だけを記録する
+        let change = detect_change(&conn, &path).unwrap();
+        assert!(matches!(change, FileChange::Touched(_)));
+        assert_eq!(apply_change(&conn, &path, &change), Ok(true));
+        let (quick_hash, pixel_hash): (Option<i64>, Option<i64>) = conn
+            .query_row("SELECT quick_hash, pixel_hash FROM images", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert!(quick_hash.is_some() && pixel_hash.is_some());
+        assert_eq!(detect_change(&conn, &path), Ok(FileChange::Unchanged));
+
+        // 登録した後に更新されている場合は解析し直す
+        conn.execute(forget_fingerprint, []).unwrap();
+        conn.execute(
+            "UPDATE images SET updated_at = '2000-01-01T00:00:00+00:00'",
+            [],
+        )
+        .unwrap();
+        assert_eq!(detect_change(&conn, &path), Ok(FileChange::Modified));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// 取り込みの速さを以前の方法（1枚ずつ、画像を2回デコードし、メタデータのためにもう1回開く）と比べる
+    ///
+    /// `cargo test --release benchmark_ingest -- --ignored --nocapture` で実行する。
+    /// `INGEST_BENCH_DIR` に写真のフォルダを指定するとその中のPNGを、指定しない場合は生成した画像を使う。
+    /// サムネイルはどちらも同じエンコーダーで作り、類似画像の特徴と指紋もどちらも求める。
+    /// 読めないファイルはどちらも飛ばす。
+    #[test]
+    #[ignore]
+    fn benchmark_ingest() {
+        let (paths, generated) = match std::env::var("INGEST_BENCH_DIR") {
+            Ok(dir) => {
+                let paths: Vec<PathBuf> = fs::read_dir(dir)
+                    .unwrap()
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.extension().is_some_and(|ext| ext == "png"))
+                    .collect();
+                (paths, None)
+            }
+            Err(_) => {
+                let dir = fixture_dir("benchmark");
+                (write_fixtures(&dir, 32, 1920, 1080), Some(dir))
+            }
+        };
+
+        let started = Instant::now();
+        let mut sequential_count = 0;
+        for path in &paths {
+            let Ok(image) = image::open(path) else {
+                continue;
+            };
+            let _ = encode_thumbnail(&image).unwrap();
+            let Ok(image) = image::open(path) else {
+                continue;
+            };
+            let _ = (image.dimensions(), dhash(&image));
+            let _ = descriptor_columns(&image.thumbnail(256, 256));
+            let _ = fs::read(path).map(|data| extract_metadata(&data));
+            let _ = quick_hash_file(path);
+            let _ = File::open(path).map(|file| pixel_hash(BufReader::new(file)));
+            sequential_count += 1;
+        }
+        let sequential = started.elapsed();
+
+        let started = Instant::now();
+        let mut count = 0;
+        analyze_in_parallel(&paths, worker_count(), |_, result| {
+            if result.is_ok() {
+                count += 1;
+            }
+            Ok(())
+        })
+        .unwrap();
+        let parallel = started.elapsed();
+
+        assert_eq!(count, sequential_count);
+        println!(
+            "{}枚（読めないファイル{}枚）: 以前の方法 {:?} / 新しい方法（{}スレッド） {:?}（{:.1}倍）",
+            count,
+            paths.len() - count,
+            sequential,
+            worker_count(),
+            parallel,
+            sequential.as_secs_f64() / parallel.as_secs_f64()
+        );
+        if let Some(dir) = generated {
+            fs::remove_dir_all(dir).unwrap();
+        }
+    }
+}
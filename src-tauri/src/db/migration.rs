@@ -0,0 +1,185 @@
+use super::thumbnails::register_legacy_thumbnail_decoder;
+use rusqlite::{Connection, Result};
+
+/// 現在のスキーマバージョン（`PRAGMA user_version`）
+pub(crate) fn schema_version(conn: &Connection) -> Result<usize> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// 未適用のマイグレーションを順番に適用する
+///
+/// `migrations[n]` を適用するとバージョンが `n + 1` になる。
+/// 各マイグレーションはバージョンの更新と同じトランザクションで実行するため、
+/// 途中で失敗した場合はそのマイグレーションの前の状態に戻る。
+/// 適用後のバージョンを返す。
+pub(crate) fn migrate(conn: &mut Connection, migrations: &[&str]) -> Result<usize> {
+    // マイグレーションの中で使う関数
+    register_legacy_thumbnail_decoder(conn)?;
+    let current = schema_version(conn)?;
+    for (index, sql) in migrations.iter().enumerate().skip(current) {
+        let version = index + 1;
+        let transaction = conn.transaction()?;
+        transaction.execute_batch(sql)?;
+        transaction.pragma_update(None, "user_version", version)?;
+        transaction.commit()?;
+        println!("データベースをバージョン{}に更新しました", version);
+    }
+    schema_version(conn)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::query::Queries;
+
+    fn columns(conn: &Connection, table: &str) -> Vec<String> {
+        conn.prepare(&format!("SELECT name FROM pragma_table_info('{}')", table))
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .filter_map(Result::ok)
+            .collect()
+    }
+
+    /// バージョン管理を導入する前（v1）のフォルダ一覧データベース
+    fn fixture_v1_main_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE search_folders (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT, path TEXT NOT NULL UNIQUE, uuid TEXT NOT NULL UNIQUE);
+             CREATE TABLE ignore_folders (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT, path TEXT NOT NULL UNIQUE, uuid TEXT NOT NULL UNIQUE);
+             INSERT INTO search_folders (path, uuid) VALUES ('C:\\VRChat', 'uuid-1');",
+        )
+        .unwrap();
+        conn
+    }
+
+    /// バージョン管理を導入する前（v1）のフォルダごとのインデックス
+    fn fixture_v1_sub_index_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE images (
+                 id INTEGER PRIMARY KEY AUTOINCREMENT, file_path TEXT NOT NULL UNIQUE, thumbnail TEXT,
+                 width INTEGER, height INTEGER, file_size INTEGER, metadata_json TEXT,
+                 file_created_at TEXT NOT NULL, created_at TEXT NOT NULL, updated_at TEXT NOT NULL);
+             INSERT INTO images (file_path, thumbnail, width, height, file_size, metadata_json,
+                                 file_created_at, created_at, updated_at)
+             VALUES ('a.png', 'AAAA', 1920, 1080, 100, '{}',
+                     '2023-05-01T10:00:00+00:00', '2023-05-01T10:00:00+00:00', '2023-05-01T10:00:00+00:00');",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn upgrades_v1_main_db() {
+        let queries = Queries::load();
+        let mut conn = fixture_v1_main_db();
+        let version = migrate(&mut conn, queries.migrations).unwrap();
+        assert_eq!(version, queries.migrations.len());
+
+        // 既存のフォルダはそのまま残り、画像テーブルが追加される
+        let uuid: String = conn
+            .query_row(
+                "SELECT uuid FROM search_folders WHERE path = 'C:\\VRChat'",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(uuid, "uuid-1");
+        assert!(columns(&conn, "images").contains(&"folder_id".to_string()));
+    }
+
+    #[test]
+    fn normalizes_players_and_worlds() {
+        let queries = Queries::load();
+        let mut conn = fixture_v1_main_db();
+        migrate(&mut conn, queries.migrations).unwrap();
+        conn.execute_batch(
+            "INSERT INTO images (folder_id, file_path, metadata_json, file_created_at, created_at, updated_at)
+             VALUES (1, 'a.png',
+                     '{\"author\":{\"id\":\"usr_me\",\"displayName\":\"Me\"},
+                       \"world\":{\"id\":\"wrld_a\",\"name\":\"World A\",\"instanceId\":\"wrld_a:1\"},
+                       \"players\":[{\"id\":\"usr_alice\",\"displayName\":\"Alice\"},{\"id\":\"\",\"displayName\":\"Bob\"}]}',
+                     't', 't', 't'),
+                    (1, 'b.png', '', 't', 't', 't');",
+        )
+        .unwrap();
+
+        let players: Vec<(Option<String>, String)> = conn
+            .prepare("SELECT user_id, display_name FROM image_players ORDER BY display_name")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .filter_map(Result::ok)
+            .collect();
+        assert_eq!(
+            players,
+            vec![
+                (Some("usr_alice".to_string()), "Alice".to_string()),
+                (None, "Bob".to_string())
+            ]
+        );
+        let world: (String, String) = conn
+            .query_row(
+                "SELECT images.instance_id, worlds.name FROM images
+                 JOIN worlds ON worlds.world_id = images.world_id WHERE file_path = 'a.png'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(world, ("wrld_a:1".to_string(), "World A".to_string()));
+
+        // メタデータが消えた場合は同席者も消える
+        conn.execute(
+            "UPDATE images SET metadata_json = '' WHERE file_path = 'a.png'",
+            [],
+        )
+        .unwrap();
+        let count: i64 = conn
+            .query_row("SELECT count(*) FROM image_players", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn upgrades_v1_sub_index_db() {
+        let queries = Queries::load();
+        let mut conn = fixture_v1_sub_index_db();
+        let version = migrate(&mut conn, queries.sub_index_migrations).unwrap();
+        assert_eq!(version, queries.sub_index_migrations.len());
+
+        let columns = columns(&conn, "images");
+        assert!(columns.contains(&"metadata_source".to_string()));
+        assert!(columns.contains(&"capture_time_source".to_string()));
+        let (file_path, width): (String, i64) = conn
+            .query_row("SELECT file_path, width FROM images", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((file_path.as_str(), width), ("a.png", 1920));
+    }
+
+    #[test]
+    fn skips_applied_migrations() {
+        let queries = Queries::load();
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn, queries.sub_index_migrations).unwrap();
+        // 適用済みの ALTER TABLE が再実行されるとエラーになる
+        let version = migrate(&mut conn, queries.sub_index_migrations).unwrap();
+        assert_eq!(version, queries.sub_index_migrations.len());
+    }
+
+    #[test]
+    fn rolls_back_failed_migration() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        let migrations = [
+            "CREATE TABLE a (id INTEGER);",
+            "CREATE TABLE b (id INTEGER); SELECT * FROM missing;",
+        ];
+        assert!(migrate(&mut conn, &migrations).is_err());
+        assert_eq!(schema_version(&conn).unwrap(), 1);
+        assert!(columns(&conn, "b").is_empty());
+    }
+}
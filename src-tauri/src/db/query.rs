@@ -1,6 +1,8 @@
 pub struct Queries {
-    pub create_tables: &'static str,
-    pub create_sub_index: &'static str,
+    /// 統合データベースのマイグレーション（配列の順番がそのままバージョンになる）
+    pub migrations: &'static [&'static str],
+    /// フォルダごとのインデックス（旧形式）のマイグレーション
+    pub sub_index_migrations: &'static [&'static str],
     pub insert_folder: &'static str,
     pub insert_ignore_folder: &'static str,
     pub select_all_folders: &'static str,
@@ -8,6 +10,10 @@ pub struct Queries {
     pub delete_folder: &'static str,
     pub delete_ignore_folder: &'static str,
     pub insert_image: &'static str,
+    pub update_image_metadata: &'static str,
+    pub import_legacy_index: &'static str,
+    pub import_legacy_thumbnails: &'static str,
+    pub reassign_folder_images: &'static str,
 }
 
 /// クエリの初期化用マクロ
@@ -15,8 +21,25 @@ pub struct Queries {
 impl Queries {
     pub fn load() -> Self {
         Self {
-            create_tables: include_str!("sql\\create_tables.sql"), // パス修正
-            create_sub_index: include_str!("sql\\create_sub_index.sql"), // パス修正
+            migrations: &[
+                include_str!("sql\\migrations\\main\\0001_create_folders.sql"),
+                include_str!("sql\\migrations\\main\\0002_create_images.sql"),
+                include_str!("sql\\migrations\\main\\0003_normalize_metadata.sql"),
+                include_str!("sql\\migrations\\main\\0004_parse_instance_ids.sql"),
+                include_str!("sql\\migrations\\main\\0005_create_sessions.sql"),
+                include_str!("sql\\migrations\\main\\0006_create_annotations.sql"),
+                include_str!("sql\\migrations\\main\\0007_create_albums.sql"),
+                include_str!("sql\\migrations\\main\\0008_add_dhash.sql"),
+                include_str!("sql\\migrations\\main\\0009_add_image_descriptors.sql"),
+                include_str!("sql\\migrations\\main\\0010_create_images_fts.sql"),
+                include_str!("sql\\migrations\\main\\0011_create_thumbnails.sql"),
+                include_str!("sql\\migrations\\main\\0012_add_file_fingerprints.sql"),
+            ],
+            sub_index_migrations: &[
+                include_str!("sql\\migrations\\sub_index\\0001_create_images.sql"),
+                include_str!("sql\\migrations\\sub_index\\0002_add_metadata_source.sql"),
+                include_str!("sql\\migrations\\sub_index\\0003_add_capture_time_source.sql"),
+            ],
             insert_folder: include_str!("sql\\insert_folder.sql"), // パス修正
             insert_ignore_folder: include_str!("sql\\insert_ignore_folder.sql"), // パス修正
             select_all_folders: include_str!("sql\\select_all_folders.sql"), // パス修正
@@ -24,6 +47,10 @@ impl Queries {
             delete_folder: include_str!("sql\\delete_folder.sql"), // パス修正
             delete_ignore_folder: include_str!("sql\\delete_ignore_folder.sql"), // パス修正
             insert_image: include_str!("sql\\insert_image.sql"),   // パス修正
+            update_image_metadata: include_str!("sql\\update_image_metadata.sql"),
+            import_legacy_index: include_str!("sql\\import_legacy_index.sql"),
+            import_legacy_thumbnails: include_str!("sql\\import_legacy_thumbnails.sql"),
+            reassign_folder_images: include_str!("sql\\reassign_folder_images.sql"),
         }
     }
 }
@@ -31,8 +58,25 @@ impl Queries {
 impl Queries {
     pub fn load() -> Self {
         Self {
-            create_tables: include_str!("sql/create_tables.sql"),
-            create_sub_index: include_str!("sql/create_sub_index.sql"), // パス修正
+            migrations: &[
+                include_str!("sql/migrations/main/0001_create_folders.sql"),
+                include_str!("sql/migrations/main/0002_create_images.sql"),
+                include_str!("sql/migrations/main/0003_normalize_metadata.sql"),
+                include_str!("sql/migrations/main/0004_parse_instance_ids.sql"),
+                include_str!("sql/migrations/main/0005_create_sessions.sql"),
+                include_str!("sql/migrations/main/0006_create_annotations.sql"),
+                include_str!("sql/migrations/main/0007_create_albums.sql"),
+                include_str!("sql/migrations/main/0008_add_dhash.sql"),
+                include_str!("sql/migrations/main/0009_add_image_descriptors.sql"),
+                include_str!("sql/migrations/main/0010_create_images_fts.sql"),
+                include_str!("sql/migrations/main/0011_create_thumbnails.sql"),
+                include_str!("sql/migrations/main/0012_add_file_fingerprints.sql"),
+            ],
+            sub_index_migrations: &[
+                include_str!("sql/migrations/sub_index/0001_create_images.sql"),
+                include_str!("sql/migrations/sub_index/0002_add_metadata_source.sql"),
+                include_str!("sql/migrations/sub_index/0003_add_capture_time_source.sql"),
+            ],
             insert_folder: include_str!("sql/insert_folder.sql"),
             insert_ignore_folder: include_str!("sql/insert_ignore_folder.sql"),
             select_all_folders: include_str!("sql/select_all_folders.sql"),
@@ -40,6 +84,10 @@ impl Queries {
             delete_folder: include_str!("sql/delete_folder.sql"),
             delete_ignore_folder: include_str!("sql/delete_ignore_folder.sql"),
             insert_image: include_str!("sql/insert_image.sql"),
+            update_image_metadata: include_str!("sql/update_image_metadata.sql"),
+            import_legacy_index: include_str!("sql/import_legacy_index.sql"),
+            import_legacy_thumbnails: include_str!("sql/import_legacy_thumbnails.sql"),
+            reassign_folder_images: include_str!("sql/reassign_folder_images.sql"),
         }
     }
 }
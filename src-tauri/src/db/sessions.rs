@@ -0,0 +1,363 @@
+use super::{decode_cursor, encode_cursor, init_db, query_images, MAX_PAGE_SIZE};
+use crate::config::{default_config, read_config};
+use crate::model::session::{Session, SessionPage, SessionPlayer};
+use crate::search::{Expr, Field, Op};
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection, Result};
+use std::path::PathBuf;
+use tauri::{AppHandle, Manager};
+
+/// 滞在一覧の1ページの件数の既定値
+const DEFAULT_SESSION_PAGE_SIZE: u32 = 50;
+
+/// 設定された滞在の区切り（撮影間隔のしきい値）
+///
+/// 設定ファイルを読むため、登録処理ごとに1回だけ読んで [`update_sessions`] に渡す。
+/// 設定ファイルが壊れている場合も登録を止めないよう、既定の間隔を使う。
+pub(crate) fn session_gap(app: &AppHandle) -> Duration {
+    let config_path = app.path().app_data_dir().unwrap_or(PathBuf::from("."));
+    read_config(&config_path)
+        .unwrap_or_else(|e| {
+            eprintln!(
+                "設定ファイルの読み込み失敗（既定の滞在の区切りを使用）: {}",
+                e
+            );
+            default_config()
+        })
+        .session_gap()
+}
+
+/// クラスタリングの対象
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    /// すべての滞在を作り直す
+    All,
+    /// 滞在に属していない写真があるインスタンスだけ作り直す
+    Pending,
+}
+
+/// 写真をインスタンスと撮影間隔で滞在にまとめ、作成した滞在の数を返す
+///
+/// 同じインスタンスで撮影された写真を撮影日時順に並べ、間隔が `gap` を超えたところで区切る。
+/// インスタンスIDの無い写真はどの滞在にも属さない。
+fn cluster_sessions(conn: &Connection, gap: Duration, scope: Scope) -> Result<usize> {
+    let mut query = String::from(
+        "SELECT id, instance_id, world_id, file_created_at FROM images WHERE instance_id IS NOT NULL",
+    );
+    if scope == Scope::Pending {
+        // 撮影日時を解析できない写真はどの滞在にも入らないため、作り直しのきっかけにしない
+        query.push_str(
+            " AND instance_id IN (SELECT instance_id FROM images \
+             WHERE session_id IS NULL AND instance_id IS NOT NULL \
+             AND julianday(file_created_at) IS NOT NULL)",
+        );
+    }
+    let mut rows: Vec<(i64, String, Option<String>, DateTime<Utc>)> = conn
+        .prepare(&query)?
+        .query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?
+        .filter_map(Result::ok)
+        .filter_map(|(id, instance_id, world_id, time)| {
+            Some((id, instance_id, world_id, time.parse().ok()?))
+        })
+        .collect();
+    if rows.is_empty() {
+        return Ok(0);
+    }
+    // タイムゾーンの表記が混ざっていても正しく並ぶよう、解析した日時で並べる
+    rows.sort_by(|a, b| (&a.1, a.3, a.0).cmp(&(&b.1, b.3, b.0)));
+
+    let tx = conn.unchecked_transaction()?;
+    if scope == Scope::All {
+        tx.execute("DELETE FROM sessions", [])?;
+    } else {
+        let mut delete = tx.prepare("DELETE FROM sessions WHERE instance_id = ?")?;
+        let mut previous = None;
+        for (_, instance_id, _, _) in &rows {
+            if previous != Some(instance_id) {
+                delete.execute([instance_id])?;
+                previous = Some(instance_id);
+            }
+        }
+    }
+
+    let mut insert_session = tx.prepare(
+        "INSERT INTO sessions (instance_id, world_id, started_at, ended_at, image_count)
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    let mut assign = tx.prepare("UPDATE images SET session_id = ?1 WHERE id = ?2")?;
+    // 同席者の和集合（IDの無い古いデータは表示名でまとめる）
+    let mut insert_players = tx.prepare(
+        "INSERT INTO session_players (session_id, user_id, display_name)
+         SELECT ?1, image_players.user_id,
+                coalesce(max(players.display_name), max(image_players.display_name))
+         FROM images
+         JOIN image_players ON image_players.image_id = images.id
+         LEFT JOIN players ON players.user_id = image_players.user_id
+         WHERE images.session_id = ?1
+         GROUP BY coalesce(image_players.user_id, image_players.display_name)",
+    )?;
+
+    let mut count = 0;
+    let mut start = 0;
+    for end in 1..=rows.len() {
+        let split = end == rows.len()
+            || rows[end].1 != rows[end - 1].1
+            || rows[end].3 - rows[end - 1].3 > gap;
+        if !split {
+            continue;
+        }
+        let members = &rows[start..end];
+        let world_id = members.iter().find_map(|row| row.2.as_deref());
+        insert_session.execute(params![
+            members[0].1,
+            world_id,
+            members[0].3.to_rfc3339(),
+            members[members.len() - 1].3.to_rfc3339(),
+            members.len() as i64,
+        ])?;
+        let session_id = tx.last_insert_rowid();
+        for (id, _, _, _) in members {
+            assign.execute(params![session_id, id])?;
+        }
+        insert_players.execute([session_id])?;
+        count += 1;
+        start = end;
+    }
+    drop((insert_session, assign, insert_players));
+    tx.commit()?;
+    Ok(count)
+}
+
+/// 新しく登録された写真や、インスタンス・撮影日時が変わった写真の滞在を作り直す
+pub(crate) fn update_sessions(conn: &Connection, gap: Duration) -> Result<usize> {
+    cluster_sessions(conn, gap, Scope::Pending)
+}
+
+fn session_players(conn: &Connection, session_id: i64) -> Result<Vec<SessionPlayer>> {
+    conn.prepare_cached(
+        "SELECT user_id, display_name FROM session_players
+         WHERE session_id = ? ORDER BY display_name",
+    )?
+    .query_map([session_id], |row| {
+        Ok(SessionPlayer {
+            user_id: row.get(0)?,
+            display_name: row.get(1)?,
+        })
+    })?
+    .collect()
+}
+
+/// 滞在を新しい順に1ページ分返す
+fn query_sessions(
+    conn: &Connection,
+    cursor: Option<(SqlValue, i64)>,
+    limit: u32,
+) -> Result<SessionPage> {
+    let mut query = String::from(
+        "SELECT sessions.id, sessions.instance_id, sessions.world_id, worlds.name,
+                instances.access_type, sessions.started_at, sessions.ended_at, sessions.image_count
+         FROM sessions
+         LEFT JOIN worlds ON worlds.world_id = sessions.world_id
+         LEFT JOIN instances ON instances.instance_id = sessions.instance_id",
+    );
+    let mut params: Vec<SqlValue> = Vec::new();
+    if let Some((started_at, id)) = cursor {
+        query.push_str(" WHERE (sessions.started_at, sessions.id) < (?, ?)");
+        params.push(started_at);
+        params.push(SqlValue::Integer(id));
+    }
+    query.push_str(" ORDER BY sessions.started_at DESC, sessions.id DESC LIMIT ?");
+    // 次のページがあるか判定するため1件多く取得する
+    params.push(SqlValue::Integer(limit as i64 + 1));
+
+    let mut items = conn
+        .prepare(&query)?
+        .query_map(params_from_iter(params), |row| {
+            Ok(Session {
+                id: row.get(0)?,
+                instance_id: row.get(1)?,
+                world_id: row.get(2)?,
+                world_name: row.get(3)?,
+                access_type: row.get(4)?,
+                started_at: row.get(5)?,
+                ended_at: row.get(6)?,
+                image_count: row.get(7)?,
+                players: Vec::new(),
+            })
+        })?
+        .collect::<Result<Vec<_>>>()?;
+
+    let next_cursor = if items.len() > limit as usize {
+        items.truncate(limit as usize);
+        items
+            .last()
+            .map(|last| encode_cursor(&SqlValue::Text(last.started_at.clone()), last.id))
+    } else {
+        None
+    };
+    for session in &mut items {
+        session.players = session_players(conn, session.id)?;
+    }
+    Ok(SessionPage { items, next_cursor })
+}
+
+/// 設定された撮影間隔で、すべての写真の滞在を作り直す
+#[tauri::command]
+pub fn rebuild_sessions(app: AppHandle) -> std::result::Result<usize, String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    cluster_sessions(&conn, session_gap(&app), Scope::All).map_err(|e| e.to_string())
+}
+
+/// 滞在（ワールド・期間・同席者・写真の枚数）を新しい順に返す
+#[tauri::command]
+pub fn get_sessions(
+    app: AppHandle,
+    cursor: Option<String>,
+    limit: Option<u32>,
+) -> std::result::Result<SessionPage, String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    let limit = limit
+        .unwrap_or(DEFAULT_SESSION_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let cursor = match cursor {
+        Some(cursor) => Some(decode_cursor(&cursor).ok_or("カーソルが不正です")?),
+        None => None,
+    };
+    query_sessions(&conn, cursor, limit).map_err(|e| e.to_string())
+}
+
+/// 滞在中に撮影された写真を撮影日時順に返す
+#[tauri::command]
+pub fn get_session_images(
+    app: AppHandle,
+    session_id: i64,
+) -> std::result::Result<Vec<(String, i64, String)>, String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    let filter = Expr::term(Field::Session, Op::Eq, session_id.to_string());
+    query_images(&conn, Some(&filter)).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migration::migrate;
+    use crate::db::query::Queries;
+
+    fn insert_image(conn: &Connection, path: &str, instance_id: &str, time: &str, player: &str) {
+        let metadata = format!(
+            r#"{{"world":{{"id":"wrld_a","instanceId":"{}"}},"players":[{{"displayName":"{}"}}]}}"#,
+            instance_id, player
+        );
+        conn.execute(
+            "INSERT INTO images (folder_id, file_path, metadata_json, file_created_at, created_at, updated_at)
+             VALUES (1, ?1, ?2, ?3, 't', 't')",
+            params![path, metadata, time],
+        )
+        .unwrap();
+    }
+
+    fn sessions(conn: &Connection) -> Vec<(String, i64, i64)> {
+        conn.prepare(
+            "SELECT sessions.instance_id, sessions.image_count, count(DISTINCT session_players.display_name)
+             FROM sessions LEFT JOIN session_players ON session_players.session_id = sessions.id
+             GROUP BY sessions.id ORDER BY sessions.instance_id, sessions.started_at",
+        )
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .map(Result::unwrap)
+        .collect()
+    }
+
+    #[test]
+    fn splits_by_instance_and_gap() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+        migrate(&mut conn, Queries::load().migrations).unwrap();
+        conn.execute(
+            "INSERT INTO search_folders (path, uuid) VALUES ('/photos', 'u')",
+            [],
+        )
+        .unwrap();
+        insert_image(
+            &conn,
+            "a.png",
+            "wrld_a:1",
+            "2024-01-01T20:00:00+00:00",
+            "Alice",
+        );
+        insert_image(
+            &conn,
+            "b.png",
+            "wrld_a:1",
+            "2024-01-01T20:20:00+00:00",
+            "Bob",
+        );
+        insert_image(
+            &conn,
+            "c.png",
+            "wrld_a:1",
+            "2024-01-01T21:35:00+00:00",
+            "Alice",
+        );
+        insert_image(
+            &conn,
+            "d.png",
+            "wrld_a:2",
+            "2024-01-01T20:10:00+00:00",
+            "Carol",
+        );
+
+        let gap = Duration::minutes(30);
+        assert_eq!(cluster_sessions(&conn, gap, Scope::All).unwrap(), 3);
+        assert_eq!(
+            sessions(&conn),
+            vec![
+                ("wrld_a:1".to_string(), 2, 2),
+                ("wrld_a:1".to_string(), 1, 1),
+                ("wrld_a:2".to_string(), 1, 1)
+            ]
+        );
+
+        // 間を埋める写真が追加されると、そのインスタンスの滞在だけ作り直す
+        insert_image(
+            &conn,
+            "e.png",
+            "wrld_a:1",
+            "2024-01-01T20:45:00+00:00",
+            "Dave",
+        );
+        insert_image(
+            &conn,
+            "f.png",
+            "wrld_a:1",
+            "2024-01-01T21:10:00+00:00",
+            "Erin",
+        );
+        assert_eq!(cluster_sessions(&conn, gap, Scope::Pending).unwrap(), 1);
+        assert_eq!(
+            sessions(&conn),
+            vec![
+                ("wrld_a:1".to_string(), 5, 4),
+                ("wrld_a:2".to_string(), 1, 1)
+            ]
+        );
+
+        // 写真を削除すると、その滞在は作り直しの対象になる
+        conn.execute("DELETE FROM images WHERE file_path = 'e.png'", [])
+            .unwrap();
+        assert_eq!(cluster_sessions(&conn, gap, Scope::Pending).unwrap(), 2);
+
+        // 撮影日時を解析できない写真があっても、毎回作り直すことはない
+        insert_image(&conn, "g.png", "wrld_a:2", "unknown", "Carol");
+        assert_eq!(cluster_sessions(&conn, gap, Scope::Pending).unwrap(), 0);
+    }
+}
@@ -0,0 +1,159 @@
+use super::init_db;
+use super::thumbnails::{load_thumbnail, THUMBNAIL_JOIN};
+use crate::image_hash::{
+    color_histogram, hamming_distance, histogram_distance, histogram_from_bytes,
+    histogram_to_bytes, phash, HISTOGRAM_BINS,
+};
+use crate::model::similar::SimilarImage;
+use image::DynamicImage;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+use std::path::Path;
+use tauri::AppHandle;
+
+/// 返す画像の数の既定値
+const DEFAULT_SIMILAR_LIMIT: u32 = 20;
+/// 距離のうち知覚ハッシュ（構図）の割合（残りは色ヒストグラム）
+const PHASH_WEIGHT: f32 = 0.6;
+
+/// 見た目の特徴
+struct Descriptor {
+    phash: u64,
+    histogram: Vec<f32>,
+}
+
+impl Descriptor {
+    fn distance(&self, other: &Descriptor) -> f32 {
+        let phash = hamming_distance(self.phash, other.phash) as f32 / 64.0;
+        let histogram = histogram_distance(&self.histogram, &other.histogram);
+        PHASH_WEIGHT * phash + (1.0 - PHASH_WEIGHT) * histogram
+    }
+}
+
+/// サムネイルから見た目の特徴を計算し、`phash` と `color_histogram` の列の値にする
+pub(crate) fn descriptor_columns(thumbnail: &DynamicImage) -> (i64, Vec<u8>) {
+    (
+        phash(thumbnail) as i64,
+        histogram_to_bytes(&color_histogram(thumbnail)),
+    )
+}
+
+/// 見た目の特徴が無い（以前のバージョンで登録された）画像の特徴をサムネイルから計算する
+pub(crate) fn backfill_descriptors(conn: &Connection) -> Result<usize> {
+    let ids: Vec<i64> = conn
+        .prepare(&format!(
+            "SELECT images.id FROM images {}
+             WHERE (images.phash IS NULL OR images.color_histogram IS NULL)
+               AND thumbnails.data IS NOT NULL",
+            THUMBNAIL_JOIN
+        ))?
+        .query_map([], |row| row.get(0))?
+        .filter_map(Result::ok)
+        .collect();
+
+    let tx = conn.unchecked_transaction()?;
+    let mut updated = 0;
+    {
+        let mut stmt =
+            tx.prepare("UPDATE images SET phash = ?1, color_histogram = ?2 WHERE id = ?3")?;
+        for id in ids {
+            let Some(image) = load_thumbnail(&tx, id)? else {
+                continue;
+            };
+            let (phash, histogram) = descriptor_columns(&image);
+            stmt.execute(params![phash, histogram, id])?;
+            updated += 1;
+        }
+    }
+    tx.commit()?;
+    Ok(updated)
+}
+
+fn descriptor_from_row(phash: i64, histogram: Vec<u8>) -> Option<Descriptor> {
+    let histogram = histogram_from_bytes(&histogram);
+    (histogram.len() == HISTOGRAM_BINS).then_some(Descriptor {
+        phash: phash as u64,
+        histogram,
+    })
+}
+
+/// 指定した画像に見た目の近い画像を距離の近い順に返す（指定した画像自身は含めない）
+fn similar_images(
+    conn: &Connection,
+    uuid: &str,
+    file_path: &str,
+    limit: usize,
+) -> std::result::Result<Vec<SimilarImage>, String> {
+    let (target_id, target) = conn
+        .query_row(
+            "SELECT images.id, images.phash, images.color_histogram FROM images
+             JOIN search_folders ON search_folders.id = images.folder_id
+             WHERE search_folders.uuid = ?1 AND images.file_path = ?2
+               AND images.phash IS NOT NULL AND images.color_histogram IS NOT NULL",
+            params![uuid, file_path],
+            |row| Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .and_then(|(id, phash, histogram)| Some((id, descriptor_from_row(phash, histogram)?)))
+        .ok_or_else(|| "指定された画像の特徴が見つかりません。".to_string())?;
+
+    let mut ranked: Vec<(f32, i64)> = conn
+        .prepare(
+            "SELECT id, phash, color_histogram FROM images
+             WHERE phash IS NOT NULL AND color_histogram IS NOT NULL AND id != ?",
+        )
+        .map_err(|e| e.to_string())?
+        .query_map([target_id], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get(1)?, row.get(2)?))
+        })
+        .map_err(|e| e.to_string())?
+        .filter_map(Result::ok)
+        .filter_map(|(id, phash, histogram)| {
+            let descriptor = descriptor_from_row(phash, histogram)?;
+            Some((target.distance(&descriptor), id))
+        })
+        .collect();
+    ranked.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT images.file_path, search_folders.uuid FROM images
+             JOIN search_folders ON search_folders.id = images.folder_id WHERE images.id = ?",
+        )
+        .map_err(|e| e.to_string())?;
+    let mut results = Vec::new();
+    for (distance, id) in ranked {
+        if results.len() >= limit {
+            break;
+        }
+        let (file_path, uuid): (String, String) = stmt
+            .query_row([id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?;
+        // 登録後に削除されたファイルは返さない
+        if !Path::new(&file_path).exists() {
+            continue;
+        }
+        results.push(SimilarImage {
+            file_path,
+            image_id: id,
+            uuid,
+            distance,
+        });
+    }
+    Ok(results)
+}
+
+/// 指定した画像に見た目（構図と色合い）の近い画像を、近い順に返す
+///
+/// 見た目の特徴は登録時にサムネイルから計算したものを使うため、画像ファイルは読み込まない。
+#[tauri::command]
+pub fn find_similar_images(
+    app: AppHandle,
+    uuid: String,
+    file_path: String,
+    limit: Option<u32>,
+) -> std::result::Result<Vec<SimilarImage>, String> {
+    let conn = init_db(&app).map_err(|e| e.to_string())?;
+    let limit = limit.unwrap_or(DEFAULT_SIMILAR_LIMIT) as usize;
+    similar_images(&conn, &uuid, &file_path, limit)
+}
@@ -0,0 +1,21 @@
+-- フォルダごとのデータベース（legacyとしてATTACH済み）の画像を統合データベースに取り込む
+-- 入れ子になった登録フォルダがある場合は、画像を含む最も深いフォルダに登録する
+INSERT OR IGNORE INTO images (folder_id, file_path, width, height, file_size, metadata_json, metadata_source,
+                              file_created_at, capture_time_source, created_at, updated_at)
+SELECT COALESCE((SELECT search_folders.id
+                  FROM search_folders
+                  WHERE substr(legacy.images.file_path, 1, length(search_folders.path) + 1)
+                      IN (search_folders.path || '/', search_folders.path || '\')
+                  ORDER BY length(search_folders.path) DESC
+                  LIMIT 1), ?1),
+       file_path,
+       width,
+       height,
+       file_size,
+       metadata_json,
+       metadata_source,
+       file_created_at,
+       capture_time_source,
+       created_at,
+       updated_at
+FROM legacy.images;
\ No newline at end of file
@@ -0,0 +1,10 @@
+-- フォルダごとのデータベース（legacyとしてATTACH済み）のサムネイルを取り込む
+-- （Base64の文字列またはPNGのバイト列で保存されているため、バイト列に戻して保存する）
+INSERT OR IGNORE INTO thumbnails (image_id, variant, mime, data)
+SELECT images.id,
+       'small',
+       CASE WHEN hex(substr(legacy_thumbnails.data, 1, 3)) = 'FFD8FF' THEN 'image/jpeg' ELSE 'image/png' END,
+       legacy_thumbnails.data
+FROM (SELECT file_path, decode_legacy_thumbnail(thumbnail) AS data FROM legacy.images) AS legacy_thumbnails
+         JOIN images ON images.file_path = legacy_thumbnails.file_path
+WHERE legacy_thumbnails.data IS NOT NULL;
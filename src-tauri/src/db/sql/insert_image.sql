@@ -1,9 +1,37 @@
 -- INSERT OR IGNORE INTO images (file_path, thumbnail, width, height, file_size,metadata_json,file_created_at, created_at, updated_at)
 --         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7,?8,?9);
 
-INSERT INTO images (file_path, thumbnail, width, height, file_size, metadata_json, file_created_at, created_at,
-                    updated_at)
-VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9) ON CONFLICT(file_path) DO
+-- 入れ子になった登録フォルダがある場合は、ファイルを含む最も深いフォルダに登録する（?11は見つからない場合の登録先）
+INSERT INTO images (file_path, width, height, file_size, metadata_json, file_created_at, created_at,
+                    updated_at, metadata_source, capture_time_source, dhash, phash, color_histogram, mtime_ns,
+                    inode, device, quick_hash, pixel_hash, folder_id)
+VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19,
+        COALESCE((SELECT id
+                  FROM search_folders
+                  WHERE substr(?1, 1, length(path) + 1) IN (path || '/', path || '\')
+                  ORDER BY length(path) DESC
+                  LIMIT 1), ?11)) ON CONFLICT(file_path) DO
 UPDATE SET
-    thumbnail = excluded.thumbnail,
+    folder_id = excluded.folder_id,
+    width = excluded.width,
+    height = excluded.height,
+    file_size = excluded.file_size,
+    file_created_at = excluded.file_created_at,
+    capture_time_source = excluded.capture_time_source,
+    dhash = excluded.dhash,
+    phash = excluded.phash,
+    color_histogram = excluded.color_histogram,
+    mtime_ns = excluded.mtime_ns,
+    inode = excluded.inode,
+    device = excluded.device,
+    quick_hash = excluded.quick_hash,
+    pixel_hash = excluded.pixel_hash,
+    -- 画像にメタデータが無い場合は、履歴から推定したメタデータを残す
+    -- :inferred_sources は MetadataSource::INFERRED のJSON配列（最後の引数として渡す）
+    metadata_json = CASE
+        WHEN excluded.metadata_source IS NULL AND images.metadata_source IN (SELECT value FROM json_each(:inferred_sources)) THEN images.metadata_json
+        ELSE excluded.metadata_json END,
+    metadata_source = CASE
+        WHEN excluded.metadata_source IS NULL AND images.metadata_source IN (SELECT value FROM json_each(:inferred_sources)) THEN images.metadata_source
+        ELSE excluded.metadata_source END,
     updated_at = excluded.updated_at;
\ No newline at end of file
@@ -0,0 +1,19 @@
+-- 全フォルダの画像を1つのテーブルで管理（フォルダ削除時は画像も削除）
+CREATE TABLE IF NOT EXISTS images (
+                                      id INTEGER PRIMARY KEY AUTOINCREMENT,
+                                      folder_id INTEGER NOT NULL REFERENCES search_folders (id) ON DELETE CASCADE,
+                                      file_path TEXT NOT NULL UNIQUE,
+                                      thumbnail TEXT,
+                                      width INTEGER,
+                                      height INTEGER,
+                                      file_size INTEGER,
+                                      metadata_json TEXT,
+                                      metadata_source TEXT,
+                                      file_created_at TEXT NOT NULL,
+                                      capture_time_source TEXT,
+                                      created_at TEXT NOT NULL,
+                                      updated_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_images_folder_id ON images (folder_id);
+CREATE INDEX IF NOT EXISTS idx_images_file_created_at ON images (file_created_at);
\ No newline at end of file
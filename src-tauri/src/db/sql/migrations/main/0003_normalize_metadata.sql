@@ -0,0 +1,129 @@
+-- メタデータJSONからワールド・インスタンス・プレイヤーを正規化したテーブル
+-- 検索時に毎回JSONを展開しなくて済むよう、images の更新に合わせてトリガーで維持する
+
+-- ワールド（名前は最後に登録された写真のもの）
+CREATE TABLE IF NOT EXISTS worlds (
+                                      world_id TEXT PRIMARY KEY,
+                                      name TEXT NOT NULL
+);
+
+-- インスタンス（wrld_xxx:12345~region(jp) 形式のID）
+CREATE TABLE IF NOT EXISTS instances (
+                                         instance_id TEXT PRIMARY KEY,
+                                         world_id TEXT NOT NULL
+);
+
+-- プレイヤー（表示名は最後に登録された写真のもの）
+CREATE TABLE IF NOT EXISTS players (
+                                       user_id TEXT PRIMARY KEY,
+                                       display_name TEXT NOT NULL
+);
+
+-- 写真に写っている（同じインスタンスにいた）プレイヤー
+-- 古いログ由来のデータにはユーザーIDが無いため、撮影時点の表示名も保持する
+CREATE TABLE IF NOT EXISTS image_players (
+                                             image_id INTEGER NOT NULL REFERENCES images (id) ON DELETE CASCADE,
+                                             user_id TEXT,
+                                             display_name TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_image_players_image_id ON image_players (image_id);
+CREATE INDEX IF NOT EXISTS idx_image_players_user_id ON image_players (user_id);
+CREATE INDEX IF NOT EXISTS idx_image_players_display_name ON image_players (display_name);
+
+ALTER TABLE images ADD COLUMN world_id TEXT;
+ALTER TABLE images ADD COLUMN instance_id TEXT;
+ALTER TABLE images ADD COLUMN author_id TEXT;
+-- 並び替え用（ワールド名は worlds の名前、ワールドIDが無い写真はメタデータの名前）
+ALTER TABLE images ADD COLUMN world_name TEXT NOT NULL DEFAULT '';
+ALTER TABLE images ADD COLUMN player_count INTEGER NOT NULL DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS idx_images_world_id ON images (world_id);
+CREATE INDEX IF NOT EXISTS idx_images_instance_id ON images (instance_id);
+CREATE INDEX IF NOT EXISTS idx_images_author_id ON images (author_id);
+-- 並び替えとカーソルの比較（並び替えキー, id）に使う
+CREATE INDEX IF NOT EXISTS idx_images_world_name ON images (world_name, id);
+CREATE INDEX IF NOT EXISTS idx_images_player_count ON images (player_count, id);
+
+-- メタデータJSONを正規化テーブルに反映する処理（INSERT時・UPDATE時のトリガーで共有する）
+-- このビューへのINSERTは INSTEAD OF トリガーで処理され、ビュー自体には何も保存されない
+CREATE VIEW IF NOT EXISTS image_metadata_sync AS
+SELECT id AS image_id, metadata_json
+FROM images;
+
+CREATE TRIGGER IF NOT EXISTS image_metadata_sync_insert
+    INSTEAD OF INSERT
+    ON image_metadata_sync
+BEGIN
+    INSERT INTO worlds (world_id, name)
+    SELECT json_extract(doc, '$.world.id'), coalesce(json_extract(doc, '$.world.name'), '')
+    FROM (SELECT CASE WHEN json_valid(NEW.metadata_json) THEN NEW.metadata_json END AS doc)
+    WHERE coalesce(json_extract(doc, '$.world.id'), '') != ''
+    ON CONFLICT (world_id) DO UPDATE SET name = excluded.name
+    WHERE excluded.name != '';
+
+    INSERT OR IGNORE INTO instances (instance_id, world_id)
+    SELECT json_extract(doc, '$.world.instanceId'), coalesce(json_extract(doc, '$.world.id'), '')
+    FROM (SELECT CASE WHEN json_valid(NEW.metadata_json) THEN NEW.metadata_json END AS doc)
+    WHERE coalesce(json_extract(doc, '$.world.instanceId'), '') != '';
+
+    INSERT INTO players (user_id, display_name)
+    SELECT json_extract(doc, '$.author.id'), coalesce(json_extract(doc, '$.author.displayName'), '')
+    FROM (SELECT CASE WHEN json_valid(NEW.metadata_json) THEN NEW.metadata_json END AS doc)
+    WHERE coalesce(json_extract(doc, '$.author.id'), '') != ''
+    ON CONFLICT (user_id) DO UPDATE SET display_name = excluded.display_name
+    WHERE excluded.display_name != '';
+
+    -- 要素がオブジェクトでない場合に備え、value ではなく元のJSONをパスで参照する
+    INSERT INTO players (user_id, display_name)
+    SELECT json_extract(doc, fullkey || '.id'), coalesce(json_extract(doc, fullkey || '.displayName'), '')
+    FROM (SELECT CASE WHEN json_valid(NEW.metadata_json) THEN NEW.metadata_json END AS doc),
+         json_each(doc, '$.players')
+    WHERE coalesce(json_extract(doc, fullkey || '.id'), '') != ''
+    ON CONFLICT (user_id) DO UPDATE SET display_name = excluded.display_name
+    WHERE excluded.display_name != '';
+
+    INSERT INTO image_players (image_id, user_id, display_name)
+    SELECT DISTINCT NEW.image_id,
+                    nullif(json_extract(doc, fullkey || '.id'), ''),
+                    json_extract(doc, fullkey || '.displayName')
+    FROM (SELECT CASE WHEN json_valid(NEW.metadata_json) THEN NEW.metadata_json END AS doc),
+         json_each(doc, '$.players')
+    WHERE coalesce(json_extract(doc, fullkey || '.displayName'), '') != '';
+
+    UPDATE images
+    SET world_id     = nullif(json_extract(doc, '$.world.id'), ''),
+        instance_id  = nullif(json_extract(doc, '$.world.instanceId'), ''),
+        author_id    = nullif(json_extract(doc, '$.author.id'), ''),
+        world_name   = coalesce((SELECT name FROM worlds WHERE world_id = json_extract(doc, '$.world.id')),
+                                json_extract(doc, '$.world.name'), ''),
+        player_count = (SELECT count(*) FROM image_players WHERE image_id = NEW.image_id)
+    FROM (SELECT CASE WHEN json_valid(NEW.metadata_json) THEN NEW.metadata_json END AS doc)
+    WHERE id = NEW.image_id;
+END;
+
+-- ワールド名が変わった場合は、同じワールドの写真の並び替え用の名前も更新する
+CREATE TRIGGER IF NOT EXISTS worlds_name_update
+    AFTER UPDATE OF name
+    ON worlds
+BEGIN
+    UPDATE images SET world_name = NEW.name WHERE world_id = NEW.world_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS images_metadata_insert
+    AFTER INSERT
+    ON images
+BEGIN
+    INSERT INTO image_metadata_sync (image_id, metadata_json) VALUES (NEW.id, NEW.metadata_json);
+END;
+
+CREATE TRIGGER IF NOT EXISTS images_metadata_update
+    AFTER UPDATE OF metadata_json
+    ON images
+BEGIN
+    DELETE FROM image_players WHERE image_id = NEW.id;
+    INSERT INTO image_metadata_sync (image_id, metadata_json) VALUES (NEW.id, NEW.metadata_json);
+END;
+
+-- 登録済みの画像にもトリガーを適用する
+UPDATE images SET metadata_json = metadata_json;
\ No newline at end of file
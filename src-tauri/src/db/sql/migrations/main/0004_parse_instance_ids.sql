@@ -0,0 +1,10 @@
+-- インスタンスIDを分解した公開範囲・リージョン・作成者・nonce
+-- 値は登録後にアプリ側でインスタンスIDを解析して設定する（access_type が NULL の行が未解析）
+ALTER TABLE instances ADD COLUMN access_type TEXT;
+ALTER TABLE instances ADD COLUMN region TEXT;
+ALTER TABLE instances ADD COLUMN owner_id TEXT;
+ALTER TABLE instances ADD COLUMN nonce TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_instances_access_type ON instances (access_type);
+CREATE INDEX IF NOT EXISTS idx_instances_region ON instances (region);
+CREATE INDEX IF NOT EXISTS idx_instances_owner_id ON instances (owner_id);
@@ -0,0 +1,45 @@
+-- 同じインスタンスで続けて撮影された写真のまとまり（滞在）
+-- 分け方は撮影間隔のしきい値で変わるため、アプリ側でクラスタリングして作り直す
+CREATE TABLE IF NOT EXISTS sessions (
+                                        id INTEGER PRIMARY KEY AUTOINCREMENT,
+                                        instance_id TEXT NOT NULL,
+                                        world_id TEXT,
+                                        started_at TEXT NOT NULL,
+                                        ended_at TEXT NOT NULL,
+                                        image_count INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_sessions_instance_id ON sessions (instance_id);
+CREATE INDEX IF NOT EXISTS idx_sessions_started_at ON sessions (started_at);
+
+-- 滞在中に写真に写っていたプレイヤー（各写真の同席者の和集合）
+CREATE TABLE IF NOT EXISTS session_players (
+                                               session_id INTEGER NOT NULL REFERENCES sessions (id) ON DELETE CASCADE,
+                                               user_id TEXT,
+                                               display_name TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_session_players_session_id ON session_players (session_id);
+
+-- NULL の写真は次のクラスタリングで同じインスタンスの滞在ごと作り直す
+ALTER TABLE images ADD COLUMN session_id INTEGER REFERENCES sessions (id) ON DELETE SET NULL;
+
+CREATE INDEX IF NOT EXISTS idx_images_session_id ON images (session_id);
+
+-- インスタンスや撮影日時が変わった写真、削除された写真を含む滞在は作り直す
+CREATE TRIGGER IF NOT EXISTS images_session_update
+    AFTER UPDATE OF instance_id, file_created_at
+    ON images
+    WHEN OLD.session_id IS NOT NULL
+        AND (OLD.instance_id IS NOT NEW.instance_id OR OLD.file_created_at IS NOT NEW.file_created_at)
+BEGIN
+    DELETE FROM sessions WHERE id = OLD.session_id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS images_session_delete
+    AFTER DELETE
+    ON images
+    WHEN OLD.session_id IS NOT NULL
+BEGIN
+    DELETE FROM sessions WHERE id = OLD.session_id;
+END;
@@ -0,0 +1,20 @@
+-- ユーザーが付けたタグ・お気に入り・評価
+-- 再スキャンやフォルダの削除・再登録で images の行が作り直されても残るよう、ファイルパスで紐づける
+CREATE TABLE IF NOT EXISTS image_tags (
+                                          file_path TEXT NOT NULL,
+                                          tag TEXT NOT NULL,
+                                          created_at TEXT NOT NULL,
+                                          PRIMARY KEY (file_path, tag)
+);
+
+CREATE INDEX IF NOT EXISTS idx_image_tags_tag ON image_tags (tag);
+
+CREATE TABLE IF NOT EXISTS image_annotations (
+                                                 file_path TEXT PRIMARY KEY,
+                                                 favorite INTEGER NOT NULL DEFAULT 0,
+                                                 rating INTEGER CHECK (rating BETWEEN 1 AND 5),
+                                                 updated_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_image_annotations_favorite ON image_annotations (favorite);
+CREATE INDEX IF NOT EXISTS idx_image_annotations_rating ON image_annotations (rating);
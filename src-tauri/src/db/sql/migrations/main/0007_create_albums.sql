@@ -0,0 +1,23 @@
+-- アルバム
+-- 手動アルバムは album_images に画像を並べ、スマートアルバムは保存した検索条件を開くたびに評価する
+CREATE TABLE IF NOT EXISTS albums (
+                                      id INTEGER PRIMARY KEY AUTOINCREMENT,
+                                      name TEXT NOT NULL,
+                                      smart INTEGER NOT NULL DEFAULT 0,
+                                      query TEXT,           -- クエリ文字列（スマートアルバム）
+                                      conditions_json TEXT, -- 検索画面の条件リスト（スマートアルバム）
+                                      position INTEGER NOT NULL,
+                                      created_at TEXT NOT NULL,
+                                      updated_at TEXT NOT NULL
+);
+
+-- 手動アルバムの画像（タグと同じく、images の行が作り直されても残るようファイルパスで紐づける）
+CREATE TABLE IF NOT EXISTS album_images (
+                                            album_id INTEGER NOT NULL REFERENCES albums (id) ON DELETE CASCADE,
+                                            file_path TEXT NOT NULL,
+                                            position INTEGER NOT NULL,
+                                            added_at TEXT NOT NULL,
+                                            PRIMARY KEY (album_id, file_path)
+);
+
+CREATE INDEX IF NOT EXISTS idx_album_images_position ON album_images (album_id, position);
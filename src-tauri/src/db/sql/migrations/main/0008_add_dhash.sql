@@ -0,0 +1,5 @@
+-- 重複検出用の知覚ハッシュ（dHash、64ビットを符号付き整数として保存）
+-- 以前のバージョンで登録された画像は、重複検出の前にサムネイルから計算する
+ALTER TABLE images ADD COLUMN dhash INTEGER;
+
+CREATE INDEX IF NOT EXISTS idx_images_dhash ON images (dhash);
@@ -0,0 +1,6 @@
+-- 類似画像の検索に使う見た目の特徴（サムネイルから計算する）
+-- phash: 知覚ハッシュ（64ビットを符号付き整数として保存）
+-- color_histogram: RGB各4段階の色ヒストグラム（f32のリトルエンディアン×64）
+-- 登録時に計算し、以前のバージョンで登録された画像は起動時に計算する
+ALTER TABLE images ADD COLUMN phash INTEGER;
+ALTER TABLE images ADD COLUMN color_histogram BLOB;
@@ -0,0 +1,77 @@
+-- ワールド名・撮影者・同席者・ファイル名・タグの全文検索
+-- 日本語の名前も部分一致で探せるよう trigram トークナイザーを使う（rowid は images.id）
+CREATE VIRTUAL TABLE IF NOT EXISTS images_fts USING fts5(
+    world,
+    author,
+    players,
+    file_name,
+    tags,
+    tokenize = 'trigram'
+);
+
+-- 全文検索に登録する内容
+CREATE VIEW IF NOT EXISTS images_fts_source AS
+SELECT images.id,
+       coalesce(nullif(json_extract(doc, '$.world.name'), ''), worlds.name, '')                   AS world,
+       coalesce(json_extract(doc, '$.author.displayName'), '')                                   AS author,
+       coalesce((SELECT group_concat(json_extract(doc, fullkey || '.displayName'), ' ')
+                 FROM json_each(doc, '$.players')), '')                                          AS players,
+       -- 区切り文字より後ろ（Windowsの \ も / とみなす）
+       replace(path, rtrim(path, replace(path, '/', '')), '')                                    AS file_name,
+       coalesce((SELECT group_concat(tag, ' ') FROM image_tags
+                 WHERE image_tags.file_path = images.file_path), '')                             AS tags
+FROM images
+         JOIN (SELECT id AS doc_id,
+                      CASE WHEN json_valid(metadata_json) THEN metadata_json END AS doc,
+                      replace(file_path, '\', '/')                               AS path
+               FROM images) AS source ON source.doc_id = images.id
+         LEFT JOIN worlds ON worlds.world_id = images.world_id;
+
+CREATE TRIGGER IF NOT EXISTS images_fts_insert
+    AFTER INSERT
+    ON images
+BEGIN
+    INSERT INTO images_fts (rowid, world, author, players, file_name, tags)
+    SELECT * FROM images_fts_source WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS images_fts_update
+    AFTER UPDATE OF metadata_json, file_path
+    ON images
+BEGIN
+    DELETE FROM images_fts WHERE rowid = OLD.id;
+    INSERT INTO images_fts (rowid, world, author, players, file_name, tags)
+    SELECT * FROM images_fts_source WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS images_fts_delete
+    AFTER DELETE
+    ON images
+BEGIN
+    DELETE FROM images_fts WHERE rowid = OLD.id;
+END;
+
+-- タグはファイルパスで紐づくため、同じパスの画像を登録し直す
+CREATE TRIGGER IF NOT EXISTS image_tags_fts_insert
+    AFTER INSERT
+    ON image_tags
+BEGIN
+    DELETE FROM images_fts WHERE rowid IN (SELECT id FROM images WHERE file_path = NEW.file_path);
+    INSERT INTO images_fts (rowid, world, author, players, file_name, tags)
+    SELECT images_fts_source.* FROM images_fts_source
+    JOIN images ON images.id = images_fts_source.id WHERE images.file_path = NEW.file_path;
+END;
+
+CREATE TRIGGER IF NOT EXISTS image_tags_fts_delete
+    AFTER DELETE
+    ON image_tags
+BEGIN
+    DELETE FROM images_fts WHERE rowid IN (SELECT id FROM images WHERE file_path = OLD.file_path);
+    INSERT INTO images_fts (rowid, world, author, players, file_name, tags)
+    SELECT images_fts_source.* FROM images_fts_source
+    JOIN images ON images.id = images_fts_source.id WHERE images.file_path = OLD.file_path;
+END;
+
+-- 登録済みの画像を全文検索に登録する
+INSERT INTO images_fts (rowid, world, author, players, file_name, tags)
+SELECT * FROM images_fts_source;
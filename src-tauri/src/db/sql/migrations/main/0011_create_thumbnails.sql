@@ -0,0 +1,21 @@
+-- サムネイルを画像の行から分け、バイト列のまま保存する
+-- （画像を検索・集計するたびにサムネイルのデータを読み込まないようにする）
+CREATE TABLE IF NOT EXISTS thumbnails
+(
+    image_id INTEGER NOT NULL REFERENCES images (id) ON DELETE CASCADE,
+    variant  TEXT    NOT NULL, -- 大きさの種類（'small' = 一覧表示用の長辺256px）
+    mime     TEXT    NOT NULL,
+    data     BLOB    NOT NULL,
+    PRIMARY KEY (image_id, variant)
+) WITHOUT ROWID;
+
+-- 既存のサムネイル（Base64の文字列またはPNGのバイト列）を移す。読めないものは次回のスキャンで作り直す
+INSERT INTO thumbnails (image_id, variant, mime, data)
+SELECT id,
+       'small',
+       CASE WHEN hex(substr(data, 1, 3)) = 'FFD8FF' THEN 'image/jpeg' ELSE 'image/png' END,
+       data
+FROM (SELECT id, decode_legacy_thumbnail(thumbnail) AS data FROM images WHERE thumbnail IS NOT NULL)
+WHERE data IS NOT NULL;
+
+ALTER TABLE images DROP COLUMN thumbnail;
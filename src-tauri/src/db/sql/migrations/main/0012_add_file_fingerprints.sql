@@ -0,0 +1,11 @@
+-- 再スキャンで画像を読み直さずに変更を判定するためのファイルの指紋
+-- mtime_ns: 更新日時（UNIX時間のナノ秒）
+-- inode, device: inode番号とデバイス番号（取得できない環境では NULL）
+-- quick_hash: 先頭と末尾の64KBとファイルサイズのハッシュ
+-- pixel_hash: PNGの画素のチャンク（IHDR・PLTE・IDAT）のハッシュ（メタデータだけの変更の判定に使う）
+-- 64ビットの値は符号付き整数として保存する。以前のバージョンで登録した画像は NULL で、次のスキャンで記録する
+ALTER TABLE images ADD COLUMN mtime_ns INTEGER;
+ALTER TABLE images ADD COLUMN inode INTEGER;
+ALTER TABLE images ADD COLUMN device INTEGER;
+ALTER TABLE images ADD COLUMN quick_hash INTEGER;
+ALTER TABLE images ADD COLUMN pixel_hash INTEGER;
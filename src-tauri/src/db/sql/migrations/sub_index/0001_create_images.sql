@@ -1,3 +1,4 @@
+-- フォルダごとのインデックス（統合データベースへの移行前の形式）
 CREATE TABLE IF NOT EXISTS images (
                                       id INTEGER PRIMARY KEY AUTOINCREMENT,
                                       file_path TEXT NOT NULL UNIQUE,
@@ -0,0 +1 @@
+ALTER TABLE images ADD COLUMN metadata_source TEXT;
\ No newline at end of file
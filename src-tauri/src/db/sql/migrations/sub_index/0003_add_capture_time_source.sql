@@ -0,0 +1 @@
+ALTER TABLE images ADD COLUMN capture_time_source TEXT;
\ No newline at end of file
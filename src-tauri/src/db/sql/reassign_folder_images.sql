@@ -0,0 +1,11 @@
+-- 削除するフォルダ（?1）の画像のうち、他の登録フォルダにも含まれる画像を最も深いフォルダへ付け替える
+-- 付け替え先が無い画像はフォルダの削除時に外部キー制約（ON DELETE CASCADE）で削除される
+UPDATE images
+SET folder_id = COALESCE((SELECT search_folders.id
+                          FROM search_folders
+                          WHERE search_folders.id != ?1
+                            AND substr(images.file_path, 1, length(search_folders.path) + 1)
+                                IN (search_folders.path || '/', search_folders.path || '\')
+                          ORDER BY length(search_folders.path) DESC
+                          LIMIT 1), folder_id)
+WHERE folder_id = ?1;
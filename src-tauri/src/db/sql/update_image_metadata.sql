@@ -0,0 +1,16 @@
+-- 画素の変わっていない画像のメタデータだけを登録し直す（サムネイルと類似画像の特徴はそのまま）
+UPDATE images
+SET
+    file_created_at = ?4,
+    capture_time_source = ?5,
+    updated_at = ?6,
+    -- 画像にメタデータが無い場合は、履歴から推定したメタデータを残す
+    -- :inferred_sources は MetadataSource::INFERRED のJSON配列（最後の引数として渡す）
+    -- 名前付きの引数は先に現れた番号の続きになるため、?6 より後に書いて7番目にする
+    metadata_json = CASE
+        WHEN ?3 IS NULL AND metadata_source IN (SELECT value FROM json_each(:inferred_sources)) THEN metadata_json
+        ELSE ?2 END,
+    metadata_source = CASE
+        WHEN ?3 IS NULL AND metadata_source IN (SELECT value FROM json_each(:inferred_sources)) THEN metadata_source
+        ELSE ?3 END
+WHERE file_path = ?1;
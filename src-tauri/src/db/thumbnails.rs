@@ -0,0 +1,192 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageResult};
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection, OptionalExtension, Result};
+
+/// 一覧表示用のサムネイル（長辺256px）
+pub(crate) const THUMBNAIL_VARIANT: &str = "small";
+/// サムネイルの長辺の最大値
+const THUMBNAIL_SIZE: u32 = 256;
+/// 新しく作るサムネイルの形式
+pub(crate) const THUMBNAIL_MIME: &str = "image/jpeg";
+const JPEG_QUALITY: u8 = 85;
+
+/// 画像と一緒に一覧表示用のサムネイル（`thumbnails.mime`, `thumbnails.data`）を取得するための結合
+pub(crate) const THUMBNAIL_JOIN: &str = "LEFT JOIN thumbnails ON thumbnails.image_id = images.id \
+                                         AND thumbnails.variant = 'small'";
+
+const UPSERT_THUMBNAIL: &str = "INSERT INTO thumbnails (image_id, variant, mime, data)
+     SELECT id, ?2, ?3, ?4 FROM images WHERE file_path = ?1
+     ON CONFLICT(image_id, variant) DO UPDATE SET mime = excluded.mime, data = excluded.data";
+
+/// 一覧表示用のサムネイルをJPEGで作る
+pub(crate) fn encode_thumbnail(image: &DynamicImage) -> ImageResult<Vec<u8>> {
+    // JPEGは透過を扱えないためRGBにしてから圧縮する
+    let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8();
+    let mut buffer = Vec::new();
+    JpegEncoder::new_with_quality(&mut buffer, JPEG_QUALITY).encode_image(&thumbnail)?;
+    Ok(buffer)
+}
+
+/// 登録済みの画像のサムネイルを保存する（既にあれば置き換える）
+pub(crate) fn save_thumbnail(conn: &Connection, file_path: &str, data: &[u8]) -> Result<()> {
+    conn.execute(
+        UPSERT_THUMBNAIL,
+        params![file_path, THUMBNAIL_VARIANT, THUMBNAIL_MIME, data],
+    )?;
+    Ok(())
+}
+
+/// 保存済みのサムネイルを読み込む（無い場合や壊れている場合は `None`）
+pub(crate) fn load_thumbnail(conn: &Connection, image_id: i64) -> Result<Option<DynamicImage>> {
+    let data: Option<Vec<u8>> = conn
+        .query_row(
+            "SELECT data FROM thumbnails WHERE image_id = ?1 AND variant = ?2",
+            params![image_id, THUMBNAIL_VARIANT],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(data.and_then(|data| image::load_from_memory(&data).ok()))
+}
+
+/// 画面に返すサムネイル
+pub(crate) struct StoredThumbnail {
+    pub mime: String,
+    pub data: Vec<u8>,
+    pub updated_at: String, // 画像を登録（更新）した日時
+}
+
+/// 画像IDから一覧表示用のサムネイルを取得する
+pub(crate) fn thumbnail_by_image_id(
+    conn: &Connection,
+    image_id: i64,
+) -> Result<Option<StoredThumbnail>> {
+    conn.query_row(
+        "SELECT thumbnails.mime, thumbnails.data, images.updated_at FROM thumbnails
+         JOIN images ON images.id = thumbnails.image_id
+         WHERE thumbnails.image_id = ?1 AND thumbnails.variant = ?2",
+        params![image_id, THUMBNAIL_VARIANT],
+        |row| {
+            Ok(StoredThumbnail {
+                mime: row.get(0)?,
+                data: row.get(1)?,
+                updated_at: row.get(2)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// 以前のバージョンで `images.thumbnail` に保存していたサムネイルをバイト列に戻す
+///
+/// 一括登録ではBase64の文字列、ファイル監視ではPNGのバイト列をそのまま保存していたため、両方を受け付ける。
+fn decode_legacy_thumbnail(value: ValueRef) -> Option<Vec<u8>> {
+    let data = match value {
+        ValueRef::Blob(data) => data.to_vec(),
+        ValueRef::Text(text) => STANDARD.decode(text.trim_ascii()).ok()?,
+        _ => return None,
+    };
+    (!data.is_empty()).then_some(data)
+}
+
+/// マイグレーションで使う関数 `decode_legacy_thumbnail(thumbnail)` を登録する
+pub(crate) fn register_legacy_thumbnail_decoder(conn: &Connection) -> Result<()> {
+    conn.create_scalar_function(
+        "decode_legacy_thumbnail",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(decode_legacy_thumbnail(ctx.get_raw(0))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::migration::migrate;
+    use crate::db::query::Queries;
+    use image::{ImageFormat, Rgb, RgbImage};
+
+    fn png() -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(RgbImage::from_pixel(8, 8, Rgb([10, 200, 30])));
+        let mut buffer = Vec::new();
+        image
+            .write_to(&mut std::io::Cursor::new(&mut buffer), ImageFormat::Png)
+            .unwrap();
+        buffer
+    }
+
+    #[test]
+    fn moves_legacy_thumbnails_to_table() {
+        let queries = Queries::load();
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "foreign_keys", "ON").unwrap();
+        // サムネイルを移行する 0011_create_thumbnails の前まで適用する
+        migrate(&mut conn, &queries.migrations[..10]).unwrap();
+        conn.execute_batch(
+            "INSERT INTO search_folders (path, uuid) VALUES ('/photos', 'u');
+             INSERT INTO images (folder_id, file_path, file_created_at, created_at, updated_at)
+             VALUES (1, '/photos/base64.png', 't', 't', 't'), (1, '/photos/blob.png', 't', 't', 't'),
+                    (1, '/photos/broken.png', 't', 't', 't');
+             UPDATE images SET thumbnail = 'not base64!' WHERE file_path = '/photos/broken.png';",
+        )
+        .unwrap();
+        conn.execute(
+            "UPDATE images SET thumbnail = ?1 WHERE file_path = '/photos/base64.png'",
+            [STANDARD.encode(png())],
+        )
+        .unwrap();
+        conn.execute(
+            "UPDATE images SET thumbnail = ?1 WHERE file_path = '/photos/blob.png'",
+            [png()],
+        )
+        .unwrap();
+
+        migrate(&mut conn, queries.migrations).unwrap();
+        let thumbnails: Vec<(String, String, Vec<u8>)> = conn
+            .prepare(
+                "SELECT images.file_path, thumbnails.mime, thumbnails.data FROM thumbnails
+                 JOIN images ON images.id = thumbnails.image_id ORDER BY images.file_path",
+            )
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(
+            thumbnails,
+            vec![
+                (
+                    "/photos/base64.png".to_string(),
+                    "image/png".to_string(),
+                    png()
+                ),
+                (
+                    "/photos/blob.png".to_string(),
+                    "image/png".to_string(),
+                    png()
+                ),
+            ]
+        );
+        assert!(load_thumbnail(&conn, 1).unwrap().is_some());
+
+        // 新しいサムネイルはJPEGで置き換わり、画像を削除すると一緒に消える
+        let jpeg = encode_thumbnail(&image::load_from_memory(&png()).unwrap()).unwrap();
+        save_thumbnail(&conn, "/photos/blob.png", &jpeg).unwrap();
+        let mime: String = conn
+            .query_row(
+                "SELECT mime FROM thumbnails WHERE image_id = 2",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(mime, THUMBNAIL_MIME);
+        conn.execute("DELETE FROM images", []).unwrap();
+        let count: i64 = conn
+            .query_row("SELECT count(*) FROM thumbnails", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+}
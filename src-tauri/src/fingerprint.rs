@@ -0,0 +1,174 @@
+//! 画像をデコードせずにファイルの変更を見分けるための指紋
+
+use std::fs::{File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// クイックハッシュに使う先頭・末尾の長さ
+const QUICK_HASH_SPAN: u64 = 64 * 1024;
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// ファイルシステムから分かるファイルの状態（ファイルを読まずに取得できる）
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FileStat {
+    pub size: u64,
+    /// 更新日時（UNIX時間のナノ秒）
+    pub mtime_ns: i64,
+    /// inode番号とデバイス番号（取得できない環境では `None`）
+    pub inode: Option<i64>,
+    pub device: Option<i64>,
+}
+
+impl FileStat {
+    pub(crate) fn from_metadata(metadata: &Metadata) -> FileStat {
+        let mtime_ns = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_nanos() as i64)
+            .unwrap_or_default();
+        #[cfg(unix)]
+        let (inode, device) = {
+            use std::os::unix::fs::MetadataExt;
+            (Some(metadata.ino() as i64), Some(metadata.dev() as i64))
+        };
+        #[cfg(not(unix))]
+        let (inode, device) = (None, None);
+        FileStat {
+            size: metadata.len(),
+            mtime_ns,
+            inode,
+            device,
+        }
+    }
+}
+
+/// FNV-1a（保存した値と比べるため、Rustのバージョンで変わらないハッシュを使う）
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+fn quick_hash_parts(head: &[u8], tail: &[u8], size: u64) -> u64 {
+    fnv1a(fnv1a(fnv1a(FNV_OFFSET, &size.to_le_bytes()), head), tail)
+}
+
+/// 先頭と末尾の64KBとファイルサイズから求めるハッシュ（読み込み済みのデータから求める）
+pub(crate) fn quick_hash(data: &[u8]) -> u64 {
+    let head = &data[..data.len().min(QUICK_HASH_SPAN as usize)];
+    let tail_start = data
+        .len()
+        .saturating_sub(QUICK_HASH_SPAN as usize)
+        .max(head.len());
+    quick_hash_parts(head, &data[tail_start..], data.len() as u64)
+}
+
+/// [`quick_hash`] と同じ値を、ファイルの先頭と末尾だけを読んで求める
+pub(crate) fn quick_hash_file(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut head = vec![0; size.min(QUICK_HASH_SPAN) as usize];
+    file.read_exact(&mut head)?;
+    let tail_start = size.saturating_sub(QUICK_HASH_SPAN).max(head.len() as u64);
+    let mut tail = vec![0; (size - tail_start) as usize];
+    file.seek(SeekFrom::Start(tail_start))?;
+    file.read_exact(&mut tail)?;
+    Ok(quick_hash_parts(&head, &tail, size))
+}
+
+/// PNGの画素に関わるチャンク（IHDR・PLTE・IDAT）のハッシュ
+///
+/// 各チャンクに記録されたCRCから求めるため、チャンクの本体は読み飛ばす。
+/// テキストチャンク（VRCXのメタデータなど）だけを書き換えた場合は変わらない。
+/// PNGでない場合や画素のデータが無い場合は `None`。
+pub(crate) fn pixel_hash<R: Read + Seek>(mut reader: R) -> Option<u64> {
+    let mut signature = [0; 8];
+    reader.read_exact(&mut signature).ok()?;
+    if signature != PNG_SIGNATURE {
+        return None;
+    }
+    let mut hash = FNV_OFFSET;
+    let mut has_pixels = false;
+    loop {
+        // 長さ（4バイト）と種類（4バイト）
+        let mut header = [0; 8];
+        if reader.read_exact(&mut header).is_err() {
+            break;
+        }
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let kind = &header[4..];
+        if kind == b"IEND" {
+            break;
+        }
+        let mut crc = [0; 4];
+        if reader.seek(SeekFrom::Current(length as i64)).is_err()
+            || reader.read_exact(&mut crc).is_err()
+        {
+            break;
+        }
+        if matches!(kind, b"IHDR" | b"PLTE" | b"IDAT") {
+            hash = fnv1a(fnv1a(hash, &header), &crc);
+            has_pixels |= kind == b"IDAT";
+        }
+    }
+    has_pixels.then_some(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn png(pixel: u8, description: &str) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, 4, 4);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder
+            .add_itxt_chunk("Description".to_string(), description.to_string())
+            .unwrap();
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[pixel; 16]).unwrap();
+        writer.finish().unwrap();
+        data
+    }
+
+    #[test]
+    fn pixel_hash_ignores_text_chunks() {
+        let original = pixel_hash(Cursor::new(png(10, "{\"world\":\"A\"}"))).unwrap();
+        let retagged = pixel_hash(Cursor::new(png(10, "{\"world\":\"B\"}"))).unwrap();
+        let repainted = pixel_hash(Cursor::new(png(200, "{\"world\":\"A\"}"))).unwrap();
+        assert_eq!(original, retagged);
+        assert_ne!(original, repainted);
+        assert_eq!(
+            pixel_hash(Cursor::new(b"\xFF\xD8\xFF\xE0 jpeg".to_vec())),
+            None
+        );
+    }
+
+    #[test]
+    fn quick_hash_matches_file_read() {
+        let dir = std::env::temp_dir().join(format!(
+            "vrcxphotosearcher-fingerprint-{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        for size in [10usize, 100 * 1024, 300 * 1024] {
+            let data: Vec<u8> = (0..size).map(|i| (i * 31 % 251) as u8).collect();
+            let path = dir.join(format!("{}.bin", size));
+            std::fs::write(&path, &data).unwrap();
+            assert_eq!(quick_hash_file(&path).unwrap(), quick_hash(&data));
+
+            // 中央だけの変更は見逃すが、先頭・末尾の変更は検出する
+            let mut changed = data.clone();
+            changed[0] ^= 1;
+            assert_ne!(quick_hash(&changed), quick_hash(&data));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
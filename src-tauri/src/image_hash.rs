@@ -0,0 +1,157 @@
+//! 画像の見た目が近いかを比較するための知覚ハッシュ
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+/// 差分ハッシュ（dHash）
+///
+/// 9x8のグレースケールに縮小し、横に隣り合う画素の明るさの大小を64ビットに並べる。
+/// 解像度の違いや再圧縮ではほとんど変わらないため、同じ写真のコピーを見つけるのに使う。
+pub(crate) fn dhash(image: &DynamicImage) -> u64 {
+    // 大きな画像を直接縮小すると遅いため、先に粗く縮小する
+    let small = image
+        .thumbnail(64, 64)
+        .resize_exact(9, 8, FilterType::Triangle)
+        .to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash <<= 1;
+            if small.get_pixel(x, y)[0] < small.get_pixel(x + 1, y)[0] {
+                hash |= 1;
+            }
+        }
+    }
+    hash
+}
+
+/// 2つのハッシュで異なるビットの数
+pub(crate) fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// 知覚ハッシュ（pHash）
+///
+/// 32x32のグレースケールに縮小して離散コサイン変換し、低周波の8x8成分が中央値より大きいかを
+/// 64ビットに並べる。dHashより構図の似た別の写真を見分けやすいため、類似画像の検索に使う。
+pub(crate) fn phash(image: &DynamicImage) -> u64 {
+    const SIZE: usize = 32;
+    const LOW: usize = 8;
+    let small = image
+        .resize_exact(SIZE as u32, SIZE as u32, FilterType::Triangle)
+        .to_luma8();
+    let pixels: Vec<f64> = small.pixels().map(|p| p[0] as f64).collect();
+
+    // 低周波成分だけを計算する
+    let cosines: Vec<f64> = (0..LOW)
+        .flat_map(|u| {
+            (0..SIZE).map(move |x| {
+                (std::f64::consts::PI * u as f64 * (2 * x + 1) as f64 / (2 * SIZE) as f64).cos()
+            })
+        })
+        .collect();
+    let mut coefficients = Vec::with_capacity(LOW * LOW);
+    for v in 0..LOW {
+        for u in 0..LOW {
+            let mut sum = 0.0;
+            for y in 0..SIZE {
+                for x in 0..SIZE {
+                    sum += pixels[y * SIZE + x] * cosines[u * SIZE + x] * cosines[v * SIZE + y];
+                }
+            }
+            coefficients.push(sum);
+        }
+    }
+
+    // 直流成分（画像全体の明るさ）は中央値の計算から除く
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+    coefficients
+        .iter()
+        .fold(0u64, |hash, &c| (hash << 1) | (c > median) as u64)
+}
+
+/// 色ヒストグラムの1チャンネルあたりの区間数
+const HISTOGRAM_LEVELS: usize = 4;
+/// 色ヒストグラムの区間数（RGB各4段階）
+pub(crate) const HISTOGRAM_BINS: usize = HISTOGRAM_LEVELS * HISTOGRAM_LEVELS * HISTOGRAM_LEVELS;
+
+/// 画素数で正規化した色ヒストグラム（合計が1）
+pub(crate) fn color_histogram(image: &DynamicImage) -> Vec<f32> {
+    let small = image.thumbnail(64, 64).to_rgb8();
+    let mut histogram = vec![0f32; HISTOGRAM_BINS];
+    let level = |v: u8| v as usize * HISTOGRAM_LEVELS / 256;
+    for pixel in small.pixels() {
+        let [r, g, b] = pixel.0;
+        histogram[(level(r) * HISTOGRAM_LEVELS + level(g)) * HISTOGRAM_LEVELS + level(b)] += 1.0;
+    }
+    let total = (small.width() * small.height()).max(1) as f32;
+    histogram.iter_mut().for_each(|count| *count /= total);
+    histogram
+}
+
+/// 2つの色ヒストグラムの違い（0〜1、共通部分の割合を1から引いたもの）
+pub(crate) fn histogram_distance(a: &[f32], b: &[f32]) -> f32 {
+    1.0 - a.iter().zip(b).map(|(x, y)| x.min(*y)).sum::<f32>()
+}
+
+/// 色ヒストグラムを保存用のバイト列（f32のリトルエンディアン）にする
+pub(crate) fn histogram_to_bytes(histogram: &[f32]) -> Vec<u8> {
+    histogram.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+pub(crate) fn histogram_from_bytes(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let v = ((x * 255 / width) ^ (y * 64 / height)) as u8;
+            Rgb([v, v / 2, 255 - v])
+        }))
+    }
+
+    #[test]
+    fn resized_copy_has_close_hash() {
+        let original = gradient(640, 360);
+        let copy = original.resize_exact(320, 180, FilterType::Nearest);
+        assert!(hamming_distance(dhash(&original), dhash(&copy)) <= 4);
+
+        let flipped = original.fliph();
+        assert!(hamming_distance(dhash(&original), dhash(&flipped)) > 16);
+    }
+
+    /// 写真のようになめらかに変化する画像
+    fn waves(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+            let (fx, fy) = (x as f32 / width as f32, y as f32 / height as f32);
+            let v = (128.0 + 100.0 * (fx * 6.0).sin() * (fy * 4.0).cos()) as u8;
+            Rgb([v, 255 - v, (fx * 255.0) as u8])
+        }))
+    }
+
+    #[test]
+    fn similar_images_have_close_descriptors() {
+        let original = waves(640, 360);
+        let copy = original.resize_exact(320, 180, FilterType::Triangle);
+        assert!(hamming_distance(phash(&original), phash(&copy)) <= 8);
+        assert!(hamming_distance(phash(&original), phash(&original.fliph())) > 20);
+
+        let histogram = color_histogram(&original);
+        assert!((histogram.iter().sum::<f32>() - 1.0).abs() < 1e-3);
+        assert!(histogram_distance(&histogram, &color_histogram(&copy)) < 0.1);
+        assert_eq!(
+            histogram_from_bytes(&histogram_to_bytes(&histogram)),
+            histogram
+        );
+    }
+}
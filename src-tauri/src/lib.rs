@@ -1,12 +1,20 @@
+mod backfill;
 mod config;
 mod db;
+mod fingerprint;
+mod image_hash;
+mod metadata;
 mod model;
+mod protocol;
+mod search;
+mod watcher;
 
 use db::*;
 use std::path::PathBuf;
 use tauri::Manager;
 
 use config::*;
+use watcher::start_watcher;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
@@ -15,6 +23,12 @@ pub fn run() {
         .plugin(tauri_plugin_dialog::init())
         // 使用するTauriプラグインを追加
         .plugin(tauri_plugin_opener::init())
+        // サムネイルと元画像をIPCを通さずに画面へ渡すプロトコル
+        .register_asynchronous_uri_scheme_protocol(
+            protocol::THUMBNAIL_SCHEME,
+            protocol::thumbnail_protocol,
+        )
+        .register_asynchronous_uri_scheme_protocol(protocol::IMAGE_SCHEME, protocol::image_protocol)
         // フォルダ操作・画像スキャン関連コマンドを追加
         .invoke_handler(tauri::generate_handler![
             add_folder,               // フォルダ追加
@@ -30,7 +44,37 @@ pub fn run() {
             add_ignore_folder,      // フォルダ追加
             delete_ignore_folder,   // フォルダ削除
             get_all_ignore_folders, // 全フォルダ取得
-            search_images
+            search_images,
+            search_images_by_query,
+            search_images_page,
+            get_search_facets,
+            get_player_names,
+            get_player_cooccurrence,
+            get_player_graph,
+            get_sessions,
+            get_session_images,
+            rebuild_sessions,
+            add_image_tags,
+            remove_image_tags,
+            set_image_favorite,
+            set_image_rating,
+            get_image_annotations,
+            get_all_tags,
+            get_albums,
+            create_album,
+            rename_album,
+            update_smart_album,
+            delete_album,
+            reorder_albums,
+            add_album_images,
+            remove_album_images,
+            reorder_album_images,
+            get_album_images,
+            find_duplicate_images,
+            find_similar_images,
+            search_images_fulltext,
+            backfill::vrcx_db::import_vrcx_database,
+            backfill::output_log::import_output_logs
         ])
         // Tauriイベントのサンプルフックセット
         .setup(|app| {
@@ -40,12 +84,42 @@ pub fn run() {
                 .path()
                 .app_data_dir()
                 .unwrap_or(PathBuf::from("."));
-            let _config = load_config(&config_path);
+            let config = load_config(&config_path);
 
             #[cfg(debug_assertions)]
             app.get_webview_window("main").unwrap().open_devtools();
             // 初期処理（例：DBや必要フォルダの作成）があればここに追加
-            init_db(app_handle).unwrap();
+            if let Err(e) = init_db(app_handle) {
+                eprintln!("データベースの初期化に失敗しました: {}", e);
+            }
+            // 以前のバージョンのフォルダごとのインデックスを統合データベースへ移行
+            if let Err(e) = migrate_legacy_indexes(app_handle) {
+                eprintln!("旧インデックスの移行に失敗しました: {}", e);
+            }
+            // 登録済みの画像のインスタンスIDを解析
+            if let Err(e) = init_db(app_handle).and_then(|conn| index_instances(&conn)) {
+                eprintln!("インスタンスIDの解析に失敗しました: {}", e);
+            }
+            // 滞在に属していない写真をまとめる
+            if let Err(e) =
+                init_db(app_handle).and_then(|conn| update_sessions(&conn, config.session_gap()))
+            {
+                eprintln!("滞在のクラスタリングに失敗しました: {}", e);
+            }
+            // 以前のバージョンで登録された画像の知覚ハッシュと見た目の特徴を、起動を待たせずに計算する
+            let handle = app_handle.clone();
+            std::thread::spawn(move || {
+                if let Err(e) = init_db(&handle).and_then(|conn| {
+                    backfill_dhashes(&conn)?;
+                    backfill_descriptors(&conn)
+                }) {
+                    eprintln!("知覚ハッシュの計算に失敗しました: {}", e);
+                }
+            });
+            // 登録フォルダの監視を開始（新しいスクリーンショットを自動登録）
+            if let Err(e) = start_watcher(app_handle) {
+                eprintln!("フォルダ監視の開始に失敗しました: {}", e);
+            }
             println!("Tauri application is starting!");
             Ok(())
         })
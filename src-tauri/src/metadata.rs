@@ -0,0 +1,152 @@
+use chrono::{DateTime, Utc};
+use png::Decoder;
+use serde_json::Value;
+use std::io::Cursor;
+
+mod capture_time;
+mod file_name;
+mod instance_id;
+mod legacy;
+mod xmp;
+
+pub(crate) use capture_time::{parse_metadata_time, resolve_capture_time, CaptureTimeSource};
+pub(crate) use file_name::parse_vrchat_file_name;
+pub(crate) use instance_id::parse_instance_id;
+pub(crate) use legacy::parse as parse_legacy_metadata;
+
+/// VRCXが書き込むiTXtチャンクのキーワード
+const VRCX_KEYWORD: &str = "Description";
+/// XMPパケットを格納するiTXtチャンクのキーワード
+const XMP_KEYWORD: &str = "XML:com.adobe.xmp";
+
+/// メタデータの取得元
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MetadataSource {
+    /// VRCXが書き込んだiTXt(Description)のJSON
+    Vrcx,
+    /// VRChatが書き込んだXMPパケット
+    Xmp,
+    /// 旧形式（`lfs|2|...`）の文字列を変換したもの
+    LegacyLfs,
+    /// JSONとして解析できなかった文字列
+    Raw,
+    /// VRCXのデータベース（ゲームログ）から推定したメタデータ
+    VrcxDatabase,
+    /// VRChatのログ（output_log）から推定したメタデータ
+    OutputLog,
+}
+
+impl MetadataSource {
+    /// 画像に埋め込まれておらず、履歴から推定したメタデータの取得元
+    pub(crate) const INFERRED: [MetadataSource; 2] =
+        [MetadataSource::VrcxDatabase, MetadataSource::OutputLog];
+
+    /// [`MetadataSource::INFERRED`] をSQLに渡すためのJSON配列（`json_each` で展開する）
+    pub(crate) fn inferred_json() -> String {
+        Value::from(
+            MetadataSource::INFERRED
+                .iter()
+                .map(|source| source.as_str())
+                .collect::<Vec<_>>(),
+        )
+        .to_string()
+    }
+
+    /// `images.metadata_source` に保存する値
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            MetadataSource::Vrcx => "vrcx",
+            MetadataSource::Xmp => "xmp",
+            MetadataSource::LegacyLfs => "lfs",
+            MetadataSource::Raw => "raw",
+            MetadataSource::VrcxDatabase => "vrcx_db",
+            MetadataSource::OutputLog => "output_log",
+        }
+    }
+}
+
+/// 画像から取り出したメタデータ
+pub(crate) struct ExtractedMetadata {
+    pub json: String,
+    pub source: MetadataSource,
+    /// メタデータに記録された撮影日時
+    pub captured_at: Option<DateTime<Utc>>,
+}
+
+/// PNGのテキストチャンクからメタデータを取り出す
+///
+/// VRCXのiTXt(Description)を優先し、旧形式の文字列やVRChatのXMPパケットは
+/// VRCXと同じJSON形式に変換して返す。
+/// 画素のデコードにも使うため、ファイルは読み込み済みのデータで受け取る。
+pub(crate) fn extract_metadata(data: &[u8]) -> Result<Option<ExtractedMetadata>, String> {
+    // PNGデコーダーを作成
+    let decoder = Decoder::new(Cursor::new(data));
+    let reader = decoder
+        .read_info()
+        .map_err(|e| format!("PNG解析エラー: {}", e))?;
+    let info = reader.info();
+
+    // VRCXのメタデータチャンク（iTXt、古いツールではtEXt）を検索
+    let description = info
+        .utf8_text
+        .iter()
+        .find(|t| t.keyword == VRCX_KEYWORD)
+        .map(|itxt| itxt.get_text().unwrap_or_default())
+        .or_else(|| {
+            info.uncompressed_latin1_text
+                .iter()
+                .find(|t| t.keyword == VRCX_KEYWORD)
+                .map(|text| text.text.clone())
+        });
+    // VRChatのXMPパケットを検索（iTXt、まれにtEXtに格納される）
+    let packet = info
+        .utf8_text
+        .iter()
+        .find(|t| t.keyword == XMP_KEYWORD)
+        .and_then(|itxt| itxt.get_text().ok())
+        .or_else(|| {
+            info.uncompressed_latin1_text
+                .iter()
+                .find(|t| t.keyword == XMP_KEYWORD)
+                .map(|text| text.text.clone())
+        });
+    // 撮影日時はVRCXのメタデータがある場合でもXMPから取得する
+    let captured_at = packet
+        .as_deref()
+        .and_then(xmp::find_create_date)
+        .and_then(|date| parse_metadata_time(&date));
+
+    if let Some(data) = &description {
+        // JSONとしてパース可能な場合はパース
+        if let Ok(parsed_json) = serde_json::from_str::<Value>(data) {
+            return Ok(Some(ExtractedMetadata {
+                json: parsed_json.to_string(), // JSON文字列として返却
+                source: MetadataSource::Vrcx,
+                captured_at,
+            }));
+        }
+        // 旧形式（lfs|2|...）の場合はVRCXと同じ形式に変換
+        if let Some(parsed_json) = parse_legacy_metadata(data) {
+            return Ok(Some(ExtractedMetadata {
+                json: parsed_json.to_string(),
+                source: MetadataSource::LegacyLfs,
+                captured_at,
+            }));
+        }
+    }
+
+    if let Some(xmp) = packet.as_deref().and_then(xmp::parse) {
+        return Ok(Some(ExtractedMetadata {
+            json: xmp.to_vrcx_json().to_string(),
+            source: MetadataSource::Xmp,
+            captured_at,
+        }));
+    }
+
+    // JSONでない場合は元データをそのまま返す
+    Ok(description.map(|data| ExtractedMetadata {
+        json: data,
+        source: MetadataSource::Raw,
+        captured_at,
+    }))
+}
@@ -0,0 +1,152 @@
+use super::file_name::parse_vrchat_file_name;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use std::fs::Metadata;
+use std::path::Path;
+
+/// 撮影日時の取得元
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CaptureTimeSource {
+    /// 画像に埋め込まれたメタデータ（XMPのCreateDateなど）
+    Metadata,
+    /// VRChatのファイル名（`VRChat_2024-01-05_21-14-03.123_1920x1080.png`）
+    FileName,
+    /// ファイルの更新日時
+    Modified,
+    /// ファイルの作成日時（取得できない環境ではinodeの変更日時）
+    Created,
+}
+
+impl CaptureTimeSource {
+    /// `images.capture_time_source` に保存する値
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            CaptureTimeSource::Metadata => "metadata",
+            CaptureTimeSource::FileName => "file_name",
+            CaptureTimeSource::Modified => "mtime",
+            CaptureTimeSource::Created => "ctime",
+        }
+    }
+}
+
+/// 撮影日時を決定する
+///
+/// メタデータ、ファイル名、更新日時、作成日時の順に、最初に取得できたものを使う。
+/// コピーやバックアップの復元で変わってしまうファイルシステムの日時は最後の手段。
+pub(crate) fn resolve_capture_time(
+    file_path: &Path,
+    metadata_time: Option<DateTime<Utc>>,
+    file_metadata: &Metadata,
+) -> Option<(DateTime<Utc>, CaptureTimeSource)> {
+    if let Some(time) = metadata_time {
+        return Some((time, CaptureTimeSource::Metadata));
+    }
+    if let Some(time) = parse_vrchat_file_name(file_path) {
+        return Some((time, CaptureTimeSource::FileName));
+    }
+    if let Ok(time) = file_metadata.modified() {
+        return Some((time.into(), CaptureTimeSource::Modified));
+    }
+    if let Ok(time) = file_metadata.created() {
+        return Some((time.into(), CaptureTimeSource::Created));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let Some(time) = DateTime::from_timestamp(file_metadata.ctime(), 0) {
+            return Some((time, CaptureTimeSource::Created));
+        }
+    }
+    None
+}
+
+/// メタデータに書かれた日時を読み取る（タイムゾーンが無い場合はローカル時刻とみなす）
+pub(crate) fn parse_metadata_time(value: &str) -> Option<DateTime<Utc>> {
+    let value = value.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    [
+        "%Y-%m-%dT%H:%M:%S%.f",
+        "%Y-%m-%dT%H:%M",
+        "%Y:%m:%d %H:%M:%S",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+    .and_then(|naive| Local.from_local_datetime(&naive).earliest())
+    .map(|time| time.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, File};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn parses_metadata_times() {
+        let expected = DateTime::parse_from_rfc3339("2024-01-05T12:14:03.123Z").unwrap();
+        assert_eq!(
+            parse_metadata_time(" 2024-01-05T21:14:03.123+09:00 "),
+            Some(expected.with_timezone(&Utc))
+        );
+        // タイムゾーンの無い日時はローカル時刻
+        let local = |text: &str, format: &str| {
+            Local
+                .from_local_datetime(&NaiveDateTime::parse_from_str(text, format).unwrap())
+                .earliest()
+                .map(|time| time.with_timezone(&Utc))
+        };
+        assert_eq!(
+            parse_metadata_time("2024-01-05T21:14:03.5"),
+            local("2024-01-05 21:14:03.5", "%Y-%m-%d %H:%M:%S%.f")
+        );
+        assert_eq!(
+            parse_metadata_time("2024-01-05T21:14"),
+            local("2024-01-05 21:14:00", "%Y-%m-%d %H:%M:%S")
+        );
+        assert_eq!(
+            parse_metadata_time("2024:01:05 21:14:03"),
+            local("2024-01-05 21:14:03", "%Y-%m-%d %H:%M:%S")
+        );
+        assert_eq!(parse_metadata_time("yesterday"), None);
+    }
+
+    #[test]
+    fn resolves_capture_time_in_priority_order() {
+        let dir = std::env::temp_dir().join(format!(
+            "vrcxphotosearcher-capture-time-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir).unwrap();
+        let named = dir.join("VRChat_2024-01-05_21-14-03.123_1920x1080.png");
+        let unnamed = dir.join("photo.png");
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        for path in [&named, &unnamed] {
+            File::create(path).unwrap().set_modified(modified).unwrap();
+        }
+        let metadata_time = parse_metadata_time("2024-02-01T00:00:00Z");
+
+        let (time, source) =
+            resolve_capture_time(&named, metadata_time, &fs::metadata(&named).unwrap()).unwrap();
+        assert_eq!(
+            (Some(time), source),
+            (metadata_time, CaptureTimeSource::Metadata)
+        );
+
+        let (time, source) =
+            resolve_capture_time(&named, None, &fs::metadata(&named).unwrap()).unwrap();
+        assert_eq!(
+            (Some(time), source),
+            (parse_vrchat_file_name(&named), CaptureTimeSource::FileName)
+        );
+
+        let (time, source) =
+            resolve_capture_time(&unnamed, None, &fs::metadata(&unnamed).unwrap()).unwrap();
+        assert_eq!(
+            (time, source),
+            (DateTime::<Utc>::from(modified), CaptureTimeSource::Modified)
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
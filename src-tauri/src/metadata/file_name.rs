@@ -0,0 +1,73 @@
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use std::path::Path;
+
+/// VRChatのスクリーンショットのファイル名から撮影日時を読み取る
+///
+/// - `VRChat_2024-01-05_21-14-03.123_1920x1080.png`（現行の形式）
+/// - `VRChat_1920x1080_2022-01-05_21-14-03.123.png`（2022年頃までの形式）
+///
+/// ファイル名の日時は撮影したPCのローカル時刻。
+pub(crate) fn parse_vrchat_file_name(path: &Path) -> Option<DateTime<Utc>> {
+    let stem = path.file_stem()?.to_str()?;
+    let rest = stem.strip_prefix("VRChat_")?;
+    let parts: Vec<&str> = rest.split('_').collect();
+
+    let (date, time) = match parts.as_slice() {
+        [date, time, ..] if is_date(date) => (*date, *time),
+        [_resolution, date, time, ..] if is_date(date) => (*date, *time),
+        _ => return None,
+    };
+
+    let naive =
+        NaiveDateTime::parse_from_str(&format!("{} {}", date, time), "%Y-%m-%d %H-%M-%S%.f")
+            .ok()?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+fn is_date(value: &str) -> bool {
+    value.len() == 10 && value.as_bytes()[4] == b'-' && value.as_bytes()[7] == b'-'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn local(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32, ms: i64) -> DateTime<Utc> {
+        (Local
+            .with_ymd_and_hms(y, mo, d, h, mi, s)
+            .earliest()
+            .unwrap()
+            + chrono::Duration::milliseconds(ms))
+        .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn parses_current_and_old_file_names() {
+        assert_eq!(
+            parse_vrchat_file_name(Path::new(
+                "/photos/VRChat_2024-01-05_21-14-03.123_1920x1080.png"
+            )),
+            Some(local(2024, 1, 5, 21, 14, 3, 123))
+        );
+        assert_eq!(
+            parse_vrchat_file_name(Path::new("VRChat_1920x1080_2022-01-05_21-14-03.456.png")),
+            Some(local(2022, 1, 5, 21, 14, 3, 456))
+        );
+    }
+
+    #[test]
+    fn rejects_other_file_names() {
+        for name in [
+            "IMG_0001.png",
+            "VRChat_.png",
+            "VRChat_1920x1080.png",
+            "VRChat_2024-13-05_21-14-03.123_1920x1080.png",
+            "VRChat_2024-01-05_noon_1920x1080.png",
+        ] {
+            assert_eq!(parse_vrchat_file_name(Path::new(name)), None, "{}", name);
+        }
+    }
+}
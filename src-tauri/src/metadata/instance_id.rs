@@ -0,0 +1,136 @@
+/// インスタンスの公開範囲
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AccessType {
+    Public,
+    /// `~hidden(usr_...)`
+    FriendsPlus,
+    /// `~friends(usr_...)`
+    Friends,
+    /// `~private(usr_...)~canRequestInvite`
+    InvitePlus,
+    /// `~private(usr_...)`
+    Invite,
+    /// `~group(grp_...)`
+    Group,
+}
+
+impl AccessType {
+    /// `instances.access_type` に保存する値（検索の `instance_type:` で指定する値）
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            AccessType::Public => "public",
+            AccessType::FriendsPlus => "friends+",
+            AccessType::Friends => "friends",
+            AccessType::InvitePlus => "invite+",
+            AccessType::Invite => "invite",
+            AccessType::Group => "group",
+        }
+    }
+}
+
+/// インスタンスIDを分解したもの
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct InstanceId {
+    /// `~` より前のインスタンス名（`12345` など）
+    pub name: String,
+    pub access_type: AccessType,
+    pub region: String,
+    /// インスタンスを作成したユーザーのID、またはグループID
+    pub owner_id: Option<String>,
+    pub nonce: Option<String>,
+}
+
+/// リージョンの指定が無い（古い）インスタンスのリージョン
+const DEFAULT_REGION: &str = "us";
+
+/// `12345~private(usr_...)~canRequestInvite~region(jp)~nonce(...)` 形式のインスタンスIDを分解する
+///
+/// 先頭の `wrld_...:` は取り除く。知らないタグは無視する。
+pub(crate) fn parse_instance_id(value: &str) -> InstanceId {
+    let value = match value.split_once(':') {
+        Some((world_id, rest)) if world_id.starts_with("wrld_") => rest,
+        _ => value,
+    };
+    let mut parts = value.split('~');
+    let name = parts.next().unwrap_or_default().to_string();
+
+    let mut instance = InstanceId {
+        name,
+        access_type: AccessType::Public,
+        region: DEFAULT_REGION.to_string(),
+        owner_id: None,
+        nonce: None,
+    };
+    let mut can_request_invite = false;
+    for tag in parts {
+        // `key(value)` または `key`
+        let (key, argument) = match tag.split_once('(') {
+            Some((key, rest)) => (key, rest.strip_suffix(')').unwrap_or(rest)),
+            None => (tag, ""),
+        };
+        let argument = (!argument.is_empty()).then(|| argument.to_string());
+        match key {
+            "hidden" => {
+                instance.access_type = AccessType::FriendsPlus;
+                instance.owner_id = argument;
+            }
+            "friends" => {
+                instance.access_type = AccessType::Friends;
+                instance.owner_id = argument;
+            }
+            "private" => {
+                instance.access_type = AccessType::Invite;
+                instance.owner_id = argument;
+            }
+            "group" => {
+                instance.access_type = AccessType::Group;
+                instance.owner_id = argument;
+            }
+            "canRequestInvite" => can_request_invite = true,
+            "region" => {
+                if let Some(region) = argument {
+                    instance.region = region;
+                }
+            }
+            "nonce" => instance.nonce = argument,
+            _ => {}
+        }
+    }
+    if can_request_invite && instance.access_type == AccessType::Invite {
+        instance.access_type = AccessType::InvitePlus;
+    }
+    instance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_access_type_region_owner_and_nonce() {
+        let instance = parse_instance_id(
+            "wrld_abc:12345~private(usr_owner)~canRequestInvite~region(jp)~nonce(xyz)",
+        );
+        assert_eq!(
+            instance,
+            InstanceId {
+                name: "12345".to_string(),
+                access_type: AccessType::InvitePlus,
+                region: "jp".to_string(),
+                owner_id: Some("usr_owner".to_string()),
+                nonce: Some("xyz".to_string()),
+            }
+        );
+
+        let instance = parse_instance_id("67890~group(grp_a)~groupAccessType(plus)~region(eu)");
+        assert_eq!(
+            (instance.access_type, instance.owner_id.as_deref()),
+            (AccessType::Group, Some("grp_a"))
+        );
+
+        let instance = parse_instance_id("42");
+        assert_eq!(instance.access_type, AccessType::Public);
+        assert_eq!(instance.region, "us");
+        assert_eq!(instance.owner_id, None);
+    }
+}
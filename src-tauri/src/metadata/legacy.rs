@@ -0,0 +1,133 @@
+use serde_json::{json, Value};
+
+/// 旧形式（`lfs|2|...`）のスクリーンショットメタデータを、VRCXと同じ形式のJSONに変換する
+///
+/// ```text
+/// lfs|2|author:usr_xxx,Name|world:wrld_xxx,12345~region(jp),World Name|pos:1.0,2.0,3.0|players:usr_a,1.0,2.0,3.0,Alice;usr_b,4.0,5.0,6.0,Bob
+/// ```
+///
+/// バージョン1の `players` は座標を持たず `usr_a,Alice` の形式で書かれている。
+/// `lfs|` で始まらない文字列や、ワールド情報も撮影者情報も無い場合は `None` を返す。
+pub(crate) fn parse(data: &str) -> Option<Value> {
+    let mut fields = data.trim().split('|');
+    if fields.next()? != "lfs" {
+        return None;
+    }
+    let version = fields.next()?;
+    if !version.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let mut author = None;
+    let mut world = None;
+    let mut players = Vec::new();
+    for field in fields {
+        let Some((key, value)) = field.split_once(':') else {
+            continue;
+        };
+        match key {
+            "author" => author = parse_author(value),
+            "world" => world = parse_world(value),
+            "players" => players = value.split(';').filter_map(parse_player).collect(),
+            _ => {}
+        }
+    }
+    if author.is_none() && world.is_none() {
+        return None;
+    }
+
+    Some(json!({
+        "application": "lfs",
+        "version": 1,
+        "author": author.unwrap_or_else(|| json!({ "id": "", "displayName": "" })),
+        "world": world.unwrap_or_else(|| json!({ "name": "", "id": "", "instanceId": "" })),
+        "players": players,
+    }))
+}
+
+/// `usr_xxx,Name`
+fn parse_author(value: &str) -> Option<Value> {
+    let (id, name) = value.split_once(',')?;
+    Some(json!({ "id": id, "displayName": name }))
+}
+
+/// `wrld_xxx,12345~region(jp),World Name`（ワールド名にカンマが含まれる場合がある）
+fn parse_world(value: &str) -> Option<Value> {
+    let mut parts = value.splitn(3, ',');
+    let id = parts.next()?;
+    let instance = parts.next().unwrap_or_default();
+    let name = parts.next().unwrap_or_default();
+    let instance_id = if instance.is_empty() {
+        String::new()
+    } else {
+        format!("{}:{}", id, instance)
+    };
+    Some(json!({ "name": name, "id": id, "instanceId": instance_id }))
+}
+
+/// `usr_xxx,x,y,z,Name`（バージョン2）または `usr_xxx,Name`（バージョン1）
+fn parse_player(value: &str) -> Option<Value> {
+    let parts: Vec<&str> = value.split(',').collect();
+    let (id, name) = match parts.as_slice() {
+        [id, x, y, z, name @ ..]
+            if !name.is_empty() && [x, y, z].iter().all(|v| v.parse::<f64>().is_ok()) =>
+        {
+            (*id, name.join(","))
+        }
+        [id, name @ ..] if !name.is_empty() => (*id, name.join(",")),
+        _ => return None,
+    };
+    if name.is_empty() {
+        return None;
+    }
+    Some(json!({ "id": id, "displayName": name }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_version_2() {
+        let data = "lfs|2|author:usr_a,Tea|world:wrld_b,12345~region(jp),Cafe, Bar & Grill|pos:1.0,2.0,3.0|players:usr_c,1.0,2.0,3.0,Cake;usr_d,4.5,-5.0,6.0,Milk, Sugar";
+        assert_eq!(
+            parse(data).unwrap(),
+            json!({
+                "application": "lfs",
+                "version": 1,
+                "author": { "id": "usr_a", "displayName": "Tea" },
+                "world": {
+                    "name": "Cafe, Bar & Grill",
+                    "id": "wrld_b",
+                    "instanceId": "wrld_b:12345~region(jp)",
+                },
+                "players": [
+                    { "id": "usr_c", "displayName": "Cake" },
+                    { "id": "usr_d", "displayName": "Milk, Sugar" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn parses_version_1_players_and_missing_fields() {
+        let value = parse("lfs|1|world:wrld_b|players:usr_c,Cake;broken").unwrap();
+        assert_eq!(value["author"], json!({ "id": "", "displayName": "" }));
+        assert_eq!(
+            value["world"],
+            json!({ "name": "", "id": "wrld_b", "instanceId": "" })
+        );
+        assert_eq!(
+            value["players"],
+            json!([{ "id": "usr_c", "displayName": "Cake" }])
+        );
+    }
+
+    #[test]
+    fn rejects_other_strings() {
+        assert_eq!(parse("{\"world\":{}}"), None);
+        assert_eq!(parse("lfs|x|author:usr_a,Tea"), None);
+        // ワールド情報も撮影者情報も無い
+        assert_eq!(parse("lfs|2|pos:1,2,3"), None);
+    }
+}
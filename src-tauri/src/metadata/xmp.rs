@@ -0,0 +1,238 @@
+use serde_json::{json, Value};
+
+/// VRChatがスクリーンショットに埋め込むXMPの内容
+///
+/// ```xml
+/// <rdf:Description xmlns:xmp="http://ns.adobe.com/xap/1.0/" xmlns:vrc="http://ns.vrchat.com/vrc/1.0/">
+///   <xmp:CreatorTool>VRChat</xmp:CreatorTool>
+///   <xmp:Author>DisplayName</xmp:Author>
+///   <xmp:CreateDate>2024-01-05T21:14:03.123+09:00</xmp:CreateDate>
+///   <vrc:WorldID>wrld_...</vrc:WorldID>
+///   <vrc:WorldDisplayName>World Name</vrc:WorldDisplayName>
+///   <vrc:AuthorID>usr_...</vrc:AuthorID>
+/// </rdf:Description>
+/// ```
+#[derive(Debug, Default)]
+pub(crate) struct XmpMetadata {
+    pub world_id: Option<String>,
+    pub world_name: Option<String>,
+    pub author_id: Option<String>,
+    pub author_name: Option<String>,
+}
+
+impl XmpMetadata {
+    /// VRCXのメタデータと同じ形式のJSONに変換する
+    pub(crate) fn to_vrcx_json(&self) -> Value {
+        json!({
+            "application": "VRChat",
+            "version": 1,
+            "author": {
+                "id": self.author_id.clone().unwrap_or_default(),
+                "displayName": self.author_name.clone().unwrap_or_default(),
+            },
+            "world": {
+                "name": self.world_name.clone().unwrap_or_default(),
+                "id": self.world_id.clone().unwrap_or_default(),
+                "instanceId": "",
+            },
+            "players": [],
+        })
+    }
+}
+
+/// XMPパケットからVRChatのメタデータを読み取る
+///
+/// VRChat以外のツールが書き込んだXMPなど、ワールド情報も撮影者情報も
+/// 含まれない場合は `None` を返す。
+pub(crate) fn parse(packet: &str) -> Option<XmpMetadata> {
+    let metadata = XmpMetadata {
+        world_id: find_property(packet, "WorldID"),
+        world_name: find_property(packet, "WorldDisplayName"),
+        author_id: find_property(packet, "AuthorID"),
+        author_name: find_property(packet, "Author").or_else(|| find_property(packet, "creator")),
+    };
+    if metadata.world_id.is_none() && metadata.world_name.is_none() && metadata.author_id.is_none()
+    {
+        return None;
+    }
+    Some(metadata)
+}
+
+/// XMPパケットに記録された撮影日時（`xmp:CreateDate` / `exif:DateTimeOriginal`）
+pub(crate) fn find_create_date(packet: &str) -> Option<String> {
+    find_property(packet, "CreateDate").or_else(|| find_property(packet, "DateTimeOriginal"))
+}
+
+/// 名前空間接頭辞を問わず、要素または属性として書かれたプロパティの値を取得する
+fn find_property(packet: &str, local_name: &str) -> Option<String> {
+    find_element(packet, local_name)
+        .or_else(|| find_attribute(packet, local_name))
+        .filter(|value| !value.is_empty())
+}
+
+/// `<prefix:Name>value</prefix:Name>` 形式の値を取得する
+fn find_element(packet: &str, local_name: &str) -> Option<String> {
+    let mut rest = packet;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let name_end = rest.find(|c: char| c == '>' || c == '/' || c.is_whitespace())?;
+        let name = &rest[..name_end];
+        if strip_prefix(name) != local_name {
+            continue;
+        }
+        let tag_end = rest.find('>')?;
+        if rest[..tag_end].ends_with('/') {
+            // 空要素（値は属性側にある可能性がある）
+            continue;
+        }
+        let body = &rest[tag_end + 1..];
+        let Some(end) = body.find(&format!("</{}>", name)) else {
+            // 閉じタグの無い要素は飛ばして、次に現れる同名の要素を探す
+            continue;
+        };
+        let inner = &body[..end];
+        // dc:creator などは rdf:Seq / rdf:Alt の中に値が入る
+        let text = match inner.find("<rdf:li") {
+            Some(li) => {
+                let li = &inner[li..];
+                match (li.find('>'), li.find("</rdf:li>")) {
+                    (Some(value_start), Some(value_end)) if value_start < value_end => {
+                        &li[value_start + 1..value_end]
+                    }
+                    _ => continue,
+                }
+            }
+            None => inner,
+        };
+        return Some(unescape(text.trim()));
+    }
+    None
+}
+
+/// `prefix:Name="value"` 形式の値を取得する
+fn find_attribute(packet: &str, local_name: &str) -> Option<String> {
+    for quote in ['"', '\''] {
+        let pattern = format!(":{}={}", local_name, quote);
+        if let Some(start) = packet.find(&pattern) {
+            let value = &packet[start + pattern.len()..];
+            let end = value.find(quote)?;
+            return Some(unescape(value[..end].trim()));
+        }
+    }
+    None
+}
+
+fn strip_prefix(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// XMLの文字参照を元に戻す
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        result.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let Some(semicolon) = rest.find(';') else {
+            break;
+        };
+        let entity = &rest[1..semicolon];
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                .or_else(|| entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                .and_then(char::from_u32),
+        };
+        match decoded {
+            Some(c) => {
+                result.push(c);
+                rest = &rest[semicolon + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// VRChatが書き込むXMP（プロパティは属性として書かれる）
+    const VRCHAT_PACKET: &str = r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="XMP Core 4.4.0-Exiv2">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:tiff="http://ns.adobe.com/tiff/1.0/"
+    xmlns:vrc="http://ns.vrchat.com/vrc/1.0/"
+   xmp:CreatorTool="VRChat"
+   xmp:Author="Tea &amp; Cake"
+   xmp:CreateDate="2024-01-05T21:14:03.123+09:00"
+   xmp:ModifyDate="2024-01-05T21:14:03.123+09:00"
+   tiff:Make="VRChat"
+   vrc:WorldID="wrld_4cf554b4-430c-4f8f-b53e-1f294eed230b"
+   vrc:WorldDisplayName="The Black Cat &#x2615;"
+   vrc:AuthorID="usr_c1644b5b-3ca4-45b4-97c6-a2a0de70d469"/>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#;
+
+    #[test]
+    fn parses_vrchat_attribute_packet() {
+        let metadata = parse(VRCHAT_PACKET).unwrap();
+        assert_eq!(
+            metadata.world_id.as_deref(),
+            Some("wrld_4cf554b4-430c-4f8f-b53e-1f294eed230b")
+        );
+        assert_eq!(metadata.world_name.as_deref(), Some("The Black Cat ☕"));
+        assert_eq!(
+            metadata.author_id.as_deref(),
+            Some("usr_c1644b5b-3ca4-45b4-97c6-a2a0de70d469")
+        );
+        assert_eq!(metadata.author_name.as_deref(), Some("Tea & Cake"));
+    }
+
+    #[test]
+    fn parses_element_packet_with_creator_sequence() {
+        let packet = r#"<rdf:Description xmlns:vrc="http://ns.vrchat.com/vrc/1.0/">
+   <vrc:WorldDisplayName>Open &lt;World&gt;</vrc:WorldDisplayName>
+   <dc:creator><rdf:Seq><rdf:li>Photographer</rdf:li></rdf:Seq></dc:creator>
+ </rdf:Description>"#;
+        let metadata = parse(packet).unwrap();
+        assert_eq!(metadata.world_name.as_deref(), Some("Open <World>"));
+        assert_eq!(metadata.world_id, None);
+        assert_eq!(metadata.author_name.as_deref(), Some("Photographer"));
+    }
+
+    #[test]
+    fn skips_elements_without_closing_tag() {
+        let packet = "<old:WorldID>broken <vrc:WorldID>wrld_ok</vrc:WorldID>";
+        assert_eq!(find_element(packet, "WorldID").as_deref(), Some("wrld_ok"));
+        assert_eq!(find_element("<vrc:WorldID>broken", "WorldID"), None);
+    }
+
+    #[test]
+    fn ignores_packets_from_other_tools() {
+        let packet = r#"<rdf:Description xmp:CreatorTool="Photoshop" xmp:Author="someone"/>"#;
+        assert!(parse(packet).is_none());
+    }
+
+    #[test]
+    fn unescapes_character_references() {
+        assert_eq!(
+            unescape("a &amp; b &#65;&#x42; &unknown; &"),
+            "a & b AB &unknown; &"
+        );
+    }
+}
@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// アルバム
+///
+/// スマートアルバムは `query` または `conditions` の検索条件を開くたびに評価する。
+#[derive(Serialize)]
+pub struct Album {
+    pub id: i64,
+    pub name: String,
+    pub smart: bool,
+    pub query: Option<String>,
+    pub conditions: Option<Vec<HashMap<String, String>>>,
+    pub image_count: Option<i64>, // 手動アルバムの画像の枚数（スマートアルバムは None）
+    pub created_at: String,
+    pub updated_at: String,
+}
+
+/// アルバムの作成・検索条件の変更リクエスト
+///
+/// `query` と `conditions` のどちらも無い場合は手動アルバムになる。
+#[derive(Deserialize, Default)]
+pub struct AlbumRequest {
+    pub name: String,
+    #[serde(default)]
+    pub query: Option<String>,
+    #[serde(default)]
+    pub conditions: Option<Vec<HashMap<String, String>>>,
+}
@@ -0,0 +1,9 @@
+use serde::Serialize;
+
+/// 画像に付けたタグ・お気に入り・評価
+#[derive(Serialize, Default)]
+pub struct ImageAnnotations {
+    pub tags: Vec<String>,
+    pub favorite: bool,
+    pub rating: Option<u8>, // 1〜5（未評価は None）
+}
@@ -0,0 +1,10 @@
+use serde::Serialize;
+
+/// VRChatのログの取り込み結果
+#[derive(Serialize)]
+pub struct OutputLogImport {
+    /// 推定したメタデータを書き込んだ画像の数
+    pub updated: usize,
+    /// 読み取れなかったログファイル（パスとエラー）
+    pub failed_logs: Vec<String>,
+}
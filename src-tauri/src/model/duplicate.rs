@@ -0,0 +1,19 @@
+use serde::Serialize;
+
+/// 同じ写真（またはほぼ同じ写真）のまとまり
+#[derive(Serialize)]
+pub struct DuplicateGroup {
+    pub keeper: String,              // 残すことを勧める画像のファイルパス
+    pub images: Vec<DuplicateImage>, // 残す画像が先頭
+}
+
+#[derive(Serialize)]
+pub struct DuplicateImage {
+    pub file_path: String,
+    pub uuid: String,
+    pub width: i64,
+    pub height: i64,
+    pub file_size: i64,
+    pub has_metadata: bool, // 画像にメタデータが埋め込まれているか
+    pub distance: u32,      // 残す画像との知覚ハッシュの距離（0 は見た目が同じ）
+}
@@ -1,2 +1,8 @@
+pub mod album;
+pub mod annotation;
+pub mod backfill;
+pub mod duplicate;
 pub mod image;
 pub mod search;
+pub mod session;
+pub mod similar;
@@ -1,4 +1,5 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// 検索フォルダのデータ構造
 #[derive(Serialize)]
@@ -7,3 +8,144 @@ pub struct SearchFolder {
     pub path: String,
     pub uuid: String,
 }
+
+/// プレイヤーの表示名と、その名前で記録されている写真の情報
+#[derive(Serialize)]
+pub struct PlayerName {
+    pub display_name: String,
+    pub image_count: i64,
+    pub first_seen: String, // この名前で記録された最初の撮影日時
+    pub last_seen: String,  // この名前で記録された最後の撮影日時
+}
+
+/// 検索結果の並び順
+#[derive(Deserialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    /// 撮影日時
+    #[default]
+    CapturedAt,
+    /// ワールド名
+    World,
+    /// ファイルサイズ
+    FileSize,
+    /// 同じインスタンスにいたプレイヤーの人数
+    PlayerCount,
+}
+
+/// ページ単位の検索リクエスト
+///
+/// `query`（クエリ文字列）と `conditions`（検索画面の条件リスト）のどちらも無い場合は全画像が対象。
+/// 2ページ目以降は前のページの `next_cursor` を `cursor` に渡す。
+#[derive(Deserialize, Default)]
+pub struct SearchPageRequest {
+    #[serde(default)]
+    pub query: Option<String>,
+    #[serde(default)]
+    pub conditions: Option<Vec<HashMap<String, String>>>,
+    #[serde(default)]
+    pub sort: SortKey,
+    #[serde(default)]
+    pub descending: bool,
+    #[serde(default)]
+    pub cursor: Option<String>,
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+
+/// 検索結果の1ページ
+#[derive(Serialize)]
+pub struct SearchPage {
+    pub items: Vec<(String, i64, String)>, // (ファイルパス, 画像ID, フォルダのUUID)
+    pub next_cursor: Option<String>,       // 最後のページでは None
+}
+
+/// 検索結果の絞り込み候補（ファセット）のリクエスト
+#[derive(Deserialize, Default)]
+pub struct FacetRequest {
+    #[serde(default)]
+    pub query: Option<String>,
+    #[serde(default)]
+    pub conditions: Option<Vec<HashMap<String, String>>>,
+    #[serde(default)]
+    pub limit: Option<u32>, // ファセットごとの最大件数（撮影月は全件）
+}
+
+/// ファセットの1項目
+#[derive(Serialize)]
+pub struct FacetCount {
+    pub value: String, // 検索に使う値（ワールドID・ユーザーIDなど）
+    pub label: String, // 表示名
+    pub count: i64,    // 該当する画像の枚数
+}
+
+/// 検索結果に含まれるワールド・プレイヤーなどの集計
+#[derive(Serialize)]
+pub struct SearchFacets {
+    pub worlds: Vec<FacetCount>,
+    pub players: Vec<FacetCount>,
+    pub authors: Vec<FacetCount>,
+    pub instance_types: Vec<FacetCount>,
+    pub regions: Vec<FacetCount>,
+    pub tags: Vec<FacetCount>,
+    pub months: Vec<FacetCount>,
+}
+
+/// 一緒に写っているプレイヤーの集計リクエスト
+///
+/// `player` を指定した場合はそのプレイヤーと一緒に写っている相手だけを集計する。
+#[derive(Deserialize, Default)]
+pub struct CooccurrenceRequest {
+    #[serde(default)]
+    pub player: Option<String>, // ユーザーID（usr_...）または表示名
+    #[serde(default)]
+    pub world: Option<String>, // ワールドID（wrld_...）またはワールド名
+    #[serde(default)]
+    pub after: Option<String>, // この日時以降に撮影された写真
+    #[serde(default)]
+    pub before: Option<String>, // この日時より前に撮影された写真
+    #[serde(default)]
+    pub min_count: Option<u32>, // これより少ない組み合わせは除く
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+
+/// 2人のプレイヤーが一緒に写っている写真の枚数
+#[derive(Serialize)]
+pub struct PlayerPair {
+    pub player: String, // ユーザーID（IDが記録されていない場合は表示名）
+    pub player_label: String,
+    pub other: String,
+    pub other_label: String,
+    pub count: i64,
+}
+
+/// プレイヤーの関係グラフ（辺の重みは一緒に写っている写真の枚数）
+#[derive(Serialize)]
+pub struct PlayerGraph {
+    pub nodes: Vec<FacetCount>, // value = プレイヤー、count = 写っている写真の枚数
+    pub edges: Vec<GraphEdge>,
+}
+
+#[derive(Serialize)]
+pub struct GraphEdge {
+    pub source: String,
+    pub target: String,
+    pub weight: i64,
+}
+
+/// 全文検索の結果
+///
+/// `world` などの文字列はHTMLエスケープ済みで、一致した部分を `<mark>` で囲んである。
+#[derive(Serialize)]
+pub struct FullTextHit {
+    pub file_path: String,
+    pub image_id: i64, // サムネイルは vrcxthumb:// から取得する
+    pub uuid: String,
+    pub score: f64, // 大きいほどよく一致している
+    pub world: String,
+    pub author: String,
+    pub players: String,
+    pub file_name: String,
+    pub tags: String,
+}
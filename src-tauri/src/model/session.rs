@@ -0,0 +1,28 @@
+use serde::Serialize;
+
+/// 同じインスタンスで続けて撮影された写真のまとまり（滞在）
+#[derive(Serialize)]
+pub struct Session {
+    pub id: i64,
+    pub instance_id: String,
+    pub world_id: Option<String>,
+    pub world_name: Option<String>,
+    pub access_type: Option<String>, // インスタンスの公開範囲（public / friends+ など）
+    pub started_at: String,          // 最初の写真の撮影日時
+    pub ended_at: String,            // 最後の写真の撮影日時
+    pub image_count: i64,
+    pub players: Vec<SessionPlayer>, // 滞在中の写真に写っていたプレイヤー
+}
+
+#[derive(Serialize)]
+pub struct SessionPlayer {
+    pub user_id: Option<String>,
+    pub display_name: String,
+}
+
+/// 滞在一覧の1ページ分
+#[derive(Serialize)]
+pub struct SessionPage {
+    pub items: Vec<Session>,
+    pub next_cursor: Option<String>, // 次のページの取得に使うカーソル（最後のページでは None）
+}
@@ -0,0 +1,10 @@
+use serde::Serialize;
+
+/// 見た目の近い画像
+#[derive(Serialize)]
+pub struct SimilarImage {
+    pub file_path: String,
+    pub image_id: i64, // サムネイルは vrcxthumb:// から取得する
+    pub uuid: String,
+    pub distance: f32, // 0（同じ）〜1（まったく違う）
+}
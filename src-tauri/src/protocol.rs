@@ -0,0 +1,279 @@
+//! サムネイル（`vrcxthumb://localhost/<画像ID>`）と元画像（`vrcximg://localhost/<画像ID>`）を
+//! 画面へ渡すためのカスタムプロトコル
+//!
+//! 画像のデータをIPCでBase64にして送らず、画面には画像IDだけを渡して `<img>` から直接読み込ませる。
+//! 返すのは統合データベースに登録済みの画像だけで、任意のパスのファイルは読めない。
+
+use crate::db::{image_path_by_id, init_db, thumbnail_by_image_id};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::time::UNIX_EPOCH;
+use tauri::http::{header, Request, Response, StatusCode};
+use tauri::{AppHandle, UriSchemeContext, UriSchemeResponder, Wry};
+
+pub const THUMBNAIL_SCHEME: &str = "vrcxthumb";
+pub const IMAGE_SCHEME: &str = "vrcximg";
+/// 範囲指定の1回の応答で返す最大バイト数（残りはブラウザが続けて要求する）
+const MAX_CHUNK_LEN: u64 = 1024 * 1024;
+
+/// 返すデータ（範囲指定に対応するため、必要な部分だけを読み出せるようにしておく）
+struct Content {
+    mime: String,
+    len: u64,
+    etag: String,
+    last_modified: Option<String>,
+    read: Box<dyn FnOnce(Range<u64>) -> std::io::Result<Vec<u8>>>,
+}
+
+/// URLのパスから画像IDを取り出す
+fn image_id<T>(request: &Request<T>) -> Option<i64> {
+    request.uri().path().trim_start_matches('/').parse().ok()
+}
+
+fn thumbnail_content(app: &AppHandle, id: i64) -> Result<Option<Content>, String> {
+    let conn = init_db(app).map_err(|e| e.to_string())?;
+    let Some(thumbnail) = thumbnail_by_image_id(&conn, id).map_err(|e| e.to_string())? else {
+        return Ok(None);
+    };
+    let data = thumbnail.data;
+    Ok(Some(Content {
+        mime: thumbnail.mime,
+        len: data.len() as u64,
+        // 画像を登録し直すとサムネイルも作り直されるため、登録日時で区別する
+        etag: format!("\"{}-{}\"", id, thumbnail.updated_at),
+        last_modified: None,
+        read: Box::new(move |range| Ok(data[range.start as usize..range.end as usize].to_vec())),
+    }))
+}
+
+fn image_content(app: &AppHandle, id: i64) -> Result<Option<Content>, String> {
+    let conn = init_db(app).map_err(|e| e.to_string())?;
+    let Some(file_path) = image_path_by_id(&conn, id).map_err(|e| e.to_string())? else {
+        return Ok(None);
+    };
+    // 登録後に削除されたファイル
+    let Ok(metadata) = std::fs::metadata(&file_path) else {
+        return Ok(None);
+    };
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    let mime = mime_guess::from_path(&file_path)
+        .first_or_octet_stream()
+        .essence_str()
+        .to_string();
+    Ok(Some(Content {
+        mime,
+        len: metadata.len(),
+        etag: format!("\"{}-{}\"", metadata.len(), modified.as_nanos()),
+        last_modified: chrono::DateTime::from_timestamp(modified.as_secs() as i64, 0)
+            .map(|time| time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()),
+        read: Box::new(move |range| {
+            let mut file = File::open(&file_path)?;
+            file.seek(SeekFrom::Start(range.start))?;
+            let len = range.end - range.start;
+            let mut buffer = Vec::with_capacity(len as usize);
+            file.take(len).read_to_end(&mut buffer)?;
+            Ok(buffer)
+        }),
+    }))
+}
+
+/// `Range: bytes=...` から返す範囲（終端は含まない）を求める
+///
+/// 範囲の指定が無い場合や解釈できない場合（終端が先頭より前など）、複数の範囲が指定された場合は
+/// 全体を返す（`Ok(None)`）。範囲がデータの外にある場合は `Err`。
+fn parse_range(header: &str, len: u64) -> Result<Option<Range<u64>>, ()> {
+    let Some(spec) = header.trim().strip_prefix("bytes=") else {
+        return Ok(None);
+    };
+    if spec.contains(',') {
+        return Ok(None);
+    }
+    let Some((start, end)) = spec.split_once('-') else {
+        return Ok(None);
+    };
+    let (start, end) = (start.trim(), end.trim());
+    if start.is_empty() {
+        // 末尾から指定したバイト数
+        let Ok(suffix) = end.parse::<u64>() else {
+            return Ok(None);
+        };
+        if suffix == 0 || len == 0 {
+            return Err(());
+        }
+        return Ok(Some(len.saturating_sub(suffix)..len));
+    }
+    let Ok(start) = start.parse::<u64>() else {
+        return Ok(None);
+    };
+    let end = if end.is_empty() {
+        len
+    } else {
+        match end.parse::<u64>() {
+            Ok(end) if end >= start => (end + 1).min(len),
+            // 終端が先頭より前の指定は無効なので無視する
+            _ => return Ok(None),
+        }
+    };
+    if start >= len {
+        return Err(());
+    }
+    Ok(Some(start..end))
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Vec<u8>> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(message.as_bytes().to_vec())
+        .unwrap()
+}
+
+/// キャッシュの確認と範囲指定を処理して返す
+fn respond(request: &Request<Vec<u8>>, content: Content) -> Response<Vec<u8>> {
+    let header_value = |name: header::HeaderName| {
+        request
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    };
+    let mut response = Response::builder()
+        .header(header::CONTENT_TYPE, &content.mime)
+        .header(header::ACCEPT_RANGES, "bytes")
+        // 毎回ETagで更新を確認し、変わっていなければ本体を送らない
+        .header(header::CACHE_CONTROL, "no-cache")
+        .header(header::ETAG, &content.etag);
+    if let Some(last_modified) = &content.last_modified {
+        response = response.header(header::LAST_MODIFIED, last_modified);
+    }
+
+    let not_modified = header_value(header::IF_NONE_MATCH)
+        .is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == content.etag));
+    if not_modified {
+        return response
+            .status(StatusCode::NOT_MODIFIED)
+            .body(Vec::new())
+            .unwrap();
+    }
+
+    let range = match header_value(header::RANGE).map(|value| parse_range(value, content.len)) {
+        Some(Ok(range)) => range,
+        Some(Err(())) => {
+            return response
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", content.len))
+                .body(Vec::new())
+                .unwrap();
+        }
+        None => None,
+    };
+    let (status, range) = match range {
+        Some(range) => {
+            // 大きな範囲は先頭の一部だけを返し、全体をメモリに読み込まないようにする
+            let range = range.start..range.end.min(range.start + MAX_CHUNK_LEN);
+            response = response.header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", range.start, range.end - 1, content.len),
+            );
+            (StatusCode::PARTIAL_CONTENT, range)
+        }
+        None => (StatusCode::OK, 0..content.len),
+    };
+    match (content.read)(range) {
+        Ok(body) => response
+            .status(status)
+            .header(header::CONTENT_LENGTH, body.len())
+            .body(body)
+            .unwrap(),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e.to_string()),
+    }
+}
+
+fn serve(
+    app: &AppHandle,
+    request: &Request<Vec<u8>>,
+    load: fn(&AppHandle, i64) -> Result<Option<Content>, String>,
+) -> Response<Vec<u8>> {
+    let Some(id) = image_id(request) else {
+        return error_response(StatusCode::BAD_REQUEST, "画像IDが不正です");
+    };
+    match load(app, id) {
+        Ok(Some(content)) => respond(request, content),
+        Ok(None) => error_response(StatusCode::NOT_FOUND, "画像が見つかりません"),
+        Err(e) => error_response(StatusCode::INTERNAL_SERVER_ERROR, &e),
+    }
+}
+
+/// `vrcxthumb://` の処理（データベースを読むため、メインスレッドを止めないよう別スレッドで返す）
+pub fn thumbnail_protocol(
+    ctx: UriSchemeContext<'_, Wry>,
+    request: Request<Vec<u8>>,
+    responder: UriSchemeResponder,
+) {
+    let app = ctx.app_handle().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        responder.respond(serve(&app, &request, thumbnail_content))
+    });
+}
+
+/// `vrcximg://` の処理（ファイルを読むため、メインスレッドを止めないよう別スレッドで返す）
+pub fn image_protocol(
+    ctx: UriSchemeContext<'_, Wry>,
+    request: Request<Vec<u8>>,
+    responder: UriSchemeResponder,
+) {
+    let app = ctx.app_handle().clone();
+    tauri::async_runtime::spawn_blocking(move || {
+        responder.respond(serve(&app, &request, image_content))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Ok(Some(0..100)));
+        assert_eq!(parse_range("bytes=900-", 1000), Ok(Some(900..1000)));
+        assert_eq!(parse_range("bytes=-100", 1000), Ok(Some(900..1000)));
+        // 終端がデータより後ろの場合は末尾までにする
+        assert_eq!(parse_range("bytes=500-5000", 1000), Ok(Some(500..1000)));
+        assert_eq!(parse_range("bytes=-5000", 1000), Ok(Some(0..1000)));
+
+        assert_eq!(parse_range("bytes=1000-", 1000), Err(()));
+        assert_eq!(parse_range("bytes=-0", 1000), Err(()));
+
+        // 解釈できない指定や複数の範囲は全体を返す
+        assert_eq!(parse_range("bytes=10-5", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), Ok(None));
+        assert_eq!(parse_range("items=0-1", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=a-b", 1000), Ok(None));
+    }
+    #[test]
+    fn returns_large_ranges_in_chunks() {
+        let len = 3 * MAX_CHUNK_LEN;
+        let content = Content {
+            mime: "image/png".to_string(),
+            len,
+            etag: "\"1\"".to_string(),
+            last_modified: None,
+            read: Box::new(|range| Ok(vec![0; (range.end - range.start) as usize])),
+        };
+        let request = Request::builder()
+            .header(header::RANGE, "bytes=0-")
+            .body(Vec::new())
+            .unwrap();
+        let response = respond(&request, content);
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.body().len() as u64, MAX_CHUNK_LEN);
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            format!("bytes 0-{}/{}", MAX_CHUNK_LEN - 1, len)
+        );
+    }
+}
@@ -0,0 +1,283 @@
+//! 検索クエリ言語
+//!
+//! ```text
+//! world:"Great Pug" AND (player:Alice OR player:Bob) -player:Carol after:2024-01-01
+//! ```
+//!
+//! - `フィールド:値` は完全一致、`フィールド~値` は部分一致
+//! - フィールドを付けない値は、ワールド名・プレイヤー名・ファイルパスの部分一致
+//! - 空白を含む値は `"..."` で囲む（`\"` でダブルクォート自体を書ける）
+//! - `AND` / `OR` / `NOT`（大文字のみ）と括弧で条件を組み合わせる。`-条件` は `NOT 条件` と同じ
+//! - 演算子を省略して並べた条件は `AND` で結合する。優先順位は `NOT` > `AND` > `OR`
+//!
+//! | フィールド | 対象 |
+//! |---|---|
+//! | `world` | ワールド名（`wrld_` で始まる値はワールドID） |
+//! | `player` | 同じインスタンスにいたプレイヤーの表示名（`usr_` で始まる値はユーザーID） |
+//! | `person` | プレイヤーを表示名の変更にかかわらずユーザーIDで照合 |
+//! | `path` | ファイルパス |
+//! | `instance_type` | インスタンスの公開範囲（`public` / `friends+` / `friends` / `invite+` / `invite` / `group`） |
+//! | `region` | インスタンスのリージョン（`us` / `use` / `eu` / `jp`） |
+//! | `owner` | インスタンスの作成者の表示名（`usr_` / `grp_` で始まる値はユーザーID・グループID） |
+//! | `session` | 滞在のID |
+//! | `tag` | 画像に付けたタグ |
+//! | `favorite` | お気に入り（`favorite:true` / `favorite:false`） |
+//! | `rating` | 評価（`rating:4` / `rating:>=4` のように比較もできる） |
+//! | `after` / `before` / `on` | 撮影日時（`2024-01-01` または `2024-01-01T21:00`） |
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+
+mod compiler;
+mod lexer;
+mod parser;
+
+pub(crate) use compiler::{compile, escape_like};
+use parser::{favorite_value, rating_value};
+pub(crate) use parser::{parse, parse_date};
+
+/// 検索条件の構文木
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Term(Term),
+}
+
+/// 1つの条件（`player:Alice` など）
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Term {
+    pub field: Field,
+    pub op: Op,
+    pub value: String,
+}
+
+/// 条件の対象
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Field {
+    /// フィールド指定なし（ワールド名・プレイヤー名・ファイルパスのいずれか）
+    Any,
+    World,
+    Player,
+    Person,
+    FilePath,
+    /// インスタンスの公開範囲（`instances.access_type`）
+    InstanceType,
+    /// インスタンスのリージョン（`instances.region`）
+    Region,
+    /// インスタンスの作成者（`instances.owner_id`）
+    Owner,
+    /// 滞在（`images.session_id`）
+    Session,
+    /// 画像に付けたタグ（`image_tags.tag`）
+    Tag,
+    /// お気に入り（値は `true` / `false`）
+    Favorite,
+    /// 評価（1〜5）
+    Rating,
+    /// 撮影日時（`images.file_created_at`）
+    CapturedAt,
+}
+
+/// 比較演算子
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    /// 部分一致
+    Like,
+}
+
+impl Op {
+    pub(crate) fn as_sql(&self) -> &'static str {
+        match self {
+            Op::Eq => "=",
+            Op::Ne => "!=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Like => "LIKE",
+        }
+    }
+}
+
+impl Expr {
+    pub(crate) fn term(field: Field, op: Op, value: impl Into<String>) -> Expr {
+        Expr::Term(Term {
+            field,
+            op,
+            value: value.into(),
+        })
+    }
+
+    pub(crate) fn and(left: Expr, right: Expr) -> Expr {
+        Expr::And(Box::new(left), Box::new(right))
+    }
+
+    pub(crate) fn or(left: Expr, right: Expr) -> Expr {
+        Expr::Or(Box::new(left), Box::new(right))
+    }
+
+    /// 検索画面の条件リスト（`logic` / `field` / `operator` / `value`）を構文木に変換する
+    ///
+    /// 各条件の `logic` は直前の条件との結合方法を表し、SQLと同じく `AND` が `OR` より優先される。
+    /// 値の無い条件は無視し、条件が1つも無い場合は `None` を返す。
+    /// お気に入りと評価の値はクエリ文字列と同じく検証し、不正な場合はエラーを返す。
+    pub(crate) fn from_conditions(
+        conditions: &[HashMap<String, String>],
+    ) -> Result<Option<Expr>, QueryError> {
+        // ORで区切られたANDのまとまり
+        let mut groups: Vec<Expr> = Vec::new();
+        let mut current: Option<Expr> = None;
+
+        for condition in conditions {
+            let value = condition
+                .get("value")
+                .map(String::as_str)
+                .unwrap_or_default();
+            if value.is_empty() {
+                continue; // 値のない条件は無視
+            }
+            let field = match condition
+                .get("field")
+                .map(String::as_str)
+                .unwrap_or_default()
+            {
+                "player" => Field::Player,
+                "person" => Field::Person,
+                "world" => Field::World,
+                "instance_type" => Field::InstanceType,
+                "region" => Field::Region,
+                "owner" => Field::Owner,
+                "session" => Field::Session,
+                "tag" => Field::Tag,
+                "favorite" => Field::Favorite,
+                "rating" => Field::Rating,
+                "created_at" => Field::CapturedAt,
+                _ => Field::FilePath,
+            };
+            let op = match condition
+                .get("operator")
+                .map(|o| o.to_uppercase())
+                .unwrap_or_default()
+                .as_str()
+            {
+                "NE" => Op::Ne,
+                "GT" => Op::Gt,
+                "GE" => Op::Ge,
+                "LT" => Op::Lt,
+                "LE" => Op::Le,
+                "LIKE" => Op::Like,
+                _ if field == Field::FilePath => Op::Like,
+                _ => Op::Eq,
+            };
+            let value = match field {
+                Field::Favorite => favorite_value(value),
+                Field::Rating => rating_value(value),
+                _ => Ok(value),
+            }
+            .map_err(|message| QueryError {
+                message: message.to_string(),
+                position: None,
+                token: Some(value.to_string()),
+            })?;
+            let term = Expr::term(field, op, value);
+
+            let is_or = condition
+                .get("logic")
+                .is_some_and(|logic| logic.eq_ignore_ascii_case("OR"));
+            current = Some(match current.take() {
+                None => term,
+                Some(previous) if is_or => {
+                    groups.push(previous);
+                    term
+                }
+                Some(previous) => Expr::and(previous, term),
+            });
+        }
+        groups.extend(current);
+        Ok(groups.into_iter().reduce(Expr::or))
+    }
+}
+
+/// クエリの解析エラー
+///
+/// `position` はクエリ文字列中の位置（文字単位）で、入力欄で該当箇所を示すのに使う。
+/// データベースのエラーなど、クエリ中の位置と関係ないエラーでは `None` になる。
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QueryError {
+    pub message: String,
+    pub position: Option<usize>,
+    pub token: Option<String>,
+}
+
+impl QueryError {
+    pub(crate) fn at(
+        message: impl Into<String>,
+        position: usize,
+        token: impl Into<String>,
+    ) -> Self {
+        QueryError {
+            message: message.into(),
+            position: Some(position),
+            token: Some(token.into()),
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.position, &self.token) {
+            (Some(position), Some(token)) => {
+                write!(f, "{}（{}文字目: {}）", self.message, position + 1, token)
+            }
+            (Some(position), None) => write!(f, "{}（{}文字目）", self.message, position + 1),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for QueryError {
+    fn from(e: rusqlite::Error) -> Self {
+        QueryError {
+            message: e.to_string(),
+            position: None,
+            token: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn condition(field: &str, value: &str) -> HashMap<String, String> {
+        HashMap::from([
+            ("field".to_string(), field.to_string()),
+            ("value".to_string(), value.to_string()),
+        ])
+    }
+
+    #[test]
+    fn validates_favorite_and_rating_conditions() {
+        assert_eq!(
+            Expr::from_conditions(&[condition("favorite", "yes")]).unwrap(),
+            Some(Expr::term(Field::Favorite, Op::Eq, "true"))
+        );
+        assert_eq!(
+            Expr::from_conditions(&[condition("rating", "4")]).unwrap(),
+            Some(Expr::term(Field::Rating, Op::Eq, "4"))
+        );
+
+        let error = Expr::from_conditions(&[condition("favorite", "maybe")]).unwrap_err();
+        assert_eq!(error.token.as_deref(), Some("maybe"));
+        assert!(Expr::from_conditions(&[condition("rating", "good")]).is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use super::{Expr, Field, Op, Term};
+
+/// `images` テーブルに対するWHERE句の条件と、そのバインド値
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct CompiledQuery {
+    pub sql: String,
+    pub params: Vec<String>,
+}
+
+struct Compiler {
+    params: Vec<String>,
+    alias_count: usize,
+}
+
+impl Compiler {
+    fn alias(&mut self, prefix: &str) -> String {
+        self.alias_count += 1;
+        format!("{}{}", prefix, self.alias_count)
+    }
+
+    /// プレースホルダーを1つ追加して `?` を返す
+    ///
+    /// 部分一致の場合は値に含まれる `%` `_` をエスケープし、`ESCAPE` 句を付けて返す。
+    fn bind(&mut self, op: Op, value: &str) -> &'static str {
+        if op == Op::Like {
+            self.params.push(format!("%{}%", escape_like(value)));
+            "? ESCAPE '\\'"
+        } else {
+            self.params.push(value.to_string());
+            "?"
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> String {
+        match expr {
+            Expr::And(left, right) => format!("({} AND {})", self.expr(left), self.expr(right)),
+            Expr::Or(left, right) => format!("({} OR {})", self.expr(left), self.expr(right)),
+            Expr::Not(inner) => format!("NOT ({})", self.expr(inner)),
+            Expr::Term(term) => self.term(term),
+        }
+    }
+
+    fn world(&mut self, op: Op, value: &str) -> String {
+        // wrld_ で始まる値はワールドIDとして比較する
+        if value.starts_with("wrld_") {
+            return format!("images.world_id {} {}", op.as_sql(), self.bind(op, value));
+        }
+        // ワールドIDの無い写真や改名前の名前でも見つかるよう、写真のメタデータの名前とも比較する
+        let alias = self.alias("world");
+        let name = self.bind(op, value);
+        let latest_name = self.bind(op, value);
+        format!(
+            "(json_extract(images.metadata_json, '$.world.name') {op} {} \
+             OR EXISTS (SELECT 1 FROM worlds AS {a} WHERE {a}.world_id = images.world_id AND {a}.name {op} {}))",
+            name,
+            latest_name,
+            a = alias,
+            op = op.as_sql()
+        )
+    }
+
+    fn player(&mut self, op: Op, value: &str) -> String {
+        let alias = self.alias("player");
+        // usr_ で始まる値はユーザーIDとして比較する
+        let column = if value.starts_with("usr_") {
+            "user_id"
+        } else {
+            "display_name"
+        };
+        format!(
+            "EXISTS (SELECT 1 FROM image_players AS {a} WHERE {a}.image_id = images.id AND {a}.{} {} {})",
+            column,
+            op.as_sql(),
+            self.bind(op, value),
+            a = alias
+        )
+    }
+
+    fn person(&mut self, op: Op, value: &str) -> String {
+        if value.starts_with("usr_") {
+            return self.player(op, value);
+        }
+        // 表示名からユーザーIDを引き、撮影時の表示名が違う写真も含める
+        let alias = self.alias("player");
+        let name = self.bind(op, value);
+        let id_name = self.bind(op, value);
+        format!(
+            "EXISTS (SELECT 1 FROM image_players AS {a} WHERE {a}.image_id = images.id AND ({a}.display_name {op} {} \
+             OR {a}.user_id IN (SELECT user_id FROM image_players WHERE user_id IS NOT NULL AND display_name {op} {})))",
+            name,
+            id_name,
+            a = alias,
+            op = op.as_sql()
+        )
+    }
+
+    /// 写真を撮影したインスタンスの列を比較する
+    fn instance(&mut self, column: &str, op: Op, value: &str) -> String {
+        let alias = self.alias("instance");
+        format!(
+            "EXISTS (SELECT 1 FROM instances AS {a} WHERE {a}.instance_id = images.instance_id AND {a}.{} {} {})",
+            column,
+            op.as_sql(),
+            self.bind(op, value),
+            a = alias
+        )
+    }
+
+    fn owner(&mut self, op: Op, value: &str) -> String {
+        // usr_ / grp_ で始まる値はIDとして比較し、それ以外は作成者の表示名で比較する
+        if value.starts_with("usr_") || value.starts_with("grp_") {
+            return self.instance("owner_id", op, value);
+        }
+        let alias = self.alias("instance");
+        format!(
+            "EXISTS (SELECT 1 FROM instances AS {a} WHERE {a}.instance_id = images.instance_id \
+             AND {a}.owner_id IN (SELECT user_id FROM players WHERE display_name {} {}))",
+            op.as_sql(),
+            self.bind(op, value),
+            a = alias
+        )
+    }
+
+    fn tag(&mut self, op: Op, value: &str) -> String {
+        let alias = self.alias("tag");
+        format!(
+            "EXISTS (SELECT 1 FROM image_tags AS {a} WHERE {a}.file_path = images.file_path AND {a}.tag {} {})",
+            op.as_sql(),
+            self.bind(op, value),
+            a = alias
+        )
+    }
+
+    fn favorite(&mut self, op: Op, value: &str) -> String {
+        let alias = self.alias("annotation");
+        let favorite = matches!(value.to_lowercase().as_str(), "true" | "yes" | "1");
+        // favorite:false と favorite!=true はお気に入りでない（注釈の無い）画像も含める
+        let negate = if favorite == (op != Op::Ne) {
+            ""
+        } else {
+            "NOT "
+        };
+        format!(
+            "{}EXISTS (SELECT 1 FROM image_annotations AS {a} WHERE {a}.file_path = images.file_path AND {a}.favorite = 1)",
+            negate,
+            a = alias
+        )
+    }
+
+    fn rating(&mut self, op: Op, value: &str) -> String {
+        let alias = self.alias("annotation");
+        format!(
+            "EXISTS (SELECT 1 FROM image_annotations AS {a} WHERE {a}.file_path = images.file_path AND {a}.rating {} {})",
+            op.as_sql(),
+            self.bind(op, value),
+            a = alias
+        )
+    }
+
+    fn term(&mut self, term: &Term) -> String {
+        let Term { field, op, value } = term;
+        match field {
+            // 「Xがいない」は「X以外の誰かがいる」ではなく、Xが写っていない写真とする
+            Field::Player | Field::Person if *op == Op::Ne => {
+                let present = Term {
+                    field: *field,
+                    op: Op::Eq,
+                    value: value.clone(),
+                };
+                format!("NOT {}", self.term(&present))
+            }
+            Field::World => self.world(*op, value),
+            Field::Player => self.player(*op, value),
+            Field::Person => self.person(*op, value),
+            Field::InstanceType => self.instance("access_type", *op, value),
+            Field::Region => self.instance("region", *op, value),
+            Field::Owner => self.owner(*op, value),
+            Field::Tag => self.tag(*op, value),
+            Field::Favorite => self.favorite(*op, value),
+            Field::Rating => self.rating(*op, value),
+            Field::Session => format!(
+                "images.session_id {} {}",
+                op.as_sql(),
+                self.bind(*op, value)
+            ),
+            Field::FilePath => {
+                format!("images.file_path {} {}", op.as_sql(), self.bind(*op, value))
+            }
+            Field::CapturedAt => format!(
+                "images.file_created_at {} {}",
+                op.as_sql(),
+                self.bind(*op, value)
+            ),
+            Field::Any => {
+                let world = self.world(*op, value);
+                let player = self.player(*op, value);
+                let path = format!("images.file_path {} {}", op.as_sql(), self.bind(*op, value));
+                format!("({} OR {} OR {})", world, player, path)
+            }
+        }
+    }
+}
+
+/// LIKEのパターンとして文字どおりに一致するよう、`\` `%` `_` をエスケープする（`ESCAPE '\'` と組み合わせて使う）
+pub(crate) fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// 構文木をSQLの条件式に変換する（値はすべてプレースホルダーでバインドする）
+pub(crate) fn compile(expr: &Expr) -> CompiledQuery {
+    let mut compiler = Compiler {
+        params: Vec::new(),
+        alias_count: 0,
+    };
+    let sql = compiler.expr(expr);
+    CompiledQuery {
+        sql,
+        params: compiler.params,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::parse;
+
+    #[test]
+    fn binds_every_value() {
+        let expr = parse(r#"world:"Great Pug" (player:Alice OR person:Bob) -path~old"#)
+            .unwrap()
+            .unwrap();
+        let compiled = compile(&expr);
+        assert_eq!(compiled.sql.matches('?').count(), compiled.params.len());
+        assert_eq!(
+            compiled.params,
+            ["Great Pug", "Great Pug", "Alice", "Bob", "Bob", "%old%"]
+        );
+        assert!(!compiled.sql.contains("Alice"));
+    }
+
+    #[test]
+    fn matches_players_by_name_or_user_id() {
+        let compiled = compile(&Expr::term(Field::Player, Op::Eq, "usr_alice"));
+        assert!(compiled.sql.contains("player1.user_id = ?"));
+
+        let compiled = compile(&Expr::term(Field::Person, Op::Eq, "Alice"));
+        assert!(compiled.sql.contains("player1.display_name = ?"));
+        assert!(compiled.sql.contains("player1.user_id IN (SELECT user_id"));
+        assert_eq!(compiled.params, ["Alice", "Alice"]);
+    }
+
+    #[test]
+    fn player_not_equal_means_not_present() {
+        let compiled = compile(&Expr::term(Field::Player, Op::Ne, "Alice"));
+        assert!(compiled.sql.starts_with("NOT EXISTS"));
+        assert!(compiled.sql.contains("player1.display_name = ?"));
+        assert!(!compiled.sql.contains("!="));
+
+        let compiled = compile(&Expr::term(Field::Person, Op::Ne, "usr_alice"));
+        assert!(compiled.sql.starts_with("NOT EXISTS"));
+        assert!(compiled.sql.contains("player1.user_id = ?"));
+    }
+
+    #[test]
+    fn escapes_like_wildcards() {
+        let compiled = compile(&Expr::term(Field::FilePath, Op::Like, r"100%_a\b"));
+        assert_eq!(compiled.sql, r"images.file_path LIKE ? ESCAPE '\'");
+        assert_eq!(compiled.params, [r"%100\%\_a\\b%"]);
+
+        // 完全一致では値をそのまま使う
+        let compiled = compile(&Expr::term(Field::FilePath, Op::Eq, "100%"));
+        assert_eq!(compiled.sql, "images.file_path = ?");
+        assert_eq!(compiled.params, ["100%"]);
+    }
+}
@@ -0,0 +1,168 @@
+use super::QueryError;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum TokenKind {
+    LParen,
+    RParen,
+    And,
+    Or,
+    Not,
+    /// 条件の先頭の `-`（NOTと同じ）
+    Minus,
+    /// `field:value`（exact = true）または `field~value`（exact = false）
+    Field {
+        name: String,
+        exact: bool,
+        value: String,
+    },
+    /// フィールド指定の無い値
+    Value(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Token {
+    pub kind: TokenKind,
+    /// クエリ文字列中の開始位置（文字単位）
+    pub position: usize,
+    /// エラー表示用の元の文字列
+    pub text: String,
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Lexer {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn text_from(&self, start: usize) -> String {
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    /// `"..."` を読む（`\"` と `\\` はエスケープとして扱う）
+    fn quoted(&mut self) -> Result<String, QueryError> {
+        let start = self.pos;
+        self.pos += 1; // 開始の "
+        let mut value = String::new();
+        while let Some(c) = self.peek() {
+            self.pos += 1;
+            match c {
+                '"' => return Ok(value),
+                '\\' if matches!(self.peek(), Some('"') | Some('\\')) => {
+                    value.push(self.chars[self.pos]);
+                    self.pos += 1;
+                }
+                _ => value.push(c),
+            }
+        }
+        Err(QueryError::at(
+            "ダブルクォートが閉じられていません",
+            start,
+            self.text_from(start),
+        ))
+    }
+
+    /// 区切り文字までの文字列を読む
+    fn word(&mut self, is_end: impl Fn(char) -> bool) -> String {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| !c.is_whitespace() && !is_end(c))
+        {
+            self.pos += 1;
+        }
+        self.text_from(start)
+    }
+
+    fn next_token(&mut self) -> Result<Option<Token>, QueryError> {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+        let start = self.pos;
+        let Some(c) = self.peek() else {
+            return Ok(None);
+        };
+
+        let kind = match c {
+            '(' => {
+                self.pos += 1;
+                TokenKind::LParen
+            }
+            ')' => {
+                self.pos += 1;
+                TokenKind::RParen
+            }
+            '-' if self
+                .chars
+                .get(self.pos + 1)
+                .is_some_and(|next| !next.is_whitespace()) =>
+            {
+                self.pos += 1;
+                TokenKind::Minus
+            }
+            '"' => TokenKind::Value(self.quoted()?),
+            _ => {
+                let word = self.word(|c| matches!(c, '(' | ')' | '"' | ':' | '~'));
+                match self.peek() {
+                    Some(separator @ (':' | '~')) if !word.is_empty() => {
+                        self.pos += 1;
+                        // 値には日時の `:` などを含められる
+                        let value = if self.peek() == Some('"') {
+                            self.quoted()?
+                        } else {
+                            self.word(|c| c == ')')
+                        };
+                        if value.is_empty() {
+                            return Err(QueryError::at(
+                                "条件の値がありません",
+                                start,
+                                self.text_from(start),
+                            ));
+                        }
+                        TokenKind::Field {
+                            name: word,
+                            exact: separator == ':',
+                            value,
+                        }
+                    }
+                    _ if word.is_empty() => {
+                        // 単独の : や ~
+                        self.pos += 1;
+                        return Err(QueryError::at(
+                            "フィールド名がありません",
+                            start,
+                            self.text_from(start),
+                        ));
+                    }
+                    _ => match word.as_str() {
+                        "AND" => TokenKind::And,
+                        "OR" => TokenKind::Or,
+                        "NOT" => TokenKind::Not,
+                        _ => TokenKind::Value(word),
+                    },
+                }
+            }
+        };
+        Ok(Some(Token {
+            kind,
+            position: start,
+            text: self.text_from(start),
+        }))
+    }
+}
+
+/// クエリ文字列をトークンに分割する
+pub(super) fn tokenize(query: &str) -> Result<Vec<Token>, QueryError> {
+    let mut lexer = Lexer {
+        chars: query.chars().collect(),
+        pos: 0,
+    };
+    let mut tokens = Vec::new();
+    while let Some(token) = lexer.next_token()? {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
@@ -0,0 +1,344 @@
+use super::lexer::{tokenize, Token, TokenKind};
+use super::{Expr, Field, Op, QueryError};
+use crate::metadata::parse_metadata_time;
+use chrono::{DateTime, Days, Local, NaiveDate, TimeZone, Utc};
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// クエリの長さ（末尾で条件が足りない場合のエラー位置）
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    /// or := and ("OR" and)*
+    fn or(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.and()?;
+        while self.peek().is_some_and(|t| t.kind == TokenKind::Or) {
+            self.pos += 1;
+            expr = Expr::or(expr, self.and()?);
+        }
+        Ok(expr)
+    }
+
+    /// and := unary (["AND"] unary)*
+    fn and(&mut self) -> Result<Expr, QueryError> {
+        let mut expr = self.unary()?;
+        loop {
+            match self.peek().map(|t| &t.kind) {
+                Some(TokenKind::And) => self.pos += 1,
+                // 演算子を省略した場合もANDとして扱う
+                Some(
+                    TokenKind::LParen
+                    | TokenKind::Not
+                    | TokenKind::Minus
+                    | TokenKind::Field { .. }
+                    | TokenKind::Value(_),
+                ) => {}
+                _ => return Ok(expr),
+            }
+            expr = Expr::and(expr, self.unary()?);
+        }
+    }
+
+    /// unary := ("NOT" | "-") unary | primary
+    fn unary(&mut self) -> Result<Expr, QueryError> {
+        if self
+            .peek()
+            .is_some_and(|t| matches!(t.kind, TokenKind::Not | TokenKind::Minus))
+        {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    /// primary := "(" or ")" | field | value
+    fn primary(&mut self) -> Result<Expr, QueryError> {
+        let Some(token) = self.next() else {
+            return Err(QueryError {
+                message: "条件がありません".to_string(),
+                position: Some(self.end),
+                token: None,
+            });
+        };
+        match token.kind {
+            TokenKind::LParen => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token {
+                        kind: TokenKind::RParen,
+                        ..
+                    }) => Ok(expr),
+                    _ => Err(QueryError::at(
+                        "括弧が閉じられていません",
+                        token.position,
+                        token.text,
+                    )),
+                }
+            }
+            TokenKind::Field { name, exact, value } => {
+                field_term(&name, exact, value, token.position, &token.text)
+            }
+            TokenKind::Value(value) => Ok(Expr::term(Field::Any, Op::Like, value)),
+            TokenKind::RParen => Err(QueryError::at(
+                "対応する開き括弧がありません",
+                token.position,
+                token.text,
+            )),
+            TokenKind::And | TokenKind::Or | TokenKind::Not | TokenKind::Minus => Err(
+                QueryError::at("演算子の前後に条件がありません", token.position, token.text),
+            ),
+        }
+    }
+}
+
+/// お気に入りの値（true / yes / 1、false / no / 0）を `true` / `false` にそろえる
+///
+/// 検索画面の条件リストの値にも使う。
+pub(super) fn favorite_value(value: &str) -> Result<&'static str, &'static str> {
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "1" => Ok("true"),
+        "false" | "no" | "0" => Ok("false"),
+        _ => Err("お気に入りは true / false で指定してください"),
+    }
+}
+
+/// 評価の値が数値であることを確かめる（検索画面の条件リストの値にも使う）
+pub(super) fn rating_value(value: &str) -> Result<&str, &'static str> {
+    match value.parse::<u8>() {
+        Ok(_) => Ok(value),
+        Err(_) => Err("評価は数値で指定してください"),
+    }
+}
+
+/// `field:value` / `field~value` を条件に変換する
+fn field_term(
+    name: &str,
+    exact: bool,
+    value: String,
+    position: usize,
+    text: &str,
+) -> Result<Expr, QueryError> {
+    let text_op = if exact { Op::Eq } else { Op::Like };
+    let field = match name.to_lowercase().as_str() {
+        "world" => Field::World,
+        "player" => Field::Player,
+        "person" => Field::Person,
+        "path" => Field::FilePath,
+        "instance_type" => Field::InstanceType,
+        "region" => Field::Region,
+        "owner" => Field::Owner,
+        "session" => Field::Session,
+        "tag" => Field::Tag,
+        "favorite" => {
+            let favorite = favorite_value(&value)
+                .map_err(|message| QueryError::at(message, position, text))?;
+            return Ok(Expr::term(Field::Favorite, Op::Eq, favorite));
+        }
+        "rating" => {
+            // rating:>=4 のような比較
+            let (op, number) = [(">=", Op::Ge), ("<=", Op::Le), (">", Op::Gt), ("<", Op::Lt)]
+                .into_iter()
+                .find_map(|(prefix, op)| value.strip_prefix(prefix).map(|rest| (op, rest)))
+                .unwrap_or((Op::Eq, value.as_str()));
+            let number =
+                rating_value(number).map_err(|message| QueryError::at(message, position, text))?;
+            return Ok(Expr::term(Field::Rating, op, number));
+        }
+        "after" | "before" | "on" => {
+            let invalid_date = || QueryError::at("日時として解釈できません", position, text);
+            let name = name.to_lowercase();
+            if name == "on" {
+                // その日の0時から翌日の0時まで
+                let date =
+                    NaiveDate::parse_from_str(&value, "%Y-%m-%d").map_err(|_| invalid_date())?;
+                let start = local_midnight(date).ok_or_else(invalid_date)?;
+                let end = date
+                    .checked_add_days(Days::new(1))
+                    .and_then(local_midnight)
+                    .ok_or_else(invalid_date)?;
+                return Ok(Expr::and(
+                    Expr::term(Field::CapturedAt, Op::Ge, start.to_rfc3339()),
+                    Expr::term(Field::CapturedAt, Op::Lt, end.to_rfc3339()),
+                ));
+            }
+            let time = parse_date(&value).ok_or_else(invalid_date)?;
+            let op = if name == "after" { Op::Ge } else { Op::Lt };
+            return Ok(Expr::term(Field::CapturedAt, op, time.to_rfc3339()));
+        }
+        _ => return Err(QueryError::at("不明なフィールドです", position, text)),
+    };
+    Ok(Expr::term(field, text_op, value))
+}
+
+fn local_midnight(date: NaiveDate) -> Option<DateTime<Utc>> {
+    Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+        .earliest()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+/// `2024-01-01`（ローカル時刻の0時）または日時
+pub(crate) fn parse_date(value: &str) -> Option<DateTime<Utc>> {
+    match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(date) => local_midnight(date),
+        Err(_) => parse_metadata_time(value),
+    }
+}
+
+/// クエリ文字列を構文木に変換する（空のクエリは `None`）
+pub(crate) fn parse(query: &str) -> Result<Option<Expr>, QueryError> {
+    let tokens = tokenize(query)?;
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        end: query.chars().count(),
+    };
+    let expr = parser.or()?;
+    // or() の後に残るのは対応しない閉じ括弧のみ
+    if let Some(token) = parser.next() {
+        return Err(QueryError::at(
+            "対応する開き括弧がありません",
+            token.position,
+            token.text,
+        ));
+    }
+    Ok(Some(expr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(field: Field, op: Op, value: &str) -> Expr {
+        Expr::term(field, op, value)
+    }
+
+    fn not(expr: Expr) -> Expr {
+        Expr::Not(Box::new(expr))
+    }
+
+    #[test]
+    fn parses_grouping_and_negation() {
+        let expr = parse(r#"world:"Great Pug" AND (player:Alice OR player~Bob) -player:Carol"#)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            expr,
+            Expr::and(
+                Expr::and(
+                    term(Field::World, Op::Eq, "Great Pug"),
+                    Expr::or(
+                        term(Field::Player, Op::Eq, "Alice"),
+                        term(Field::Player, Op::Like, "Bob"),
+                    ),
+                ),
+                not(term(Field::Player, Op::Eq, "Carol")),
+            )
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let expr = parse("a OR b c NOT d").unwrap().unwrap();
+        assert_eq!(
+            expr,
+            Expr::or(
+                term(Field::Any, Op::Like, "a"),
+                Expr::and(
+                    Expr::and(
+                        term(Field::Any, Op::Like, "b"),
+                        term(Field::Any, Op::Like, "c")
+                    ),
+                    not(term(Field::Any, Op::Like, "d")),
+                ),
+            )
+        );
+    }
+
+    #[test]
+    fn parses_dates() {
+        let Some(Expr::Term(after)) = parse("after:2024-01-01T21:30").unwrap() else {
+            panic!("term expected");
+        };
+        assert_eq!((after.field, after.op), (Field::CapturedAt, Op::Ge));
+        assert!(matches!(
+            parse("on:2024-01-01").unwrap(),
+            Some(Expr::And(_, _))
+        ));
+    }
+
+    #[test]
+    fn parses_favorite_as_boolean() {
+        assert_eq!(
+            parse("favorite:yes").unwrap(),
+            Some(term(Field::Favorite, Op::Eq, "true"))
+        );
+        assert_eq!(
+            parse("favorite~0").unwrap(),
+            Some(term(Field::Favorite, Op::Eq, "false"))
+        );
+    }
+
+    #[test]
+    fn parses_rating_comparison() {
+        assert_eq!(
+            parse("rating:>=4").unwrap(),
+            Some(term(Field::Rating, Op::Ge, "4"))
+        );
+        assert_eq!(
+            parse("rating:5").unwrap(),
+            Some(term(Field::Rating, Op::Eq, "5"))
+        );
+    }
+
+    #[test]
+    fn empty_query_matches_everything() {
+        assert_eq!(parse("   ").unwrap(), None);
+    }
+
+    #[test]
+    fn reports_error_position() {
+        let error = parse("world:A AND (player:B").unwrap_err();
+        assert_eq!(error.position, Some(12));
+        assert_eq!(error.token.as_deref(), Some("("));
+
+        let error = parse(r#"player:"Alice"#).unwrap_err();
+        assert_eq!(error.position, Some(7));
+
+        let error = parse("colour:red").unwrap_err();
+        assert_eq!(
+            (error.position, error.token.as_deref()),
+            (Some(0), Some("colour:red"))
+        );
+
+        let error = parse("rating:good").unwrap_err();
+        assert_eq!(error.position, Some(0));
+
+        let error = parse("a favorite:maybe").unwrap_err();
+        assert_eq!(error.position, Some(2));
+
+        let error = parse("after:yesterday").unwrap_err();
+        assert_eq!(error.position, Some(0));
+
+        let error = parse("a OR").unwrap_err();
+        assert_eq!((error.position, error.token), (Some(4), None));
+
+        let error = parse("a )").unwrap_err();
+        assert_eq!(error.position, Some(2));
+    }
+}
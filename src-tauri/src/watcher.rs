@@ -0,0 +1,341 @@
+use crate::db::{init_db, is_ignored, is_image_file, process_image_file, session_gap};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+
+/// 最後の変更から登録までの待機時間（VRCXがiTXtを追記し終えるのを待つ）
+const SETTLE_DURATION: Duration = Duration::from_secs(3);
+/// 書き込み完了を確認できなくても登録を行うまでの上限時間
+const GIVE_UP_DURATION: Duration = Duration::from_secs(60);
+/// 保留中ファイルを確認する間隔
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// PNGファイル末尾のIENDチャンク
+const PNG_TRAILER: [u8; 12] = [
+    0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+];
+/// JPEGファイル末尾のEOIマーカー
+const JPEG_TRAILER: [u8; 2] = [0xFF, 0xD9];
+
+/// 監視対象のフォルダと除外フォルダ
+#[derive(Default)]
+struct WatchRoots {
+    folders: Vec<(PathBuf, String)>, // (フォルダパス, UUID)
+    ignore_dirs: HashSet<PathBuf>,
+}
+
+impl WatchRoots {
+    /// ファイルが属する登録フォルダのUUIDを返す（除外対象ならNone）
+    fn resolve(&self, path: &Path) -> Option<String> {
+        if is_ignored(path, &self.ignore_dirs) {
+            return None;
+        }
+        // 入れ子になった登録フォルダがある場合は最も深いフォルダを優先
+        self.folders
+            .iter()
+            .filter(|(folder, _)| path.starts_with(folder))
+            .max_by_key(|(folder, _)| folder.components().count())
+            .map(|(_, uuid)| uuid.clone())
+    }
+}
+
+struct WatcherInner {
+    watcher: RecommendedWatcher,
+    watched: Vec<PathBuf>,
+}
+
+/// フォルダ監視の状態（Tauriのstateとして管理）
+pub struct FolderWatcher {
+    inner: Mutex<WatcherInner>,
+    roots: Arc<Mutex<WatchRoots>>,
+}
+
+/// 書き込み完了待ちのファイル
+struct PendingFile {
+    uuid: String,
+    first_seen: Instant,
+    last_event: Instant,
+    last_size: u64,
+}
+
+/// 画像登録完了時にフロントエンドへ通知するイベント
+#[derive(Clone, Serialize)]
+struct ImageIndexedEvent {
+    file_path: String,
+    uuid: String,
+}
+
+/// フォルダ監視を開始する
+pub fn start_watcher(app: &AppHandle) -> Result<(), String> {
+    let (tx, rx) = channel();
+    let watcher = notify::recommended_watcher(move |res: notify::Result<Event>| {
+        // 受信側が終了している場合は何もしない
+        let _ = tx.send(res);
+    })
+    .map_err(|e| e.to_string())?;
+
+    let roots = Arc::new(Mutex::new(WatchRoots::default()));
+    app.manage(FolderWatcher {
+        inner: Mutex::new(WatcherInner {
+            watcher,
+            watched: Vec::new(),
+        }),
+        roots: roots.clone(),
+    });
+
+    let app_handle = app.clone();
+    thread::spawn(move || run_event_loop(app_handle, rx, roots));
+
+    refresh_watcher(app)
+}
+
+/// 登録フォルダ・除外フォルダの変更を監視対象に反映する
+pub fn refresh_watcher(app: &AppHandle) -> Result<(), String> {
+    let Some(state) = app.try_state::<FolderWatcher>() else {
+        // 監視が開始されていない場合は何もしない
+        return Ok(());
+    };
+
+    let conn = init_db(app).map_err(|e| e.to_string())?;
+    let folders: Vec<(PathBuf, String)> = conn
+        .prepare("SELECT path, uuid FROM search_folders")
+        .map_err(|e| e.to_string())?
+        .query_map([], |row| {
+            let folder: String = row.get(0)?; // フォルダパス
+            let uuid: String = row.get(1)?; // UUID
+            Ok((PathBuf::from(folder), uuid))
+        })
+        .map_err(|e| e.to_string())?
+        .filter_map(Result::ok)
+        .collect();
+    let ignore_dirs: HashSet<PathBuf> = conn
+        .prepare("SELECT path FROM ignore_folders")
+        .map_err(|e| e.to_string())?
+        .query_map([], |row| row.get::<_, String>(0))
+        .map_err(|e| e.to_string())?
+        .filter_map(Result::ok)
+        .map(PathBuf::from)
+        .collect();
+
+    let mut inner = state.inner.lock().map_err(|e| e.to_string())?;
+    let WatcherInner { watcher, watched } = &mut *inner;
+    for path in watched.drain(..) {
+        // 既に削除されたフォルダは監視解除に失敗するため無視
+        let _ = watcher.unwatch(&path);
+    }
+    for (folder, _) in &folders {
+        if !folder.is_dir() {
+            continue;
+        }
+        match watcher.watch(folder, RecursiveMode::Recursive) {
+            Ok(_) => watched.push(folder.clone()),
+            Err(e) => eprintln!("フォルダ監視の開始に失敗: {:?} - エラー: {}", folder, e),
+        }
+    }
+
+    let mut roots = state.roots.lock().map_err(|e| e.to_string())?;
+    roots.folders = folders;
+    roots.ignore_dirs = ignore_dirs;
+    Ok(())
+}
+
+fn run_event_loop(
+    app: AppHandle,
+    rx: Receiver<notify::Result<Event>>,
+    roots: Arc<Mutex<WatchRoots>>,
+) {
+    let mut pending: HashMap<PathBuf, PendingFile> = HashMap::new();
+    loop {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                if let Ok(roots) = roots.lock() {
+                    handle_event(&roots, &mut pending, event);
+                }
+            }
+            Ok(Err(e)) => eprintln!("フォルダ監視エラー: {}", e),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        flush_ready_files(&app, &mut pending);
+    }
+}
+
+fn handle_event(roots: &WatchRoots, pending: &mut HashMap<PathBuf, PendingFile>, event: Event) {
+    match event.kind {
+        EventKind::Create(_) | EventKind::Modify(_) => {
+            let now = Instant::now();
+            for path in event.paths {
+                if !is_image_file(&path) {
+                    continue;
+                }
+                let Some(uuid) = roots.resolve(&path) else {
+                    continue;
+                };
+                let size = std::fs::metadata(&path).map(|m| m.len()).unwrap_or(0);
+                pending
+                    .entry(path)
+                    .and_modify(|p| {
+                        p.last_event = now;
+                        p.last_size = size;
+                    })
+                    .or_insert(PendingFile {
+                        uuid,
+                        first_seen: now,
+                        last_event: now,
+                        last_size: size,
+                    });
+            }
+        }
+        EventKind::Remove(_) => {
+            for path in event.paths {
+                pending.remove(&path);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// 書き込みが落ち着いたファイルを登録する
+fn flush_ready_files(app: &AppHandle, pending: &mut HashMap<PathBuf, PendingFile>) {
+    let now = Instant::now();
+    // 滞在の区切りは登録するファイルがあるときだけ、1回の処理につき1回読む
+    let mut gap = None;
+    let candidates: Vec<PathBuf> = pending
+        .iter()
+        .filter(|(_, p)| now.duration_since(p.last_event) >= SETTLE_DURATION)
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    for path in candidates {
+        let Some(file) = pending.get_mut(&path) else {
+            continue;
+        };
+        let size = match std::fs::metadata(&path) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => {
+                // 移動・削除されたファイル
+                pending.remove(&path);
+                continue;
+            }
+        };
+        if size != file.last_size {
+            // まだ書き込み中
+            file.last_size = size;
+            file.last_event = now;
+            continue;
+        }
+        if !is_write_complete(&path) && now.duration_since(file.first_seen) < GIVE_UP_DURATION {
+            file.last_event = now;
+            continue;
+        }
+
+        let Some(file) = pending.remove(&path) else {
+            continue;
+        };
+        let gap = *gap.get_or_insert_with(|| session_gap(app));
+        match process_image_file(app, &path, &file.uuid, gap) {
+            Ok(_) => {
+                println!("自動登録成功: {:?}", path);
+                let event = ImageIndexedEvent {
+                    file_path: path.to_string_lossy().to_string(),
+                    uuid: file.uuid,
+                };
+                if let Err(e) = app.emit("image_indexed", event) {
+                    eprintln!("イベント送信失敗: {}", e);
+                }
+            }
+            Err(e) => eprintln!("自動登録失敗: {:?}, エラー: {}", path, e),
+        }
+    }
+}
+
+/// ファイル末尾の終端マーカーで書き込み完了を判定
+fn is_write_complete(path: &Path) -> bool {
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let trailer: &[u8] = match ext.as_str() {
+        "png" => &PNG_TRAILER,
+        "jpg" | "jpeg" => &JPEG_TRAILER,
+        _ => return true,
+    };
+
+    let Ok(mut file) = File::open(path) else {
+        return false;
+    };
+    if file.seek(SeekFrom::End(-(trailer.len() as i64))).is_err() {
+        return false;
+    }
+    let mut buffer = vec![0u8; trailer.len()];
+    file.read_exact(&mut buffer).is_ok() && buffer == trailer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolves_deepest_folder_outside_ignored_dirs() {
+        let roots = WatchRoots {
+            folders: vec![
+                (PathBuf::from("/photos"), "outer".to_string()),
+                (PathBuf::from("/photos/vrchat"), "inner".to_string()),
+            ],
+            ignore_dirs: HashSet::from([PathBuf::from("/photos/vrchat/private")]),
+        };
+        assert_eq!(
+            roots.resolve(Path::new("/photos/a.png")),
+            Some("outer".to_string())
+        );
+        assert_eq!(
+            roots.resolve(Path::new("/photos/vrchat/2024-01/a.png")),
+            Some("inner".to_string())
+        );
+        assert_eq!(
+            roots.resolve(Path::new("/photos/vrchat/private/a.png")),
+            None
+        );
+        assert_eq!(roots.resolve(Path::new("/other/a.png")), None);
+        // 名前の先頭だけが一致するフォルダは別のフォルダ
+        assert_eq!(roots.resolve(Path::new("/photos-old/a.png")), None);
+    }
+
+    #[test]
+    fn detects_complete_writes_from_trailer() {
+        let dir =
+            std::env::temp_dir().join(format!("vrcxphotosearcher-watcher-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, data: &[u8]| {
+            let path = dir.join(name);
+            std::fs::write(&path, data).unwrap();
+            path
+        };
+
+        let mut png = b"\x89PNG\r\n\x1a\n....".to_vec();
+        assert!(!is_write_complete(&write("partial.png", &png)));
+        png.extend_from_slice(&PNG_TRAILER);
+        assert!(is_write_complete(&write("complete.png", &png)));
+        assert!(!is_write_complete(&write(
+            "partial.jpg",
+            b"\xFF\xD8\xFF\xE0"
+        )));
+        assert!(is_write_complete(&write(
+            "complete.JPG",
+            b"\xFF\xD8\xFF\xE0\xFF\xD9"
+        )));
+        // 終端マーカーより短いファイルや存在しないファイルは書き込み中とみなす
+        assert!(!is_write_complete(&write("tiny.png", b"\x89")));
+        assert!(!is_write_complete(&dir.join("missing.png")));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,7 +1,29 @@
-import { invoke } from '@tauri-apps/api/core'
+import { convertFileSrc, invoke } from '@tauri-apps/api/core'
 import { listen } from '@tauri-apps/api/event'
 import type { Config } from '$lib/config'
 
+// サムネイルのURL（画像IDから vrcxthumb:// で取得する）
+export function thumbnailUrl(imageId: number): string {
+  return convertFileSrc(String(imageId), 'vrcxthumb')
+}
+
+// 元画像のURL（画像IDから vrcximg:// で取得する）
+export function imageUrl(imageId: number): string {
+  return convertFileSrc(String(imageId), 'vrcximg')
+}
+
+// バックエンドが返す画像の一覧の要素（ファイルパス, 画像ID, フォルダのUUID）
+type ImageRow = [string, number, string]
+
+// 画像IDをサムネイルのURLに置き換える（[ファイルパス, サムネイルのURL, フォルダのUUID]）
+function withThumbnailUrls(items: ImageRow[]): any[] {
+  return items.map(([filePath, imageId, uuid]) => [
+    filePath,
+    thumbnailUrl(imageId),
+    uuid,
+  ])
+}
+
 // 初期設定済みかどうかを確認
 export async function getInitialSetupState(): Promise<boolean> {
   const folders = await invoke<string[]>('get_all_folders')
@@ -84,16 +106,341 @@ export async function addIgnoreFolder(path: string): Promise<void> {
   await invoke('add_ignore_folder', { path })
 }
 
+export type SortKey = 'captured_at' | 'world' | 'file_size' | 'player_count'
+
+export type SearchPageRequest = {
+  query?: string
+  conditions?: Array<any>
+  sort?: SortKey
+  descending?: boolean
+  cursor?: string | null
+  limit?: number
+}
+
+export type SearchPage = {
+  items: any[]
+  next_cursor: string | null
+}
+
+// 検索結果を並び替えてページ単位で取得（次のページは next_cursor を cursor に渡す）
+export async function searchImagesPage(
+  request: SearchPageRequest
+): Promise<SearchPage> {
+  const page = await invoke<SearchPage>('search_images_page', { request })
+  return { ...page, items: withThumbnailUrls(page.items) }
+}
+
+export type FacetCount = { value: string; label: string; count: number }
+
+export type SearchFacets = {
+  worlds: FacetCount[]
+  players: FacetCount[]
+  authors: FacetCount[]
+  instance_types: FacetCount[]
+  regions: FacetCount[]
+  tags: FacetCount[]
+  months: FacetCount[]
+}
+
+// 検索結果に含まれるワールド・プレイヤーなどの枚数を集計
+export async function getSearchFacets(request: {
+  query?: string
+  conditions?: Array<any>
+  limit?: number
+}): Promise<SearchFacets> {
+  return await invoke<SearchFacets>('get_search_facets', { request })
+}
+
+export type CooccurrenceRequest = {
+  player?: string // ユーザーID（usr_...）または表示名
+  world?: string // ワールドID（wrld_...）またはワールド名
+  after?: string
+  before?: string
+  min_count?: number
+  limit?: number
+}
+
+export type PlayerPair = {
+  player: string
+  player_label: string
+  other: string
+  other_label: string
+  count: number
+}
+
+export type PlayerGraph = {
+  nodes: FacetCount[]
+  edges: { source: string; target: string; weight: number }[]
+}
+
+// 一緒に写っているプレイヤーの組み合わせと枚数を取得（player を指定するとその人と一緒に写っている相手）
+export async function getPlayerCooccurrence(
+  request: CooccurrenceRequest
+): Promise<PlayerPair[]> {
+  return await invoke<PlayerPair[]>('get_player_cooccurrence', { request })
+}
+
+// プレイヤーの関係グラフ（辺の重みは一緒に写っている写真の枚数）を取得
+export async function getPlayerGraph(
+  request: CooccurrenceRequest
+): Promise<PlayerGraph> {
+  return await invoke<PlayerGraph>('get_player_graph', { request })
+}
+
+export type Session = {
+  id: number
+  instance_id: string
+  world_id: string | null
+  world_name: string | null
+  access_type: string | null
+  started_at: string
+  ended_at: string
+  image_count: number
+  players: { user_id: string | null; display_name: string }[]
+}
+
+// 滞在（同じインスタンスで続けて撮影された写真のまとまり）を新しい順に取得
+export async function getSessions(
+  cursor: string | null,
+  limit?: number
+): Promise<{ items: Session[]; next_cursor: string | null }> {
+  return await invoke('get_sessions', { cursor, limit })
+}
+
+// 滞在中に撮影された写真を取得
+export async function getSessionImages(sessionId: number): Promise<any[]> {
+  return withThumbnailUrls(
+    await invoke<ImageRow[]>('get_session_images', { sessionId })
+  )
+}
+
+// 設定の撮影間隔（session_gap_minutes）で滞在を作り直す
+export async function rebuildSessions(): Promise<number> {
+  return await invoke<number>('rebuild_sessions')
+}
+
+export type ImageAnnotations = {
+  tags: string[]
+  favorite: boolean
+  rating: number | null
+}
+
+// 画像にタグを付ける（複数の画像・タグをまとめて指定できる）
+export async function addImageTags(
+  filePaths: string[],
+  tags: string[]
+): Promise<number> {
+  return await invoke<number>('add_image_tags', { filePaths, tags })
+}
+
+// 画像からタグを外す
+export async function removeImageTags(
+  filePaths: string[],
+  tags: string[]
+): Promise<number> {
+  return await invoke<number>('remove_image_tags', { filePaths, tags })
+}
+
+// 画像をお気に入りに追加・解除
+export async function setImageFavorite(
+  filePaths: string[],
+  favorite: boolean
+): Promise<number> {
+  return await invoke<number>('set_image_favorite', { filePaths, favorite })
+}
+
+// 画像の評価（1〜5、null で評価を消す）を設定
+export async function setImageRating(
+  filePaths: string[],
+  rating: number | null
+): Promise<number> {
+  return await invoke<number>('set_image_rating', { filePaths, rating })
+}
+
+// 画像のタグ・お気に入り・評価を取得
+export async function getImageAnnotations(
+  filePath: string
+): Promise<ImageAnnotations> {
+  return await invoke<ImageAnnotations>('get_image_annotations', { filePath })
+}
+
+// 使われているタグと、そのタグが付いた画像の枚数を取得
+export async function getAllTags(): Promise<FacetCount[]> {
+  return await invoke<FacetCount[]>('get_all_tags')
+}
+
+export type Album = {
+  id: number
+  name: string
+  smart: boolean
+  query: string | null
+  conditions: Array<any> | null
+  image_count: number | null // 手動アルバムの画像の枚数（スマートアルバムは null）
+  created_at: string
+  updated_at: string
+}
+
+// アルバムを並び順に取得
+export async function getAlbums(): Promise<Album[]> {
+  return await invoke<Album[]>('get_albums')
+}
+
+// アルバムを作成（query か conditions を指定するとスマートアルバム）
+export async function createAlbum(request: {
+  name: string
+  query?: string
+  conditions?: Array<any>
+}): Promise<Album> {
+  return await invoke<Album>('create_album', { request })
+}
+
+export async function renameAlbum(albumId: number, name: string): Promise<void> {
+  await invoke('rename_album', { albumId, name })
+}
+
+// スマートアルバムの検索条件を変更
+export async function updateSmartAlbum(
+  albumId: number,
+  query: string | null,
+  conditions: Array<any> | null
+): Promise<void> {
+  await invoke('update_smart_album', { albumId, query, conditions })
+}
+
+export async function deleteAlbum(albumId: number): Promise<void> {
+  await invoke('delete_album', { albumId })
+}
+
+// アルバムを指定した順に並べ替え
+export async function reorderAlbums(albumIds: number[]): Promise<void> {
+  await invoke('reorder_albums', { albumIds })
+}
+
+// 手動アルバムの末尾に画像を追加
+export async function addAlbumImages(
+  albumId: number,
+  filePaths: string[]
+): Promise<number> {
+  return await invoke<number>('add_album_images', { albumId, filePaths })
+}
+
+export async function removeAlbumImages(
+  albumId: number,
+  filePaths: string[]
+): Promise<number> {
+  return await invoke<number>('remove_album_images', { albumId, filePaths })
+}
+
+// 手動アルバムの画像を指定した順に並べ替え
+export async function reorderAlbumImages(
+  albumId: number,
+  filePaths: string[]
+): Promise<void> {
+  await invoke('reorder_album_images', { albumId, filePaths })
+}
+
+// アルバムの画像をページ単位で取得（スマートアルバムは開くたびに検索条件を評価）
+export async function getAlbumImages(
+  albumId: number,
+  cursor: string | null,
+  limit?: number
+): Promise<SearchPage> {
+  const page = await invoke<SearchPage>('get_album_images', {
+    albumId,
+    cursor,
+    limit,
+  })
+  return { ...page, items: withThumbnailUrls(page.items) }
+}
+
+export type DuplicateGroup = {
+  keeper: string // 残すことを勧める画像のファイルパス
+  images: {
+    file_path: string
+    uuid: string
+    width: number
+    height: number
+    file_size: number
+    has_metadata: boolean
+    distance: number // 残す画像との知覚ハッシュの距離（0 は見た目が同じ）
+  }[]
+}
+
+// 同じ写真・ほぼ同じ写真のまとまりを取得（maxDistance は 0〜7、既定は 6）
+export async function findDuplicateImages(
+  maxDistance?: number
+): Promise<DuplicateGroup[]> {
+  return await invoke<DuplicateGroup[]>('find_duplicate_images', {
+    maxDistance,
+  })
+}
+
+export type SimilarImage = {
+  file_path: string
+  image_id: number // サムネイルは thumbnailUrl(image_id)
+  uuid: string
+  distance: number // 0（同じ）〜1（まったく違う）
+}
+
+// 見た目（構図と色合い）の近い画像を近い順に取得
+export async function findSimilarImages(
+  uuid: string,
+  filePath: string,
+  limit?: number
+): Promise<SimilarImage[]> {
+  return await invoke<SimilarImage[]>('find_similar_images', {
+    uuid,
+    filePath,
+    limit,
+  })
+}
+
+// world などは HTML エスケープ済みで、一致した部分が <mark> で囲まれている
+export type FullTextHit = {
+  file_path: string
+  image_id: number // サムネイルは thumbnailUrl(image_id)
+  uuid: string
+  score: number // 大きいほどよく一致している
+  world: string
+  author: string
+  players: string
+  file_name: string
+  tags: string
+}
+
+// ワールド名・撮影者・同席者・ファイル名・タグを全文検索し、一致度の高い順に取得
+export async function searchImagesFulltext(
+  text: string,
+  limit?: number
+): Promise<FullTextHit[]> {
+  return await invoke<FullTextHit[]>('search_images_fulltext', { text, limit })
+}
+
+// 登録済みの全画像を撮影日時順にページ単位で取得
 export async function getThumbnailsChunk(
-  offset: number,
+  cursor: string | null,
   limit: number
-): Promise<any[]> {
-  const paths = await invoke<[{ path: string; uuid: string }]>(
-    'search_files_in_folders'
-  ) // 全ファイル取得
-  const chunk = paths.slice(offset, offset + limit) // 指定範囲のファイルパスを計算
-  // サムネイル生成と取得
-  return await invoke<any>('generate_and_get_thumbnails', { filePaths: chunk }) // 結果を返す
+): Promise<SearchPage> {
+  // 撮影日時の新しい順
+  return await searchImagesPage({
+    sort: 'captured_at',
+    descending: true,
+    cursor,
+    limit,
+  })
+}
+
+// フォルダ監視で新しい画像が登録された時にサムネイルを受け取るリスナーを登録
+export async function listenImageIndexed(
+  callback: (thumbnail: any) => void
+): Promise<() => void> {
+  return await listen('image_indexed', async (event) => {
+    const payload = event.payload as { file_path: string; uuid: string }
+    const thumbnails = await invoke<ImageRow[]>('generate_and_get_thumbnails', {
+      filePaths: [[payload.file_path, payload.uuid]],
+    })
+    withThumbnailUrls(thumbnails).forEach(callback)
+  })
 }
 
 export async function getMetadata(
@@ -102,13 +449,13 @@ export async function getMetadata(
 ): Promise<any> {
   const response = await invoke<{
     metadata: any
-    data_url: string
+    image_id: number
     file_created_at: string
   }>('get_image_metadata', { uuid: dbid, filePath: filePath })
 
   return {
     metadata: response.metadata,
-    data_url: response.data_url,
+    data_url: imageUrl(response.image_id),
     file_created_at: response.file_created_at,
   }
 }
@@ -123,5 +470,47 @@ export async function setConfig(config: Object): Promise<void> {
 export async function searchImage(
   conditions: Array<any>
 ): Promise<Array<Object>> {
-  return await invoke('search_images', { conditions })
+  return withThumbnailUrls(
+    await invoke<ImageRow[]>('search_images', { conditions })
+  )
+}
+
+// クエリ文字列で検索（例: world:"Great Pug" AND (player:Alice OR player:Bob) -player:Carol）
+// 構文エラーの場合は { message, position, token } がthrowされる
+export async function searchImageByQuery(
+  query: string
+): Promise<Array<Object>> {
+  return withThumbnailUrls(
+    await invoke<ImageRow[]>('search_images_by_query', { query })
+  )
+}
+
+// ユーザーIDに対して記録されている表示名の一覧を取得（名前の変更履歴）
+export async function getPlayerNames(userId: string): Promise<
+  Array<{
+    display_name: string
+    image_count: number
+    first_seen: string
+    last_seen: string
+  }>
+> {
+  return await invoke('get_player_names', { userId })
+}
+
+// VRCXのデータベースからメタデータの無い写真の情報を推定して登録
+export async function importVrcxDatabase(dbPath?: string): Promise<number> {
+  return await invoke<number>('import_vrcx_database', { dbPath })
+}
+
+// VRChatのログの取り込み結果（failed_logs は読み取れなかったログファイル）
+export interface OutputLogImport {
+  updated: number
+  failed_logs: string[]
+}
+
+// VRChatのログ（output_log）からメタデータの無い写真の情報を推定して登録
+export async function importOutputLogs(
+  logDir?: string
+): Promise<OutputLogImport> {
+  return await invoke<OutputLogImport>('import_output_logs', { logDir })
 }
@@ -5,4 +5,6 @@ export type Config = {
     update_db_when_startup: boolean
     language: Language
   }
+  // 同じインスタンスでこれ以上撮影の間隔が空いたら別の滞在とみなす（分）
+  session_gap_minutes?: number
 }